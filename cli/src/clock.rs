@@ -0,0 +1,62 @@
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Where the manager gets the time from, swapped out to control time-based behavior
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+/// The real wall clock
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that can be cloned into tasks, the system clock by default
+#[derive(Clone)]
+pub struct SharedClock(Arc<dyn Clock>);
+
+impl SharedClock {
+    pub fn new(clock: impl Clock + 'static) -> SharedClock {
+        SharedClock(Arc::new(clock))
+    }
+
+    pub fn now(&self) -> SystemTime {
+        self.0.now()
+    }
+}
+
+impl Default for SharedClock {
+    fn default() -> Self {
+        SharedClock::new(SystemClock)
+    }
+}
+
+/// A clock that only moves when it's told to, clones share the same time
+#[cfg(test)]
+#[derive(Clone)]
+pub struct ManualClock(Arc<std::sync::Mutex<SystemTime>>);
+
+#[cfg(test)]
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock(Arc::new(std::sync::Mutex::new(SystemTime::now())))
+    }
+}
+
+#[cfg(test)]
+impl ManualClock {
+    pub fn advance(&self, by: std::time::Duration) {
+        *self.0.lock().unwrap() += by;
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.0.lock().unwrap()
+    }
+}
@@ -0,0 +1,284 @@
+use crate::config::ManagerConfig;
+use crate::daemon::start_daemon;
+use crate::file_watcher::apply_action_to_fs;
+use crate::model::{WorkspaceAction, WorkspaceManager};
+use crate::schema::protocol_schema;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+const USAGE: &str = "usage:
+    mounttab [start]
+    mounttab set-url <workspace-id> <tab-name> (<url> | --from-clipboard)
+    mounttab apply-file <workspace-id> <actions.json> [--continue-on-error]
+    mounttab schema";
+
+pub enum Command {
+    Daemon,
+    SetUrl {
+        workspace_id: String,
+        tab: String,
+        source: UrlSource,
+    },
+    // Applies a json array of workspace actions, all or nothing unless continuing on errors
+    ApplyFile {
+        workspace_id: String,
+        file: PathBuf,
+        continue_on_error: bool,
+    },
+    // Prints the JSON Schema of the protocol
+    Schema,
+}
+
+pub enum UrlSource {
+    Arg(String),
+    Clipboard,
+}
+
+impl Command {
+    pub fn parse(args: &[String]) -> Result<Command, String> {
+        match args.first().map(String::as_str) {
+            None | Some("start") => Ok(Command::Daemon),
+            Some("set-url") => match &args[1..] {
+                [workspace_id, tab, flag] if flag == "--from-clipboard" => Ok(Command::SetUrl {
+                    workspace_id: workspace_id.clone(),
+                    tab: tab.clone(),
+                    source: UrlSource::Clipboard,
+                }),
+                [workspace_id, tab, url] => Ok(Command::SetUrl {
+                    workspace_id: workspace_id.clone(),
+                    tab: tab.clone(),
+                    source: UrlSource::Arg(url.clone()),
+                }),
+                _ => Err(USAGE.to_string()),
+            },
+            Some("apply-file") => match &args[1..] {
+                [workspace_id, file] => Ok(Command::ApplyFile {
+                    workspace_id: workspace_id.clone(),
+                    file: PathBuf::from(file),
+                    continue_on_error: false,
+                }),
+                [workspace_id, file, flag] if flag == "--continue-on-error" => {
+                    Ok(Command::ApplyFile {
+                        workspace_id: workspace_id.clone(),
+                        file: PathBuf::from(file),
+                        continue_on_error: true,
+                    })
+                }
+                _ => Err(USAGE.to_string()),
+            },
+            Some("schema") => Ok(Command::Schema),
+            Some(other) => Err(format!("unknown command: {}\n{}", other, USAGE)),
+        }
+    }
+
+    pub async fn run(self) -> io::Result<()> {
+        match self {
+            Command::Daemon => {
+                println!("⛰️ Mount Tab");
+                start_daemon().await
+            }
+            Command::SetUrl {
+                workspace_id,
+                tab,
+                source,
+            } => {
+                let text = match source {
+                    UrlSource::Arg(url) => url,
+                    UrlSource::Clipboard => read_clipboard()?,
+                };
+                let manager = WorkspaceManager::new(ManagerConfig::load_default());
+                manager.load_workspaces().await;
+                set_url(&manager, &workspace_id, &tab, &text).await
+            }
+            Command::ApplyFile {
+                workspace_id,
+                file,
+                continue_on_error,
+            } => {
+                let manager = WorkspaceManager::new(ManagerConfig::load_default());
+                manager.load_workspaces().await;
+                apply_file(&manager, &workspace_id, &file, continue_on_error).await
+            }
+            Command::Schema => {
+                let schema = serde_json::to_string_pretty(&protocol_schema())
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                println!("{}", schema);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Checks that `text` is a url and writes it to the tab
+pub async fn set_url(
+    manager: &WorkspaceManager,
+    workspace_id: &str,
+    tab: &str,
+    text: &str,
+) -> io::Result<()> {
+    let url = url::Url::parse(text.trim())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("not a url: {}", e)))?;
+
+    let workspace = manager.get_workspace(workspace_id).await.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No workspace with id: {}", workspace_id),
+        )
+    })?;
+
+    let action = WorkspaceAction::ChangeTabUrl(tab.to_string(), url.to_string());
+    apply_action_to_fs(workspace.path.as_ref(), &workspace.config, &action)
+}
+
+/** Applies the actions in the file in order. Without `continue_on_error` they're one
+ * transaction that stops at the first failure, with it every action is applied on its
+ * own and the failures are reported at the end. */
+pub async fn apply_file(
+    manager: &WorkspaceManager,
+    workspace_id: &str,
+    file: &Path,
+    continue_on_error: bool,
+) -> io::Result<()> {
+    let contents = fs::read_to_string(file)?;
+    let actions: Vec<WorkspaceAction> = serde_json::from_str(&contents)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    if !continue_on_error {
+        manager.apply_actions(workspace_id, &actions).await?;
+        println!("Applied {} actions", actions.len());
+        return Ok(());
+    }
+
+    let mut failed = 0;
+    for (i, action) in actions.iter().enumerate() {
+        if let Err(e) = manager
+            .apply_actions(workspace_id, std::slice::from_ref(action))
+            .await
+        {
+            eprintln!("Action {} ({:?}) failed: {}", i, action, e);
+            failed += 1;
+        }
+    }
+    println!(
+        "Applied {} of {} actions",
+        actions.len() - failed,
+        actions.len()
+    );
+    match failed {
+        0 => Ok(()),
+        _ => Err(io::Error::other(format!("{} actions failed", failed))),
+    }
+}
+
+// Shells out to whatever clipboard tool the platform has
+#[cfg(feature = "clipboard")]
+fn read_clipboard() -> io::Result<String> {
+    let tools: &[(&str, &[&str])] = &[
+        ("pbpaste", &[]),
+        ("wl-paste", &["--no-newline"]),
+        ("xclip", &["-selection", "clipboard", "-o"]),
+        ("xsel", &["--clipboard", "--output"]),
+    ];
+    for (tool, args) in tools {
+        match std::process::Command::new(tool).args(*args).output() {
+            Ok(output) if output.status.success() => {
+                return Ok(String::from_utf8_lossy(&output.stdout).to_string());
+            }
+            _ => continue,
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "Couldn't read the clipboard, install pbpaste, wl-paste, xclip or xsel",
+    ))
+}
+
+#[cfg(not(feature = "clipboard"))]
+fn read_clipboard() -> io::Result<String> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "mounttab was built without the clipboard feature",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{write_tab, TempDir};
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn from_clipboard_is_parsed() {
+        let command = Command::parse(&args(&["set-url", "ws", "a", "--from-clipboard"]));
+        assert!(matches!(
+            command,
+            Ok(Command::SetUrl {
+                source: UrlSource::Clipboard,
+                ..
+            })
+        ));
+        assert!(Command::parse(&args(&["set-url", "ws"])).is_err());
+    }
+
+    #[tokio::test]
+    async fn set_url_writes_what_the_clipboard_had() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "a", "https://example.com/", true);
+        let manager = WorkspaceManager::new(ManagerConfig::default());
+        let id = manager.make_worksapce(&path).await.unwrap().id.clone();
+
+        let clipboard = "  https://clipboard.example/page\n";
+        set_url(&manager, &id, "a", clipboard).await.unwrap();
+        let url = fs::read_to_string(path.join("a").join("url")).unwrap();
+        assert_eq!(url, "https://clipboard.example/page");
+
+        let err = set_url(&manager, &id, "a", "not a url").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let url = fs::read_to_string(path.join("a").join("url")).unwrap();
+        assert_eq!(url, "https://clipboard.example/page");
+    }
+
+    #[tokio::test]
+    async fn apply_file_builds_the_workspace() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "a", "https://example.com/", true);
+        let manager = WorkspaceManager::new(ManagerConfig::default());
+        let id = manager.make_worksapce(&path).await.unwrap().id.clone();
+
+        let file = dir.path().join("actions.json");
+        let actions = r#"[
+            { "CreateTab": "b" },
+            { "ChangeTabUrl": ["b", "https://b.example/"] },
+            { "OpenTab": "b" },
+            { "CloseTab": "a" }
+        ]"#;
+        fs::write(&file, actions).unwrap();
+        apply_file(&manager, &id, &file, false).await.unwrap();
+        let workspace = manager.get_workspace(&id).await.unwrap();
+        let tabs: Vec<(&str, &str, bool)> = workspace
+            .tabs
+            .iter()
+            .map(|tab| (tab.name.as_str(), tab.url.as_str(), tab.is_open))
+            .collect();
+        assert_eq!(
+            tabs,
+            vec![
+                ("a", "https://example.com/", false),
+                ("b", "https://b.example/", true)
+            ]
+        );
+
+        // A negative scroll fails, without --continue-on-error that undoes the whole file
+        let failing = r#"[{ "CreateTab": "c" }, { "SetTabScroll": ["b", -1.0] }]"#;
+        fs::write(&file, failing).unwrap();
+        assert!(apply_file(&manager, &id, &file, false).await.is_err());
+        assert!(!path.join("c").exists());
+        assert!(apply_file(&manager, &id, &file, true).await.is_err());
+        assert!(path.join("c").is_dir());
+    }
+}
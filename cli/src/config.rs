@@ -0,0 +1,472 @@
+use crate::layout::TabLayout;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Name of the per workspace config file, it lives in the workspace root
+pub const WORKSPACE_CONFIG_FILE: &str = ".mounttab.json";
+
+/// Touched by the daemon in every loaded workspace so a watchdog can tell it's still alive
+pub const ALIVE_FILE: &str = ".mounttab-alive";
+
+/// Directory in the workspace root where tabs that stayed closed are moved to
+pub const ARCHIVE_DIR: &str = ".archive";
+
+/// Tab names in the workspace root from the most recently activated on, one per line
+pub const MRU_FILE: &str = ".mru";
+
+/// Every tab that was closed or removed and why, see `HistoryEntry`
+pub const HISTORY_FILE: &str = ".history";
+
+/// Where the workspace's browser windows were, see `WindowLayout`
+pub const WINDOWS_FILE: &str = ".windows.json";
+
+/// Checksums of every tab file, written and checked on request
+pub const MANIFEST_FILE: &str = ".manifest";
+
+/// Script in the workspace root that runs when the workspace is started, if scripts are allowed
+pub const START_HOOK_FILE: &str = ".on-start";
+
+/// Decides who wins when the browser and the filesystem change the same tab at the same time
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictPolicy {
+    FilesystemWins,
+    BrowserWins,
+    // Whatever got written last sticks, this is how it always worked
+    #[default]
+    LastWriteWins,
+}
+
+/// The json type a key in a tab's `meta.json` has to have
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetaType {
+    String,
+    Number,
+    Bool,
+    Array,
+    Object,
+    // Anything, as long as the key is in the schema
+    Any,
+}
+
+impl MetaType {
+    pub fn matches(&self, value: &serde_json::Value) -> bool {
+        match self {
+            MetaType::String => value.is_string(),
+            MetaType::Number => value.is_number(),
+            MetaType::Bool => value.is_boolean(),
+            MetaType::Array => value.is_array(),
+            MetaType::Object => value.is_object(),
+            MetaType::Any => true,
+        }
+    }
+}
+
+/// What to do with a browser whose outbound queue is full
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    // Messages that don't fit are lost, the browser can catch up with ChangesSince
+    #[default]
+    DropNewest,
+    Disconnect,
+}
+
+/// What to do when a watcher makes actions faster than they're applied
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WatcherOverflow {
+    // The watcher waits for room, nothing is lost
+    #[default]
+    Wait,
+    // The oldest waiting batches are dropped so the watcher keeps up
+    DropOldest,
+}
+
+/// The url the way the url crate writes it, anything that isn't a url stays as it is
+pub fn canonical_url(url: &str) -> String {
+    match url::Url::parse(url.trim()) {
+        Ok(parsed) => parsed.to_string(),
+        Err(_) => url.to_string(),
+    }
+}
+
+/** Settings for a single workspace, read from `.mounttab.json` in the workspace root.
+ * Every field is optional in the file, missing ones fall back to the defaults. */
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct WorkspaceConfig {
+    // Stable id of the workspace, written the first time it's loaded so it survives
+    // restarts and moving the directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    // Only browsers that named this client in their Hello see the workspace and can act
+    // on it. None shares it with every browser
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    pub conflict_policy: ConflictPolicy,
+    // Two writes to the same tab closer together than this count as a conflict
+    pub conflict_window_ms: u64,
+    // A url file has to stay the same this long before the tab navigates, so typing
+    // into it doesn't reload the page on every keystroke. 0 sends every change
+    pub url_settle_ms: u64,
+    // A tab whose files change more often than this in a second has its changes held
+    // back for the rest of that second and is read once when it's over. 0 turns it off
+    pub max_tab_events_per_sec: u32,
+    // Tabs that stay closed this long are moved to `.archive`, 0 keeps them forever
+    pub archive_closed_after_secs: u64,
+    // Tabs that stay open this long are closed with the Expired reason, 0 never closes them
+    pub close_open_tabs_after_secs: u64,
+    // Size caps for what clients can write into a tab, 0 means no limit
+    pub max_meta_bytes: usize,
+    pub max_notes_bytes: usize,
+    pub max_favicon_bytes: usize,
+    // Read every write back and fail if it didn't land, for filesystems that lie
+    pub paranoid_writes: bool,
+    // Writes are synced to disk before they count as done. Actions applied together
+    // share one sync at the end
+    pub durable_writes: bool,
+    // Urls are stored the way the url crate writes them, so `https://Example.com:443`
+    // and `https://example.com/` are the same url on disk. Anything that isn't a url is
+    // stored as is
+    pub canonical_urls: bool,
+    // Keys a tab's meta.json may have and the type of each, keys can be left out.
+    // None allows any json
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta_schema: Option<BTreeMap<String, MetaType>>,
+    // Turned off, edits on disk aren't picked up while the workspace is started and only
+    // the browser writes to it
+    pub watch: bool,
+    // Whether new tabs start open, for CreateTab and for tab directories made on disk
+    pub default_open: bool,
+    // The file names and formats of a tab directory
+    pub layout: TabLayout,
+}
+
+impl Default for WorkspaceConfig {
+    fn default() -> Self {
+        WorkspaceConfig {
+            id: None,
+            owner: None,
+            conflict_policy: ConflictPolicy::default(),
+            conflict_window_ms: 500,
+            url_settle_ms: 300,
+            max_tab_events_per_sec: 50,
+            archive_closed_after_secs: 0,
+            close_open_tabs_after_secs: 0,
+            max_meta_bytes: 16 * 1024,
+            max_notes_bytes: 64 * 1024,
+            max_favicon_bytes: 64 * 1024,
+            paranoid_writes: false,
+            durable_writes: false,
+            canonical_urls: false,
+            meta_schema: None,
+            watch: true,
+            default_open: false,
+            layout: TabLayout::default(),
+        }
+    }
+}
+
+impl WorkspaceConfig {
+    /// The url as it's written to disk
+    pub fn stored_url(&self, url: &str) -> String {
+        match self.canonical_urls {
+            true => canonical_url(url),
+            false => url.to_string(),
+        }
+    }
+
+    /// Errors with InvalidInput if `meta` doesn't fit the workspace's meta schema
+    pub fn check_meta(&self, meta: &serde_json::Value) -> io::Result<()> {
+        let Some(schema) = &self.meta_schema else {
+            return Ok(());
+        };
+        let invalid = |message: String| Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        let Some(fields) = meta.as_object() else {
+            return invalid("meta has to be a json object when there's a schema".to_string());
+        };
+        for (key, value) in fields {
+            match schema.get(key) {
+                None => return invalid(format!("meta key {} is not in the schema", key)),
+                Some(kind) if !kind.matches(value) => {
+                    return invalid(format!("meta key {} has to be {:?}", key, kind));
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    pub fn load(workspace_path: &Path) -> io::Result<WorkspaceConfig> {
+        let config_file = workspace_path.join(WORKSPACE_CONFIG_FILE);
+        if !config_file.exists() {
+            return Ok(WorkspaceConfig::default());
+        }
+        let contents = fs::read_to_string(config_file)?;
+        serde_json::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Reads the config file again, lets `change` edit it and writes it back
+    pub fn update(
+        workspace_path: &Path,
+        change: impl FnOnce(&mut WorkspaceConfig),
+    ) -> io::Result<WorkspaceConfig> {
+        let mut config = WorkspaceConfig::load(workspace_path)?;
+        change(&mut config);
+        let contents = serde_json::to_string_pretty(&config)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(workspace_path.join(WORKSPACE_CONFIG_FILE), contents)?;
+        Ok(config)
+    }
+}
+
+/** Settings for the whole daemon, read from `$MOUNTTAB_CONFIG` or
+ * `~/.config/mounttab/config.json`. */
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ManagerConfig {
+    // How often `.mounttab-alive` gets touched, 0 (the default) turns it off
+    pub keep_alive_interval_secs: u64,
+    // Workspaces without browsers drop their tabs from memory after this long, 0 turns it off
+    pub idle_evict_secs: u64,
+    // At most this many workspaces keep their tabs in memory, 0 means no limit
+    pub max_loaded_workspaces: usize,
+    // Workspaces loaded at startup. Relative paths are relative to the config file so a
+    // config synced between machines can point into the synced folder
+    pub workspaces: Vec<PathBuf>,
+    // Every directory directly inside one of these is loaded as a workspace too
+    pub workspace_roots: Vec<PathBuf>,
+    // Workspaces have to live under one of these, empty allows any directory
+    pub allowed_roots: Vec<PathBuf>,
+    // Workspace list changes within this window go out as a single AllWorkspaces
+    pub registry_notify_window_ms: u64,
+    // Workspace paths in the order the user put them, the rest are sorted by name
+    pub workspace_order: Vec<String>,
+    // Workspace hooks like `.on-start` only run when this is turned on
+    pub allow_scripts: bool,
+    // A workspace is scanned again at most this often, reloads asked for in between
+    // are coalesced into one
+    pub min_reload_interval_ms: u64,
+    // Named sets of workspace ids that get started and stopped together
+    pub sessions: BTreeMap<String, Vec<String>>,
+    // Every browser has its own queue of messages waiting to be sent, this long
+    pub outbound_queue_len: usize,
+    // What happens when a browser's queue is full
+    pub outbound_overflow: OverflowPolicy,
+    // Runs the daemon on one thread with every file watcher on a single task, for small
+    // machines and containers
+    pub single_threaded: bool,
+    // Batches of actions waiting between a file watcher and the browsers, at most this many
+    pub watcher_queue_len: usize,
+    // What happens when that queue is full
+    pub watcher_overflow: WatcherOverflow,
+    // Actions for a workspace a browser just started wait this long for it to acknowledge
+    // the load, 0 sends them right away
+    pub load_ack_timeout_ms: u64,
+    #[cfg(feature = "fault-injection")]
+    pub faults: crate::faults::FaultConfig,
+    // Where this config was read from, changes made at runtime get saved back there
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+}
+
+impl Default for ManagerConfig {
+    fn default() -> Self {
+        ManagerConfig {
+            keep_alive_interval_secs: 0,
+            idle_evict_secs: 0,
+            max_loaded_workspaces: 0,
+            workspaces: Vec::new(),
+            workspace_roots: Vec::new(),
+            allowed_roots: Vec::new(),
+            registry_notify_window_ms: 100,
+            workspace_order: Vec::new(),
+            allow_scripts: false,
+            min_reload_interval_ms: 1000,
+            sessions: BTreeMap::new(),
+            outbound_queue_len: 256,
+            outbound_overflow: OverflowPolicy::default(),
+            single_threaded: false,
+            watcher_queue_len: 101,
+            watcher_overflow: WatcherOverflow::default(),
+            load_ack_timeout_ms: 2000,
+            #[cfg(feature = "fault-injection")]
+            faults: Default::default(),
+            path: None,
+        }
+    }
+}
+
+impl ManagerConfig {
+    pub fn default_path() -> Option<PathBuf> {
+        if let Some(path) = env::var_os("MOUNTTAB_CONFIG") {
+            return Some(PathBuf::from(path));
+        }
+        let home = env::var_os("HOME")?;
+        Some(Path::new(&home).join(".config/mounttab/config.json"))
+    }
+
+    pub fn load(path: &Path) -> io::Result<ManagerConfig> {
+        let mut config = if path.exists() {
+            let contents = fs::read_to_string(path)?;
+            serde_json::from_str(&contents)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+        } else {
+            ManagerConfig::default()
+        };
+        config.path = Some(path.to_owned());
+        Ok(config)
+    }
+
+    /** Reads the config file again, lets `change` edit it and writes back only the keys
+     * whose value changed. Keys `change` didn't touch, and keys this version doesn't
+     * know, stay as they are in the file. */
+    pub fn update(&self, change: impl FnOnce(&mut ManagerConfig)) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let invalid = |e: serde_json::Error| io::Error::new(io::ErrorKind::InvalidData, e);
+        let mut file = match path.exists() {
+            true => serde_json::from_str(&fs::read_to_string(path)?).map_err(invalid)?,
+            false => serde_json::Value::Object(Default::default()),
+        };
+        let mut config = ManagerConfig::load(path)?;
+        let before = serde_json::to_value(&config).map_err(invalid)?;
+        change(&mut config);
+        let after = serde_json::to_value(&config).map_err(invalid)?;
+
+        let (Some(file), serde_json::Value::Object(before), serde_json::Value::Object(after)) =
+            (file.as_object_mut(), before, after)
+        else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} isn't a json object", path.display()),
+            ));
+        };
+        for (key, value) in after {
+            if before.get(&key) != Some(&value) {
+                file.insert(key, value);
+            }
+        }
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let contents = serde_json::to_string_pretty(file).map_err(invalid)?;
+        fs::write(path, contents)
+    }
+
+    /// Resolves the path and makes sure it's under one of the allowed roots
+    pub fn check_allowed_root(&self, path: &Path) -> io::Result<PathBuf> {
+        let path = fs::canonicalize(path)?;
+        if self.allowed_roots.is_empty() {
+            return Ok(path);
+        }
+        let allowed = self
+            .allowed_roots
+            .iter()
+            .any(|root| match fs::canonicalize(root) {
+                Ok(root) => path.starts_with(root),
+                Err(_) => false,
+            });
+        if !allowed {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("{} is not inside an allowed root", path.display()),
+            ));
+        }
+        Ok(path)
+    }
+
+    /// Relative paths are resolved against the directory of the config file
+    pub fn resolve_path(&self, path: &Path) -> PathBuf {
+        if path.is_absolute() {
+            return path.to_owned();
+        }
+        match self.path.as_ref().and_then(|config| config.parent()) {
+            Some(dir) => dir.join(path),
+            None => path.to_owned(),
+        }
+    }
+
+    /// A file next to the config for state the daemon keeps between restarts
+    pub fn state_file(&self, name: &str) -> Option<PathBuf> {
+        Some(self.path.as_ref()?.parent()?.join(name))
+    }
+
+    /// Loads the config from the default location, falling back to the defaults on any error
+    pub fn load_default() -> ManagerConfig {
+        let Some(path) = ManagerConfig::default_path() else {
+            return ManagerConfig::default();
+        };
+        ManagerConfig::load(&path).unwrap_or_else(|e| {
+            eprintln!(
+                "Error reading config {}, using defaults: {}",
+                path.display(),
+                e
+            );
+            ManagerConfig::default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn paths_outside_the_allowed_roots_are_rejected() {
+        let dir = TempDir::new();
+        let allowed = dir.dir("allowed");
+        let inside = dir.dir("allowed/ws");
+        let outside = dir.dir("elsewhere");
+        let config = ManagerConfig {
+            allowed_roots: vec![allowed],
+            ..ManagerConfig::default()
+        };
+
+        assert_eq!(config.check_allowed_root(&inside).unwrap(), inside);
+        let err = config.check_allowed_root(&outside).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        // Going up out of the root is caught after resolving the path
+        let escaped = inside.join("../../elsewhere");
+        let err = config.check_allowed_root(&escaped).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn no_allowed_roots_allow_any_directory() {
+        let dir = TempDir::new();
+        let ws = dir.dir("ws");
+        assert_eq!(
+            ManagerConfig::default().check_allowed_root(&ws).unwrap(),
+            ws
+        );
+    }
+
+    #[test]
+    fn an_update_writes_only_the_keys_it_changed() {
+        let dir = TempDir::new();
+        let path = dir.path().join("config.json");
+        let original = serde_json::json!({ "idle_evict_secs": 5, "from_a_newer_version": true });
+        fs::write(&path, original.to_string()).unwrap();
+
+        let config = ManagerConfig::load(&path).unwrap();
+        config
+            .update(|config| config.max_loaded_workspaces = 3)
+            .unwrap();
+        let written: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(
+            written,
+            serde_json::json!({
+                "idle_evict_secs": 5,
+                "from_a_newer_version": true,
+                "max_loaded_workspaces": 3,
+            })
+        );
+    }
+}
@@ -1,8 +1,11 @@
-use crate::model::{Browser, FromBrowserMessage, ToBrowserMessage, WorkspaceManager};
+use crate::config::ManagerConfig;
+use crate::model::{ConnectionEvent, FromBrowserMessage, WorkspaceManager};
+use crate::protocol::{self, ProtocolVersion};
 use futures_util::{SinkExt, StreamExt};
-use serde_json;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::Arc;
 use tokio::sync::mpsc::{self};
+use tokio::sync::oneshot;
 use tokio_stream::wrappers::UnboundedReceiverStream;
 use warp::ws::{Message, WebSocket};
 use warp::Filter;
@@ -13,9 +16,12 @@ static NEXT_USER_ID: AtomicUsize = AtomicUsize::new(1);
 pub async fn start_daemon() -> Result<(), std::io::Error> {
     // start websocket server
 
-    let worksapce_manager = WorkspaceManager::default();
+    let worksapce_manager = WorkspaceManager::new(ManagerConfig::load_default());
 
     worksapce_manager.load_workspaces().await;
+    worksapce_manager.spawn_keep_alive();
+    worksapce_manager.spawn_evictor();
+    worksapce_manager.spawn_archiver();
 
     let workspaces = warp::any().map(move || worksapce_manager.clone());
 
@@ -43,17 +49,22 @@ async fn user_connected(ws: WebSocket, workspaces: WorkspaceManager) {
     let (mut user_ws_tx, mut user_ws_rx) = ws.split();
 
     // convert the websocket streams to tokio streams
-    let (to_browser_tx, mut to_browser_rx) = mpsc::channel::<ToBrowserMessage>(my_id);
     let (from_browser_tx, from_browser_rx) = mpsc::unbounded_channel::<FromBrowserMessage>();
     let mut from_browser_rx = UnboundedReceiverStream::new(from_browser_rx);
+    // Everyone starts on version 1 until they say hello
+    let protocol = Arc::new(AtomicU32::new(1));
+    let send_protocol = Arc::clone(&protocol);
+    let (browser, mut to_browser_rx) = workspaces.make_browser(my_id, protocol);
 
     // Recieves message from websocket and forwards them
-    tokio::task::spawn(async move {
+    let manager = workspaces.clone();
+    let reader = tokio::task::spawn(async move {
         while let Some(msg_res) = user_ws_rx.next().await {
             let msg = match msg_res {
                 Ok(msg) => msg,
                 Err(e) => {
                     eprintln!("websocket error(uid={}): {}", my_id, e);
+                    manager.connection_event(my_id, ConnectionEvent::Error(e.to_string()));
                     continue;
                 }
             };
@@ -65,32 +76,45 @@ async fn user_connected(ws: WebSocket, workspaces: WorkspaceManager) {
                 continue;
             };
 
-            let from_browser_mes = match serde_json::from_str::<FromBrowserMessage>(msg) {
+            let from_browser_mes = match protocol::decode(msg) {
                 Ok(msg) => msg,
                 Err(e) => {
                     eprintln!(
                         "Error serde parsing message from browser(msg: {}): {}",
                         msg, e
                     );
+                    manager.connection_event(my_id, ConnectionEvent::Error(e.to_string()));
                     continue;
                 }
             };
 
-            match from_browser_tx.send(from_browser_mes) {
-                Ok(()) => {
-                    println!("Message sent successfully")
-                }
-                Err(err) => {
-                    println!("Got an error sending to socket: {}", err)
-                }
+            // Nobody reads the messages once the browser was kicked or replaced
+            if let Err(err) = from_browser_tx.send(from_browser_mes) {
+                println!("Browser {} is gone, not reading its socket: {}", my_id, err);
+                break;
             }
         }
     });
 
     // Sends message to websocket
+    #[cfg(feature = "fault-injection")]
+    let faults = workspaces.faults();
+    let (close_tx, mut close_rx) = oneshot::channel::<()>();
     tokio::task::spawn(async move {
-        while let Some(to_browser_message) = to_browser_rx.recv().await {
-            let action_str = match serde_json::to_string(&to_browser_message) {
+        loop {
+            let to_browser_message = tokio::select! {
+                message = to_browser_rx.recv() => match message {
+                    Some(message) => message,
+                    None => break,
+                },
+                _ = &mut close_rx => break,
+            };
+            #[cfg(feature = "fault-injection")]
+            if !faults.before_send().await {
+                continue;
+            }
+            let version = ProtocolVersion::negotiate(send_protocol.load(Ordering::Acquire));
+            let action_str = match protocol::encode(&to_browser_message, version) {
                 Ok(str) => str,
                 Err(e) => {
                     eprintln!("error serializing action: {}", e);
@@ -100,25 +124,21 @@ async fn user_connected(ws: WebSocket, workspaces: WorkspaceManager) {
 
             let msg = Message::text(action_str);
 
-            match user_ws_tx.send(msg).await {
-                Ok(()) => {
-                    println!("Sent message to socket");
-                }
-                Err(err) => {
-                    eprintln!("Error sending message: {}", err);
-                }
-            };
+            if let Err(err) = user_ws_tx.send(msg).await {
+                eprintln!("Error sending message: {}", err);
+            }
+        }
+        // Closes the socket of a browser that was kicked or replaced too
+        if let Err(e) = user_ws_tx.close().await {
+            eprintln!("Error closing socket of browser {}: {}", my_id, e);
         }
     });
 
-    let browser = Browser {
-        id: my_id,
-        tx: to_browser_tx,
-    };
-
     workspaces
         .browser_connected(&browser, &mut from_browser_rx)
         .await;
+    reader.abort();
+    let _ = close_tx.send(());
 
     // user_ws_rx stream will keep processing as long as the user stays
     // connected. Once they disconnect, then...
@@ -0,0 +1,91 @@
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::RwLock;
+use std::time::Duration;
+
+/** Faults to inject into the sync loop, only built with the `fault-injection` feature.
+ * Read from `faults` in the daemon config and changeable at runtime, meant for
+ * reproducing races like the echo loop against a slow or failing daemon. */
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct FaultConfig {
+    // Every message to a browser waits this long before it's sent
+    pub delay_ms: u64,
+    // Every nth message to a browser is dropped, 0 drops none
+    pub drop_every: usize,
+    // Writes for browser actions fail instead of touching the disk
+    pub fail_fs_writes: bool,
+}
+
+#[derive(Default)]
+pub struct Faults {
+    config: RwLock<FaultConfig>,
+    sent: AtomicUsize,
+}
+
+impl Faults {
+    pub fn new(config: FaultConfig) -> Faults {
+        Faults {
+            config: RwLock::new(config),
+            sent: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn set(&self, config: FaultConfig) {
+        println!("Injecting faults: {:?}", config);
+        *self.config.write().unwrap() = config;
+    }
+
+    /// Waits out the delay, returns false when the message should be dropped
+    pub async fn before_send(&self) -> bool {
+        let config = self.config.read().unwrap().clone();
+        if config.delay_ms > 0 {
+            tokio::time::sleep(Duration::from_millis(config.delay_ms)).await;
+        }
+        let n = self.sent.fetch_add(1, Ordering::Relaxed) + 1;
+        if config.drop_every > 0 && n.is_multiple_of(config.drop_every) {
+            println!("Dropping message {} to the browser, injected fault", n);
+            return false;
+        }
+        true
+    }
+
+    pub fn before_fs_write(&self) -> io::Result<()> {
+        if self.config.read().unwrap().fail_fs_writes {
+            return Err(io::Error::other(
+                "injected fault: filesystem writes are failing",
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn every_nth_message_is_dropped() {
+        let faults = Faults::new(FaultConfig {
+            drop_every: 3,
+            ..FaultConfig::default()
+        });
+        let mut sent = Vec::new();
+        for _ in 0..6 {
+            sent.push(faults.before_send().await);
+        }
+        assert_eq!(sent, vec![true, true, false, true, true, false]);
+    }
+
+    #[test]
+    fn writes_fail_only_while_set() {
+        let faults = Faults::default();
+        assert!(faults.before_fs_write().is_ok());
+        faults.set(FaultConfig {
+            fail_fs_writes: true,
+            ..FaultConfig::default()
+        });
+        assert!(faults.before_fs_write().is_err());
+    }
+}
@@ -1,29 +1,235 @@
-use crate::model::{Tab, Workspace, WorkspaceAction};
-use notify::{RecursiveMode, Watcher};
+use crate::config::{WatcherOverflow, WorkspaceConfig, ARCHIVE_DIR, MRU_FILE};
+use crate::layout::{is_blank_url, FileChange, TabLayout, TabState};
+use crate::model::{Tab, TabPrecondition, Workspace, WorkspaceAction};
+use notify::event::{ModifyKind, RenameMode};
+use notify::{EventKind, RecursiveMode, Watcher};
+use serde::Serialize;
 use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     ffi::OsStr,
     fs, io,
-    path::{Component, Path},
-    sync::atomic::{AtomicUsize, Ordering},
+    path::{Component, Path, PathBuf},
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::time::Instant;
 
+/// How long a tab's changes are counted for `max_tab_events_per_sec`
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// How often a watcher without events checks that the workspace directory is still there
+const ROOT_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The workspace and tab directories a running watcher covers
+pub type WatchedPaths = Arc<std::sync::RwLock<BTreeSet<PathBuf>>>;
+
+#[derive(Debug, Clone)]
+pub struct WatchOptions {
+    // How long a url has to stay unchanged before its ChangeTabUrl goes out
+    pub url_settle: Duration,
+    // Only these tabs are watched recursively, the root itself is watched shallowly so
+    // tabs being created and removed are still seen. None watches every tab
+    pub deep_tabs: Option<HashSet<String>>,
+    pub layout: TabLayout,
+    // What tabs made on disk without an is_open start as
+    pub default_open: bool,
+    // Changes a tab may make in a second before it's throttled, 0 never throttles
+    pub max_tab_events_per_sec: u32,
+    // Filled in by the watcher while it runs
+    pub watched: WatchedPaths,
+}
+
+impl WatchOptions {
+    pub fn from_config(config: &WorkspaceConfig) -> WatchOptions {
+        WatchOptions {
+            url_settle: Duration::from_millis(config.url_settle_ms),
+            deep_tabs: None,
+            layout: config.layout.clone(),
+            default_open: config.default_open,
+            max_tab_events_per_sec: config.max_tab_events_per_sec,
+            watched: WatchedPaths::default(),
+        }
+    }
+
+    fn watches_deeply(&self, tab: &str) -> bool {
+        self.deep_tabs
+            .as_ref()
+            .is_none_or(|tabs| tabs.contains(tab))
+    }
+
+    // Keeps `watched` in step with the tabs coming and going
+    fn track_watched(&self, path: &Path, action: &WorkspaceAction) {
+        let Ok(mut watched) = self.watched.write() else {
+            return;
+        };
+        match action {
+            WorkspaceAction::CreateTab(tab) if self.watches_deeply(tab) => {
+                watched.insert(path.join(tab));
+            }
+            WorkspaceAction::RemoveTab(tab) => {
+                watched.remove(&path.join(tab));
+            }
+            WorkspaceAction::RenameTab(from, to) if watched.remove(&path.join(from)) => {
+                watched.insert(path.join(to));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Watches the workspace and sends the actions made on disk, `ready` fires once the
+/// watches are registered so nothing done after it can be missed. The actions of one
+/// filesystem event are sent together so they can be applied as one transaction.
+/// Returns once nobody is receiving the actions anymore
 pub async fn async_watch(
     path: &Path,
-    action_tx: mpsc::Sender<WorkspaceAction>,
+    options: WatchOptions,
+    action_tx: ActionSender,
+    ready: oneshot::Sender<()>,
 ) -> notify::Result<()> {
-    let (tx, rx) = std::sync::mpsc::channel();
-    let path_clone = path.clone();
+    let mut watch = WorkspaceWatch::start(path, options)?;
+    let _ = ready.send(());
 
-    let mut watcher = notify::recommended_watcher(tx)?;
+    while let Some(actions) = watch.next_actions().await {
+        if actions.is_empty() {
+            continue;
+        }
+        if action_tx.send(actions).await.is_err() {
+            println!("Nobody is receiving actions anymore");
+            break;
+        }
+    }
 
-    println!("Watcher starting: {}", path.to_str().unwrap());
+    if watch.root_gone() {
+        return Err(notify::Error::generic(&format!(
+            "{} was moved or deleted",
+            path.display()
+        )));
+    }
+    Ok(())
+}
 
-    watcher.watch(path, RecursiveMode::Recursive)?;
+/** The channel from a watcher to whoever applies its actions. With `Wait` a full
+ * channel holds the watcher up until there's room, with `DropOldest` the oldest
+ * batches are thrown away instead and counted in `dropped`. */
+pub fn action_channel(
+    capacity: usize,
+    overflow: WatcherOverflow,
+    dropped: Arc<AtomicU64>,
+) -> (ActionSender, ActionReceiver) {
+    let capacity = capacity.max(1);
+    match overflow {
+        WatcherOverflow::Wait => {
+            let (tx, rx) = mpsc::channel(capacity);
+            (ActionSender::Wait(tx), ActionReceiver::Wait(rx))
+        }
+        WatcherOverflow::DropOldest => {
+            let (tx, rx) = broadcast::channel(capacity);
+            (
+                ActionSender::DropOldest(tx),
+                ActionReceiver::DropOldest(rx, dropped),
+            )
+        }
+    }
+}
 
-    println!("Watcher started");
+#[derive(Clone)]
+pub enum ActionSender {
+    Wait(mpsc::Sender<Vec<WorkspaceAction>>),
+    DropOldest(broadcast::Sender<Vec<WorkspaceAction>>),
+}
+
+impl ActionSender {
+    /// Errors once the receiver is gone
+    pub async fn send(&self, actions: Vec<WorkspaceAction>) -> Result<(), ()> {
+        match self {
+            ActionSender::Wait(tx) => tx.send(actions).await.map_err(|_| ()),
+            ActionSender::DropOldest(tx) => tx.send(actions).map(|_| ()).map_err(|_| ()),
+        }
+    }
+}
+
+pub enum ActionReceiver {
+    Wait(mpsc::Receiver<Vec<WorkspaceAction>>),
+    DropOldest(broadcast::Receiver<Vec<WorkspaceAction>>, Arc<AtomicU64>),
+}
+
+impl ActionReceiver {
+    /// None once the watcher is gone
+    pub async fn recv(&mut self) -> Option<Vec<WorkspaceAction>> {
+        match self {
+            ActionReceiver::Wait(rx) => rx.recv().await,
+            ActionReceiver::DropOldest(rx, dropped) => loop {
+                match rx.recv().await {
+                    Ok(actions) => return Some(actions),
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        println!("Watcher channel full, dropped {} batches", skipped);
+                        dropped.fetch_add(skipped, Ordering::Relaxed);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            },
+        }
+    }
+
+    pub fn into_stream(self) -> impl futures_util::Stream<Item = Vec<WorkspaceAction>> {
+        futures_util::stream::unfold(self, |mut rx| async move {
+            rx.recv().await.map(|actions| (actions, rx))
+        })
+    }
+}
 
-    for res in rx {
+/// A filesystem event as notify reported it, before it's turned into actions
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct RawFsEvent {
+    pub kind: RawFsEventKind,
+    // Both the old and the new path for renames
+    pub paths: Vec<PathBuf>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawFsEventKind {
+    Created,
+    Modified,
+    Renamed,
+    Removed,
+    // Access and whatever else the platform reports
+    Other,
+}
+
+impl From<notify::Event> for RawFsEvent {
+    fn from(event: notify::Event) -> Self {
+        let kind = match event.kind {
+            EventKind::Create(_) => RawFsEventKind::Created,
+            EventKind::Modify(ModifyKind::Name(_)) => RawFsEventKind::Renamed,
+            EventKind::Modify(_) => RawFsEventKind::Modified,
+            EventKind::Remove(_) => RawFsEventKind::Removed,
+            _ => RawFsEventKind::Other,
+        };
+        RawFsEvent {
+            kind,
+            paths: event.paths,
+        }
+    }
+}
+
+/// Sends every event under `path` as it comes, nothing is filtered or mapped. Returns
+/// once nobody is receiving the events anymore
+pub async fn raw_watch(
+    path: &Path,
+    event_tx: mpsc::Sender<RawFsEvent>,
+    ready: oneshot::Sender<()>,
+) -> notify::Result<()> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |res| {
+        let _ = tx.send(res);
+    })?;
+    watcher.watch(path, RecursiveMode::Recursive)?;
+    let _ = ready.send(());
+
+    while let Some(res) = rx.recv().await {
         let event = match res {
             Ok(event) => event,
             Err(error) => {
@@ -31,211 +237,739 @@ pub async fn async_watch(
                 continue;
             }
         };
-        println!("got event: {:?}", event);
+        if event_tx.send(RawFsEvent::from(event)).await.is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// What the watcher multiplexer is asked to do, every watch has its own id
+pub enum WatchCommand {
+    Watch {
+        id: usize,
+        path: PathBuf,
+        // Boxed, the options are most of the command's size
+        options: Box<WatchOptions>,
+        action_tx: ActionSender,
+        ready: oneshot::Sender<()>,
+    },
+    Unwatch(usize),
+}
 
-        let actions = watch_event_to_actions(event, &path_clone);
+/** Runs the watchers of every workspace on this one task instead of a task each, for
+ * small machines running on a single thread. Workspaces come and go through
+ * `commands`, a workspace stops being watched once nobody receives its actions.
+ * Returns when `commands` closes. */
+pub async fn multiplex_watches(mut commands: mpsc::UnboundedReceiver<WatchCommand>) {
+    let mut watches: HashMap<usize, (WorkspaceWatch, ActionSender)> = HashMap::new();
 
-        println!("Actions received: {:?}", actions);
+    enum Next {
+        Command(Option<WatchCommand>),
+        Actions(usize, Option<Vec<WorkspaceAction>>),
+    }
 
-        for action in actions {
-            match action_tx.send(action).await {
-                Ok(_res) => {}
-                Err(e) => {
-                    println!("Error sending action: {:?}", e);
+    loop {
+        // Waiting on a watch is cancel safe, so a command coming in first loses nothing
+        let next = {
+            let watches_next = async {
+                if watches.is_empty() {
+                    return std::future::pending().await;
+                }
+                let nexts = watches.iter_mut().map(|(id, (watch, _))| {
+                    Box::pin(async move { (*id, watch.next_actions().await) })
+                });
+                futures_util::future::select_all(nexts).await.0
+            };
+            tokio::select! {
+                command = commands.recv() => Next::Command(command),
+                (id, actions) = watches_next => Next::Actions(id, actions),
+            }
+        };
+
+        match next {
+            Next::Command(None) => break,
+            Next::Command(Some(WatchCommand::Watch {
+                id,
+                path,
+                options,
+                action_tx,
+                ready,
+            })) => match WorkspaceWatch::start(&path, *options) {
+                Ok(watch) => {
+                    watches.insert(id, (watch, action_tx));
+                    let _ = ready.send(());
+                }
+                Err(e) => eprintln!("error watching file: {}", e),
+            },
+            Next::Command(Some(WatchCommand::Unwatch(id))) => {
+                watches.remove(&id);
+            }
+            Next::Actions(id, None) => {
+                match watches.get(&id).is_some_and(|(watch, _)| watch.root_gone()) {
+                    true => eprintln!("Watch {} ended, its directory was moved or deleted", id),
+                    false => println!("Watch {} ended", id),
+                }
+                watches.remove(&id);
+            }
+            Next::Actions(_, Some(actions)) if actions.is_empty() => {}
+            Next::Actions(id, Some(actions)) => {
+                let Some((_, action_tx)) = watches.get(&id) else {
+                    continue;
+                };
+                if action_tx.send(actions).await.is_err() {
+                    println!("Nobody is receiving actions of {} anymore", id);
+                    watches.remove(&id);
                 }
             }
         }
     }
+    println!("Watcher multiplexer ended");
+}
 
-    Ok(())
+/// One workspace's watches and what's needed to turn its events into actions
+pub struct WorkspaceWatch {
+    path: PathBuf,
+    options: WatchOptions,
+    watcher: notify::RecommendedWatcher,
+    events: mpsc::UnboundedReceiver<notify::Result<notify::Event>>,
+    // Tab name to the newest url written to it and when it counts as settled
+    settling: HashMap<String, (String, Instant)>,
+    // With a state file only the fields that differ from what was there before changed
+    snapshots: HashMap<String, TabState>,
+    // Tab name to how many changes it made in its current second
+    rates: HashMap<String, TabRate>,
 }
 
-fn watch_event_to_actions(event: notify::Event, base_path: &Path) -> Vec<WorkspaceAction> {
-    let canonical_path = match fs::canonicalize(base_path) {
-        Ok(path) => path,
-        Err(e) => {
-            println!("Error canonicalizing path: {:?}", e);
-            return vec![];
-        }
-    };
+struct TabRate {
+    since: Instant,
+    events: u32,
+    // Changes were held back, the tab is read again once the second is over
+    throttled: bool,
+    // The url, is_open and incognito the browser was last sent, None until one went out
+    sent: SentState,
+    // Field to the newest change of it that was held back
+    held: BTreeMap<&'static str, WorkspaceAction>,
+}
 
-    println!("canonical_path: {:?} {:?}", base_path, canonical_path);
+#[derive(Default)]
+struct SentState {
+    url: Option<String>,
+    is_open: Option<bool>,
+    incognito: Option<bool>,
+}
 
-    match event.kind {
-        notify::event::EventKind::Create(_) => {
-            let actions = event
-                .paths
-                .iter()
-                .filter_map(|path| {
-                    let workspace_path = path.strip_prefix(&canonical_path).ok()?;
+impl SentState {
+    fn sent(&mut self, action: &WorkspaceAction) {
+        match action {
+            WorkspaceAction::ChangeTabUrl(_, url) => self.url = Some(url.clone()),
+            WorkspaceAction::OpenTab(_) => self.is_open = Some(true),
+            WorkspaceAction::CloseTab(_) | WorkspaceAction::CloseTabBecause(..) => {
+                self.is_open = Some(false)
+            }
+            WorkspaceAction::SetTabIncognito(_, incognito) => self.incognito = Some(*incognito),
+            _ => {}
+        }
+    }
+}
+
+// What a throttled change is held back as, changes to the same field replace each other
+fn throttled_field(action: &WorkspaceAction) -> &'static str {
+    match action {
+        WorkspaceAction::ChangeTabUrl(..) => "url",
+        WorkspaceAction::OpenTab(_)
+        | WorkspaceAction::CloseTab(_)
+        | WorkspaceAction::CloseTabBecause(..) => "is_open",
+        WorkspaceAction::SetTabIncognito(..) => "incognito",
+        action => action.kind(),
+    }
+}
 
-                    let mut comps = workspace_path.components();
-                    let tab = comps.next()?;
+impl WorkspaceWatch {
+    /// Registers the watches, no event after this returns is missed
+    pub fn start(path: &Path, options: WatchOptions) -> notify::Result<WorkspaceWatch> {
+        // notify calls this from its own thread, waiting on the events stays cancellable
+        let (tx, events) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |res| {
+            let _ = tx.send(res);
+        })?;
 
-                    let tab_name = match tab {
-                        std::path::Component::Normal(tab_name) => {
-                            Some(tab_name.to_str().unwrap().to_string())
-                        }
-                        _ => None,
-                    }?;
-
-                    let file_name = match comps.next() {
-                        Some(Component::Normal(file_name)) => Some(file_name),
-                        None => {
-                            println!("No filename");
-                            None
-                        }
-                        _ => None,
-                    };
+        println!("Watcher starting: {}", path.to_str().unwrap());
 
-                    // Just the directory was made
-                    if file_name == None {
-                        return Some(WorkspaceAction::CreateTab(tab_name));
+        match &options.deep_tabs {
+            None => watcher.watch(path, RecursiveMode::Recursive)?,
+            Some(tabs) => {
+                watcher.watch(path, RecursiveMode::NonRecursive)?;
+                for tab in tabs {
+                    let tab_path = path.join(tab);
+                    if !tab_path.is_dir() {
+                        println!("Not watching {}, there's no such tab yet", tab);
+                        continue;
                     }
+                    watcher.watch(&tab_path, RecursiveMode::Recursive)?;
+                }
+            }
+        }
 
-                    println!("No action found");
+        println!("Watcher started");
+        if let Ok(mut watched) = options.watched.write() {
+            watched.insert(path.to_path_buf());
+            for tab in ordered_tab_names(path, &options.layout).unwrap_or_default() {
+                if options.watches_deeply(&tab) {
+                    watched.insert(path.join(tab));
+                }
+            }
+        }
 
-                    None
-                })
-                .collect();
+        let mut snapshots = HashMap::new();
+        if options.layout.state_file.is_some() {
+            let (tabs, _, _) = Workspace::read_tabs(path, &options.layout);
+            for tab in tabs {
+                let state = TabState {
+                    url: tab.url,
+                    is_open: tab.is_open,
+                    incognito: tab.incognito,
+                };
+                snapshots.insert(tab.name, state);
+            }
+        }
 
-            return actions;
+        Ok(WorkspaceWatch {
+            path: path.to_path_buf(),
+            options,
+            watcher,
+            events,
+            settling: HashMap::new(),
+            snapshots,
+            rates: HashMap::new(),
+        })
+    }
+
+    /// The workspace directory isn't there anymore, notify doesn't always say so
+    pub fn root_gone(&self) -> bool {
+        !self.path.is_dir()
+    }
+
+    /** Waits for the next filesystem event or settled url and returns the actions it
+     * made, often none. None once the watcher stopped, or the workspace directory was
+     * moved or deleted. Cancel safe, everything after the wait happens without
+     * awaiting. */
+    pub async fn next_actions(&mut self) -> Option<Vec<WorkspaceAction>> {
+        if self.root_gone() {
+            return None;
         }
+        let next_settled = self.settling.values().map(|(_, at)| *at).min();
+        let settled = async {
+            match next_settled {
+                Some(at) => tokio::time::sleep_until(at).await,
+                None => std::future::pending().await,
+            }
+        };
+        let next_unthrottled = self
+            .rates
+            .values()
+            .filter(|rate| rate.throttled)
+            .map(|rate| rate.since + RATE_WINDOW)
+            .min();
+        let unthrottled = async {
+            match next_unthrottled {
+                Some(at) => tokio::time::sleep_until(at).await,
+                None => std::future::pending().await,
+            }
+        };
 
-        notify::EventKind::Modify(_) => {
-            let actions = event
-                .paths
-                .iter()
-                .filter_map(|path| {
-                    let workspace_path = path.strip_prefix(&canonical_path).ok()?;
+        tokio::select! {
+            res = self.events.recv() => {
+                let event = match res? {
+                    Ok(event) => event,
+                    Err(error) => {
+                        println!("watch error: {:?}", error);
+                        return Some(Vec::new());
+                    }
+                };
+                Some(self.event_to_actions(event))
+            }
+            // Renaming the root is often silent, so it's looked for every now and then
+            _ = tokio::time::sleep(ROOT_CHECK_INTERVAL) => Some(Vec::new()),
+            _ = unthrottled => Some(self.end_throttling()),
+            _ = settled => {
+                let now = Instant::now();
+                let mut actions = Vec::new();
+                self.settling.retain(|tab, (url, at)| {
+                    if *at > now {
+                        return true;
+                    }
+                    actions.push(WorkspaceAction::ChangeTabUrl(tab.clone(), url.clone()));
+                    false
+                });
+                Some(actions)
+            }
+        }
+    }
 
-                    let mut comps = workspace_path.components();
-                    let tab = comps.next()?;
+    fn event_to_actions(&mut self, event: notify::Event) -> Vec<WorkspaceAction> {
+        let path = self.path.as_path();
+        let actions = watch_event_to_actions(event, path, &self.options, &mut self.snapshots);
 
-                    let tab_name = match tab {
-                        std::path::Component::Normal(tab_name) => {
-                            Some(tab_name.to_str().unwrap().to_string())
+        // A pinned tab that didn't exist when the watch started gets its watch now
+        for action in &actions {
+            if let WorkspaceAction::CreateTab(tab) = action {
+                if self.options.deep_tabs.is_some() && self.options.watches_deeply(tab) {
+                    if let Err(e) = self
+                        .watcher
+                        .watch(&path.join(tab), RecursiveMode::Recursive)
+                    {
+                        println!("Error watching new tab {}: {:?}", tab, e);
+                    }
+                }
+            }
+            self.options.track_watched(path, action);
+            // A renamed pinned tab stays pinned under its new name
+            if let WorkspaceAction::RenameTab(from, to) = action {
+                if let Some(tabs) = self.options.deep_tabs.as_mut() {
+                    if tabs.remove(from) {
+                        tabs.insert(to.clone());
+                        let _ = self.watcher.unwatch(&path.join(from));
+                        if let Err(e) = self.watcher.watch(&path.join(to), RecursiveMode::Recursive)
+                        {
+                            println!("Error watching renamed tab {}: {:?}", to, e);
                         }
-                        _ => None,
-                    }?;
+                    }
+                }
+            }
+        }
 
-                    println!("Tab name: {}", tab_name);
+        let actions = self.throttle(actions);
+        hold_unsettled_urls(actions, self.options.url_settle, &mut self.settling)
+    }
 
-                    let file_name = match comps.next() {
-                        Some(Component::Normal(file_name)) => {
-                            println!("Got file: {}", file_name.to_str().unwrap().to_string());
-                            Some(file_name)
-                        }
-                        None => {
-                            println!("No filename");
-                            None
-                        }
-                        _ => {
-                            println!("Some other component");
-                            None
-                        }
-                    };
+    /** Holds back the changes of tabs past their rate for this second. Tabs coming,
+     * going and being renamed always go through, only their contents are throttled. */
+    fn throttle(&mut self, actions: Vec<WorkspaceAction>) -> Vec<WorkspaceAction> {
+        let limit = self.options.max_tab_events_per_sec;
+        if limit == 0 {
+            return actions;
+        }
+        let now = Instant::now();
+        actions
+            .into_iter()
+            .filter(|action| {
+                if matches!(
+                    action,
+                    WorkspaceAction::CreateTab(_)
+                        | WorkspaceAction::RemoveTab(_)
+                        | WorkspaceAction::RenameTab(..)
+                ) {
+                    if let WorkspaceAction::RemoveTab(tab) | WorkspaceAction::RenameTab(tab, _) =
+                        action
+                    {
+                        self.rates.remove(tab);
+                    }
+                    return true;
+                }
+                let rate = self
+                    .rates
+                    .entry(action.tab_name().to_string())
+                    .or_insert(TabRate {
+                        since: now,
+                        events: 0,
+                        throttled: false,
+                        sent: SentState::default(),
+                        held: BTreeMap::new(),
+                    });
+                if now.duration_since(rate.since) >= RATE_WINDOW && !rate.throttled {
+                    rate.since = now;
+                    rate.events = 0;
+                }
+                rate.events += 1;
+                if rate.events <= limit {
+                    rate.sent.sent(action);
+                    return true;
+                }
+                rate.held.insert(throttled_field(action), action.clone());
+                if !rate.throttled {
+                    eprintln!(
+                        "Tab {} of {} changed more than {} times in a second, holding its changes back",
+                        action.tab_name(),
+                        self.path.display(),
+                        limit
+                    );
+                    rate.throttled = true;
+                }
+                false
+            })
+            .collect()
+    }
 
-                    if file_name == Some(OsStr::new("is_open")) {
-                        let is_open = fs::read_to_string(path).ok()?;
-                        let is_open = is_open.trim();
+    /** Sends what the throttled tabs whose second is over changed while they were held
+     * back. Url, is_open and incognito are read again and only go out if they differ
+     * from what the browser was last sent, a blank tab never sends its url. Other
+     * fields send their newest held back change. */
+    fn end_throttling(&mut self) -> Vec<WorkspaceAction> {
+        let now = Instant::now();
+        let mut actions = Vec::new();
+        for (tab, rate) in self.rates.iter_mut() {
+            if !rate.throttled || now.duration_since(rate.since) < RATE_WINDOW {
+                continue;
+            }
+            rate.throttled = false;
+            rate.since = now;
+            rate.events = 0;
+            println!("Tab {} isn't throttled anymore", tab);
+            let held = std::mem::take(&mut rate.held);
+            let tab_dir = self.path.join(tab);
+            let state = match held
+                .keys()
+                .any(|field| ["url", "is_open", "incognito"].contains(field))
+            {
+                true => self.options.layout.read_state(&tab_dir).ok(),
+                false => None,
+            };
+            let mut changed = Vec::new();
+            for (field, action) in held {
+                let current = match (field, &state) {
+                    ("url", Some(state)) if !self.options.layout.is_blank(&tab_dir) => {
+                        WorkspaceAction::ChangeTabUrl(tab.clone(), state.url.trim().to_string())
+                    }
+                    ("is_open", Some(state)) if state.is_open => {
+                        WorkspaceAction::OpenTab(tab.clone())
+                    }
+                    ("is_open", Some(_)) => WorkspaceAction::CloseTab(tab.clone()),
+                    ("incognito", Some(state)) => {
+                        WorkspaceAction::SetTabIncognito(tab.clone(), state.incognito)
+                    }
+                    // Gone, unreadable or blank, nothing to send
+                    ("url" | "is_open" | "incognito", _) => continue,
+                    _ => action,
+                };
+                let unchanged = match &current {
+                    WorkspaceAction::ChangeTabUrl(_, url) => rate.sent.url.as_ref() == Some(url),
+                    WorkspaceAction::OpenTab(_) => rate.sent.is_open == Some(true),
+                    WorkspaceAction::CloseTab(_) => rate.sent.is_open == Some(false),
+                    WorkspaceAction::SetTabIncognito(_, incognito) => {
+                        rate.sent.incognito == Some(*incognito)
+                    }
+                    _ => false,
+                };
+                if !unchanged {
+                    rate.sent.sent(&current);
+                    changed.push(current);
+                }
+            }
+            if let Some(state) = state.filter(|_| self.options.layout.state_file.is_some()) {
+                self.snapshots.insert(tab.clone(), state);
+            }
+            actions.extend(changed);
+        }
+        actions
+    }
+}
 
-                        println!("Is open contents: {}", is_open.to_owned());
+// Url changes wait in `settling` until the file stops changing, every new write to the
+// same tab pushes the deadline back. Everything else goes out right away
+fn hold_unsettled_urls(
+    actions: Vec<WorkspaceAction>,
+    url_settle: Duration,
+    settling: &mut HashMap<String, (String, Instant)>,
+) -> Vec<WorkspaceAction> {
+    if url_settle.is_zero() {
+        return actions;
+    }
+    actions
+        .into_iter()
+        .filter_map(|action| match action {
+            WorkspaceAction::ChangeTabUrl(tab, url) => {
+                settling.insert(tab, (url, Instant::now() + url_settle));
+                None
+            }
+            WorkspaceAction::RemoveTab(tab) => {
+                settling.remove(&tab);
+                Some(WorkspaceAction::RemoveTab(tab))
+            }
+            // A url still settling under the old name goes out under the new one
+            WorkspaceAction::RenameTab(from, to) => {
+                if let Some(pending) = settling.remove(&from) {
+                    settling.insert(to.clone(), pending);
+                }
+                Some(WorkspaceAction::RenameTab(from, to))
+            }
+            action => Some(action),
+        })
+        .collect()
+}
 
-                        if is_open == "1".to_string() {
-                            return Some(WorkspaceAction::OpenTab(tab_name));
-                        } else if is_open == "0".to_string() {
-                            return Some(WorkspaceAction::CloseTab(tab_name));
-                        }
-                    } else if file_name == Some(OsStr::new("url")) {
-                        let tab_url = fs::read_to_string(path).ok()?;
-                        let tab_url = tab_url.trim().to_string();
+/// Dot files in the workspace root (like the config) belong to mounttab, not to a tab
+pub fn is_hidden(name: &OsStr) -> bool {
+    name.to_str().is_some_and(|name| name.starts_with('.'))
+}
 
-                        return Some(WorkspaceAction::ChangeTabUrl(tab_name, tab_url));
-                    }
+/** Errors with InvalidInput unless every tab the action names is one plain directory
+ * name in the workspace root, so a client can't reach outside it with `..`, a
+ * separator or an absolute path. */
+pub fn check_tab_names(action: &WorkspaceAction) -> io::Result<()> {
+    let mut names = vec![action.tab_name()];
+    if let WorkspaceAction::RenameTab(_, to) = action {
+        names.push(to);
+    }
+    for name in names {
+        let mut components = Path::new(name).components();
+        let single = matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(part)), None) if part == OsStr::new(name)
+        );
+        if !single || name.contains(['/', '\\']) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?} isn't a tab name", name),
+            ));
+        }
+    }
+    Ok(())
+}
 
-                    println!("No action found");
+/** The directory name a new tab gets: no whitespace or dots around it, whitespace runs
+ * become one space and runs of slashes one dash. Applying it twice changes nothing,
+ * a name with nothing left becomes `tab`. */
+pub fn normalize_tab_name(name: &str) -> String {
+    let mut normalized = String::with_capacity(name.len());
+    let mut chars = name.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            normalized.push(' ');
+        } else if c == '/' || c == '\\' {
+            while chars.next_if(|c| *c == '/' || *c == '\\').is_some() {}
+            normalized.push('-');
+        } else {
+            normalized.push(c);
+        }
+    }
+    let normalized = normalized.trim_matches(|c: char| c == '.' || c.is_whitespace());
+    match normalized.is_empty() {
+        true => "tab".to_string(),
+        false => normalized.to_string(),
+    }
+}
 
-                    None
-                })
-                .collect();
+/** Gives the tabs the action makes their normalized name: one that isn't on disk yet
+ * and the new name of a rename. Tabs that already exist keep whatever name they have
+ * on disk. Every browser and batch write goes through this, so a tab gets the same
+ * directory however the action arrives. */
+pub fn normalize_action_names(path: &Path, action: &mut WorkspaceAction) {
+    if !path.join(action.tab_name()).exists() {
+        let from = action.tab_name().to_string();
+        action.rename_tab(&from, &normalize_tab_name(&from));
+    }
+    if let WorkspaceAction::RenameTab(_, to) = action {
+        *to = normalize_tab_name(to);
+    }
+}
 
-            return actions;
+fn watch_event_to_actions(
+    mut event: notify::Event,
+    base_path: &Path,
+    options: &WatchOptions,
+    snapshots: &mut HashMap<String, TabState>,
+) -> Vec<WorkspaceAction> {
+    let layout = &options.layout;
+    let canonical_path = match fs::canonicalize(base_path) {
+        Ok(path) => path,
+        Err(e) => {
+            println!("Error canonicalizing path: {:?}", e);
+            return vec![];
         }
-        notify::event::EventKind::Remove(_) => {
-            let actions = event
-                .paths
-                .iter()
-                .filter_map(|path| {
-                    let workspace_path = path.strip_prefix(&canonical_path).ok()?;
+    };
 
-                    let mut comps = workspace_path.components();
-                    let tab = comps.next()?;
+    event.paths.retain(|path| {
+        let first = path
+            .strip_prefix(&canonical_path)
+            .ok()
+            .and_then(|p| p.components().next());
+        !matches!(first, Some(Component::Normal(name)) if is_hidden(name))
+    });
 
-                    let tab_name = match tab {
-                        std::path::Component::Normal(tab_name) => {
-                            Some(tab_name.to_str().unwrap().to_string())
-                        }
-                        _ => None,
-                    }?;
-
-                    let file_name = match comps.next() {
-                        Some(Component::Normal(file_name)) => Some(file_name),
-                        None => {
-                            println!("No filename");
-                            None
-                        }
-                        _ => None,
-                    };
+    if let Some(action) = tab_rename(&event, &canonical_path) {
+        if let WorkspaceAction::RenameTab(from, to) = &action {
+            if let Some(snapshot) = snapshots.remove(from) {
+                snapshots.insert(to.clone(), snapshot);
+            }
+        }
+        return vec![action];
+    }
 
-                    // Just the directory was made
-                    if file_name == None {
-                        return Some(WorkspaceAction::RemoveTab(tab_name));
+    let change = match event.kind {
+        notify::EventKind::Create(_) => FileChange::Created,
+        notify::EventKind::Modify(_) => FileChange::Modified,
+        notify::EventKind::Remove(_) => FileChange::Removed,
+        _ => {
+            println!("unhandled event");
+            return vec![];
+        }
+    };
+
+    let mut actions = Vec::new();
+    for path in &event.paths {
+        let Ok(workspace_path) = path.strip_prefix(&canonical_path) else {
+            continue;
+        };
+        let mut comps = workspace_path.components();
+        let Some(Component::Normal(tab_name)) = comps.next() else {
+            continue;
+        };
+        let tab_name = tab_name.to_str().unwrap().to_string();
+
+        match comps.next() {
+            // Just the directory was made or removed
+            None => match change {
+                FileChange::Created if !path.is_dir() => {
+                    println!("Ignoring malformed tab, not a directory: {}", tab_name);
+                }
+                FileChange::Created => {
+                    // A directory made by hand gets the workspace's default open state
+                    if let Err(e) = layout.init_tab(path, options.default_open) {
+                        println!("Error setting up new tab {}: {}", tab_name, e);
                     }
+                    actions.push(WorkspaceAction::CreateTab(tab_name));
+                }
+                FileChange::Removed => {
+                    snapshots.remove(&tab_name);
+                    actions.push(WorkspaceAction::RemoveTab(tab_name));
+                }
+                FileChange::Modified => println!("No filename"),
+            },
+            Some(Component::Normal(file_name)) => {
+                actions.extend(layout.file_changed(&tab_name, file_name, path, change, snapshots));
+            }
+            _ => println!("Some other component"),
+        }
+    }
+    actions
+}
 
-                    println!("No action found");
+// A tab directory renamed within the workspace, notify reports the old and new path
+// together. Renames into or out of the workspace only have one side and aren't this
+fn tab_rename(event: &notify::Event, canonical_path: &Path) -> Option<WorkspaceAction> {
+    if event.kind != EventKind::Modify(ModifyKind::Name(RenameMode::Both)) {
+        return None;
+    }
+    let [from, to] = event.paths.as_slice() else {
+        return None;
+    };
+    let tab_name = |path: &Path| {
+        let mut comps = path.strip_prefix(canonical_path).ok()?.components();
+        match (comps.next(), comps.next()) {
+            (Some(Component::Normal(name)), None) => Some(name.to_str()?.to_string()),
+            _ => None,
+        }
+    };
+    Some(WorkspaceAction::RenameTab(tab_name(from)?, tab_name(to)?))
+}
 
-                    None
-                })
-                .collect();
+/** The tab directories a group of actions wrote to, for syncing them all to disk at
+ * once when the workspace asks for durable writes. Syncing a directory syncs every file
+ * directly in it too, and the workspace root is synced for tabs coming and going. */
+#[derive(Default)]
+pub struct SyncBatch {
+    dirs: BTreeSet<PathBuf>,
+}
 
-            return actions;
+impl SyncBatch {
+    pub fn new() -> SyncBatch {
+        SyncBatch::default()
+    }
+
+    pub fn add(&mut self, path: &Path, layout: &TabLayout, action: &WorkspaceAction) {
+        self.dirs.insert(path.to_path_buf());
+        self.dirs.insert(path.join(action.tab_name()));
+        match action {
+            WorkspaceAction::RenameTab(_, to) => {
+                self.dirs.insert(path.join(to));
+            }
+            WorkspaceAction::ReorderTab(..) => {
+                for tab in ordered_tab_names(path, layout).unwrap_or_default() {
+                    self.dirs.insert(path.join(tab));
+                }
+            }
+            _ => {}
         }
-        _ => {
-            println!("unhandled event");
+    }
+
+    /// Flushes everything written since the batch started, directories that are gone
+    /// by now are skipped
+    pub fn sync(&self) -> io::Result<()> {
+        // Files first so the directories are synced after what's in them
+        for dir in &self.dirs {
+            let Ok(entries) = fs::read_dir(dir) else {
+                continue;
+            };
+            for entry in entries {
+                let entry = entry?;
+                if entry.file_type()?.is_file() {
+                    fs::File::open(entry.path())?.sync_all()?;
+                }
+            }
+        }
+        for dir in self.dirs.iter().rev() {
+            if dir.is_dir() {
+                fs::File::open(dir)?.sync_all()?;
+            }
         }
+        Ok(())
     }
+}
 
-    vec![]
+pub fn apply_action_to_fs(
+    path: &Path,
+    config: &WorkspaceConfig,
+    action: &WorkspaceAction,
+) -> io::Result<()> {
+    let mut batch = SyncBatch::new();
+    apply_action_to_fs_batched(path, config, action, &mut batch)?;
+    if config.durable_writes {
+        batch.sync()?;
+    }
+    Ok(())
 }
 
-pub fn apply_action_to_fs(path: &Path, action: &WorkspaceAction) -> io::Result<()> {
+/// Writes the action without syncing, what it touched is added to `batch` so the caller
+/// can sync once after a group of actions
+pub fn apply_action_to_fs_batched(
+    path: &Path,
+    config: &WorkspaceConfig,
+    action: &WorkspaceAction,
+    batch: &mut SyncBatch,
+) -> io::Result<()> {
+    batch.add(path, &config.layout, action);
+    let layout = &config.layout;
     match action {
         WorkspaceAction::OpenTab(tab) => {
             let dir_path = path.join(tab);
-            let is_open_file = dir_path.join("is_open");
             if !dir_path.exists() {
-                fs::create_dir(dir_path)?;
+                fs::create_dir(&dir_path)?;
             }
-            fs::write(is_open_file, "1")?;
+            layout.write_is_open(&dir_path, true)?;
         }
-        WorkspaceAction::CloseTab(tab) => {
+        WorkspaceAction::CloseTab(tab) | WorkspaceAction::CloseTabBecause(tab, _) => {
             let dir_path = path.join(tab);
-            let is_open_file = dir_path.join("is_open");
             if !dir_path.exists() {
-                fs::create_dir(dir_path)?;
+                fs::create_dir(&dir_path)?;
             }
-            fs::write(is_open_file, "0")?;
+            layout.write_is_open(&dir_path, false)?;
         }
         WorkspaceAction::CreateTab(tab) => {
             let dir_path = path.join(tab);
-            let is_open_file = dir_path.join("is_open");
-            let url_file = dir_path.join("url");
+            if dir_path.exists() && !dir_path.is_dir() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} exists and is not a tab directory", dir_path.display()),
+                ));
+            }
             if !dir_path.exists() {
-                fs::create_dir(dir_path)?;
+                fs::create_dir(&dir_path)?;
             }
-            fs::write(is_open_file, "0")?;
-            fs::write(url_file, "")?;
+            layout.write_is_open(&dir_path, config.default_open)?;
+            // Instead of an empty url file, a new tab is blank until it gets a real url
+            layout.write_blank(&dir_path, true)?;
         }
         WorkspaceAction::RemoveTab(tab) => {
             let dir_path = path.join(tab);
@@ -244,45 +978,1106 @@ pub fn apply_action_to_fs(path: &Path, action: &WorkspaceAction) -> io::Result<(
         WorkspaceAction::ChangeTabUrl(tab, url) => {
             let dir_path = path.join(tab);
             println!("dir path: {}", dir_path.to_str().unwrap());
-            let url_file = dir_path.join("url");
             if !dir_path.exists() {
-                fs::create_dir(dir_path)?;
+                fs::create_dir(&dir_path)?;
+            }
+            if layout.is_blank(&dir_path) {
+                if is_blank_url(url) {
+                    return Ok(());
+                }
+                layout.write_blank(&dir_path, false)?;
+            }
+            let url = config.stored_url(url);
+            println!("Writing url: {}", url);
+            layout.write_url(&dir_path, &url)?;
+        }
+        WorkspaceAction::SetTabIncognito(tab, incognito) => {
+            let dir_path = path.join(tab);
+            if !dir_path.exists() {
+                fs::create_dir(&dir_path)?;
             }
-            println!("Writing url: {}", url_file.to_str().unwrap());
-            fs::write(url_file, url)?;
+            layout.write_incognito(&dir_path, *incognito)?;
+        }
+        WorkspaceAction::SetTabMeta(tab, meta) => {
+            config.check_meta(meta)?;
+            let contents = serde_json::to_string_pretty(meta)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            write_capped(
+                &path.join(tab),
+                &layout.meta_file,
+                &contents,
+                config.max_meta_bytes,
+            )?;
         }
+        WorkspaceAction::SetTabNotes(tab, notes) => {
+            write_capped(
+                &path.join(tab),
+                &layout.notes_file,
+                notes,
+                config.max_notes_bytes,
+            )?;
+        }
+        WorkspaceAction::ReorderTab(tab, index) => {
+            let mut names = ordered_tab_names(path, layout)?;
+            let Some(from) = names.iter().position(|name| name == tab) else {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No tab named {}", tab),
+                ));
+            };
+            let name = names.remove(from);
+            names.insert((*index).min(names.len()), name);
+            // Every tab gets renumbered so the positions stay contiguous
+            for (position, name) in names.iter().enumerate() {
+                fs::write(
+                    path.join(name).join(&layout.order_file),
+                    position.to_string(),
+                )?;
+            }
+        }
+        WorkspaceAction::RenameTab(from, to) => {
+            let to_path = path.join(to);
+            if to_path.exists() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} already exists", to_path.display()),
+                ));
+            }
+            fs::rename(path.join(from), to_path)?;
+        }
+        WorkspaceAction::ActivateTab(tab) => {
+            if !path.join(tab).is_dir() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No tab named {}", tab),
+                ));
+            }
+            let mut mru = read_mru(path);
+            mru.retain(|name| name != tab);
+            mru.insert(0, tab.clone());
+            fs::write(path.join(MRU_FILE), mru.join("\n"))?;
+        }
+        WorkspaceAction::SetTabFavicon(tab, favicon) => {
+            write_capped(
+                &path.join(tab),
+                &layout.favicon_file,
+                favicon,
+                config.max_favicon_bytes,
+            )?;
+        }
+        WorkspaceAction::SetTabScroll(tab, scroll) => {
+            if !scroll.is_finite() || *scroll < 0.0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} isn't a scroll position", scroll),
+                ));
+            }
+            write_capped(&path.join(tab), &layout.scroll_file, &scroll.to_string(), 0)?;
+        }
+    };
+    if config.paranoid_writes {
+        verify_action_on_fs(path, config, action)?;
+    }
+    Ok(())
+}
+
+/** Errors when the tab on disk doesn't have the values the precondition expects, the
+ * compare half of a compare and swap with `apply_action_to_fs`. A tab that doesn't exist
+ * has an empty url and is closed. */
+pub fn check_precondition(
+    path: &Path,
+    config: &WorkspaceConfig,
+    tab: &str,
+    precondition: &TabPrecondition,
+) -> io::Result<()> {
+    let dir_path = path.join(tab);
+    let state = match dir_path.is_dir() {
+        true => config.layout.read_state(&dir_path)?,
+        false => TabState::default(),
+    };
+    let mut conflicts = Vec::new();
+    if let Some(url) = &precondition.url {
+        if state.url.trim() != config.stored_url(url).trim() {
+            conflicts.push(format!("url is {:?}, expected {:?}", state.url.trim(), url));
+        }
+    }
+    if let Some(is_open) = precondition.is_open {
+        if state.is_open != is_open {
+            conflicts.push(format!(
+                "is_open is {}, expected {}",
+                state.is_open, is_open
+            ));
+        }
+    }
+    if let Some(incognito) = precondition.incognito {
+        if state.incognito != incognito {
+            conflicts.push(format!(
+                "incognito is {}, expected {}",
+                state.incognito, incognito
+            ));
+        }
+    }
+    match conflicts.is_empty() {
+        true => Ok(()),
+        false => Err(io::Error::other(format!(
+            "Tab {} changed: {}",
+            tab,
+            conflicts.join(", ")
+        ))),
+    }
+}
+
+/// Reads back what `apply_action_to_fs` wrote, errors if the disk doesn't match the action
+pub fn verify_action_on_fs(
+    path: &Path,
+    config: &WorkspaceConfig,
+    action: &WorkspaceAction,
+) -> io::Result<()> {
+    let layout = &config.layout;
+    let dir_path = path.join(action.tab_name());
+    let state = || {
+        layout
+            .read_state(&dir_path)
+            .map_err(|_| not_landed(&dir_path))
     };
+    let expect = |landed: bool, file: &Path| match landed {
+        true => Ok(()),
+        false => Err(not_landed(file)),
+    };
+    match action {
+        WorkspaceAction::OpenTab(_) => expect(state()?.is_open, &layout.is_open_path(&dir_path)),
+        WorkspaceAction::CloseTab(_) | WorkspaceAction::CloseTabBecause(..) => {
+            expect(!state()?.is_open, &layout.is_open_path(&dir_path))
+        }
+        WorkspaceAction::CreateTab(_) => {
+            let state = state()?;
+            expect(
+                state.is_open == config.default_open && state.url.is_empty(),
+                &dir_path,
+            )
+        }
+        WorkspaceAction::RemoveTab(_) => expect(!dir_path.exists(), &dir_path),
+        WorkspaceAction::RenameTab(_, to) => {
+            expect(!dir_path.exists() && path.join(to).is_dir(), &path.join(to))
+        }
+        WorkspaceAction::ChangeTabUrl(_, url)
+            if is_blank_url(url) && layout.is_blank(&dir_path) =>
+        {
+            Ok(())
+        }
+        WorkspaceAction::ChangeTabUrl(_, url) => {
+            expect(state()?.url == config.stored_url(url), &dir_path)
+        }
+        WorkspaceAction::SetTabIncognito(_, incognito) => {
+            expect(state()?.incognito == *incognito, &dir_path)
+        }
+        WorkspaceAction::SetTabMeta(_, meta) => {
+            let meta_file = dir_path.join(&layout.meta_file);
+            let written: serde_json::Value = serde_json::from_str(&fs::read_to_string(&meta_file)?)
+                .map_err(|_| not_landed(&meta_file))?;
+            expect(written == *meta, &meta_file)
+        }
+        WorkspaceAction::SetTabNotes(_, notes) => {
+            expect_contents(&dir_path.join(&layout.notes_file), notes)
+        }
+        WorkspaceAction::SetTabFavicon(_, favicon) => {
+            expect_contents(&dir_path.join(&layout.favicon_file), favicon)
+        }
+        WorkspaceAction::SetTabScroll(_, scroll) => {
+            let scroll_file = dir_path.join(&layout.scroll_file);
+            expect(read_scroll(&scroll_file) == Some(*scroll), &scroll_file)
+        }
+        WorkspaceAction::ActivateTab(tab) => {
+            expect(read_mru(path).first() == Some(tab), &path.join(MRU_FILE))
+        }
+        WorkspaceAction::ReorderTab(tab, index) => {
+            let names = ordered_tab_names(path, layout)?;
+            let expected = (*index).min(names.len().saturating_sub(1));
+            expect(
+                names.iter().position(|name| name == tab) == Some(expected),
+                &dir_path.join(&layout.order_file),
+            )
+        }
+    }
+}
+
+fn read_tab_order(tab_dir: &Path, layout: &TabLayout) -> Option<usize> {
+    fs::read_to_string(tab_dir.join(&layout.order_file))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+// Tabs by their order file, the ones without go last by name
+fn tab_sort_key(tab_dir: &Path, name: &str, layout: &TabLayout) -> (usize, String) {
+    (
+        read_tab_order(tab_dir, layout).unwrap_or(usize::MAX),
+        name.to_string(),
+    )
+}
+
+/// The names of the tabs in the workspace in their order
+pub fn ordered_tab_names(path: &Path, layout: &TabLayout) -> io::Result<Vec<String>> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        if is_hidden(&entry.file_name()) || !entry.path().is_dir() {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().to_string();
+        names.push((tab_sort_key(&entry.path(), &name, layout), name));
+    }
+    names.sort();
+    Ok(names.into_iter().map(|(_, name)| name).collect())
+}
+
+/// Groups of tabs that share an order value, each sorted by name
+pub fn order_collisions(path: &Path, layout: &TabLayout) -> io::Result<Vec<Vec<String>>> {
+    let mut by_order: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+    for name in ordered_tab_names(path, layout)? {
+        if let Some(order) = read_tab_order(&path.join(&name), layout) {
+            by_order.entry(order).or_default().push(name);
+        }
+    }
+    Ok(by_order
+        .into_values()
+        .filter(|names| names.len() > 1)
+        .collect())
+}
+
+/// Renumbers every tab 0, 1, 2... in its current order, tabs that collided keep their
+/// name order and tabs without an order file get one after the rest
+pub fn repair_tab_order(path: &Path, layout: &TabLayout) -> io::Result<()> {
+    for (position, name) in ordered_tab_names(path, layout)?.iter().enumerate() {
+        fs::write(
+            path.join(name).join(&layout.order_file),
+            position.to_string(),
+        )?;
+    }
     Ok(())
 }
 
+fn expect_contents(file: &Path, expected: &str) -> io::Result<()> {
+    match fs::read_to_string(file) {
+        Ok(contents) if contents == expected => Ok(()),
+        _ => Err(not_landed(file)),
+    }
+}
+
+fn not_landed(file: &Path) -> io::Error {
+    io::Error::other(format!("write to {} didn't land on disk", file.display()))
+}
+
+// Refuses to write more than `cap` bytes so a misbehaving client can't bloat the workspace
+fn write_capped(dir_path: &Path, file_name: &str, contents: &str, cap: usize) -> io::Result<()> {
+    if cap != 0 && contents.len() > cap {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} is {} bytes, the limit is {}",
+                file_name,
+                contents.len(),
+                cap
+            ),
+        ));
+    }
+    if !dir_path.exists() {
+        fs::create_dir(dir_path)?;
+    }
+    fs::write(dir_path.join(file_name), contents)
+}
+
+fn read_scroll(scroll_file: &Path) -> Option<f64> {
+    fs::read_to_string(scroll_file).ok()?.trim().parse().ok()
+}
+
+/// Tab names in the workspace's MRU file, most recent first
+pub fn read_mru(path: &Path) -> Vec<String> {
+    fs::read_to_string(path.join(MRU_FILE))
+        .unwrap_or_default()
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// When the tab was last closed, if it's closed. That's the last write to `is_open`
+pub fn tab_closed_since(path: &Path, tab: &str, layout: &TabLayout) -> Option<SystemTime> {
+    let tab_dir = path.join(tab);
+    if layout.read_state(&tab_dir).ok()?.is_open {
+        return None;
+    }
+    fs::metadata(layout.is_open_path(&tab_dir))
+        .ok()?
+        .modified()
+        .ok()
+}
+
+/// When the tab was last opened, if it's open. That's the last write to `is_open`
+pub fn tab_open_since(path: &Path, tab: &str, layout: &TabLayout) -> Option<SystemTime> {
+    let tab_dir = path.join(tab);
+    if !layout.read_state(&tab_dir).ok()?.is_open {
+        return None;
+    }
+    fs::metadata(layout.is_open_path(&tab_dir))
+        .ok()?
+        .modified()
+        .ok()
+}
+
+/// Moves the tab into the workspace's `.archive`, a tab archived earlier with the same
+/// name gets the new one a numbered suffix
+pub fn archive_tab(path: &Path, tab: &str) -> io::Result<()> {
+    let archive = path.join(ARCHIVE_DIR);
+    fs::create_dir_all(&archive)?;
+    let mut target = archive.join(tab);
+    let mut n = 1;
+    while target.exists() {
+        target = archive.join(format!("{}-{}", tab, n));
+        n += 1;
+    }
+    fs::rename(path.join(tab), target)
+}
+
 static NEXT_WORKSPACE_ID: AtomicUsize = AtomicUsize::new(1);
 
 impl Workspace {
     pub fn new_from_fs(path: &Path) -> Workspace {
-        let tab_dirs = fs::read_dir(path);
-        let workspace_id = NEXT_WORKSPACE_ID.fetch_add(1, Ordering::Relaxed);
+        let config = Workspace::read_config(path);
+        let (tabs, malformed_tabs, tab_errors) = Workspace::read_tabs(path, &config.layout);
 
         Workspace {
-            id: workspace_id.to_string(),
+            id: Workspace::stable_id(path, &config),
             name: "Testing".to_owned(),
-            tabs: tab_dirs
-                .unwrap()
-                .into_iter()
-                .map(|d| Workspace::read_tab_from_dir(&d.unwrap().path()))
-                .into_iter()
-                .collect(),
+            tabs,
+            malformed_tabs,
+            tab_errors,
             path: path.to_str().unwrap().to_owned(),
+            config,
+            evicted: false,
+        }
+    }
+
+    /** The id lives in the workspace's `.mounttab.json`, not in its path, so it stays
+     * the same when the directory is moved. A workspace without one gets a new id
+     * that is saved right away. */
+    fn stable_id(path: &Path, config: &WorkspaceConfig) -> String {
+        if let Some(id) = &config.id {
+            return id.clone();
+        }
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let id = format!(
+            "{:x}-{:x}",
+            nanos,
+            NEXT_WORKSPACE_ID.fetch_add(1, Ordering::Relaxed)
+        );
+        if let Err(e) = WorkspaceConfig::update(path, |config| config.id = Some(id.clone())) {
+            eprintln!(
+                "Error saving the workspace id, it won't survive a restart: {}",
+                e
+            );
+        }
+        id
+    }
+
+    /// Reads the tabs and config from disk again, keeping the id and name
+    pub fn reload_from_fs(&self) -> Workspace {
+        let path = Path::new(&self.path);
+        let config = Workspace::read_config(path);
+        let (tabs, malformed_tabs, tab_errors) = Workspace::read_tabs(path, &config.layout);
+        Workspace {
+            tabs,
+            malformed_tabs,
+            tab_errors,
+            config,
+            evicted: false,
+            ..self.clone()
         }
     }
 
-    fn read_tab_from_dir(tab_dir: &Path) -> Tab {
-        let tab_name = tab_dir.components().last().unwrap();
-        let is_open_file = tab_dir.join("is_open");
-        let url_file = tab_dir.join("url");
-        Tab {
+    fn read_config(path: &Path) -> WorkspaceConfig {
+        WorkspaceConfig::load(path).unwrap_or_else(|e| {
+            eprintln!("Error reading workspace config, using defaults: {}", e);
+            WorkspaceConfig::default()
+        })
+    }
+
+    /** Returns the tabs, the names of entries that should be tabs but aren't directories
+     * and the tabs that couldn't be read with why. A tab that can't be read is left out,
+     * the rest of the workspace still loads. */
+    pub fn read_tabs(
+        path: &Path,
+        layout: &TabLayout,
+    ) -> (Vec<Tab>, Vec<String>, BTreeMap<String, String>) {
+        let mut tabs = Vec::new();
+        let mut malformed = Vec::new();
+        let mut errors = BTreeMap::new();
+
+        let tab_dirs = match fs::read_dir(path) {
+            Ok(tab_dirs) => tab_dirs,
+            Err(e) => {
+                eprintln!("Error reading workspace {}: {}", path.display(), e);
+                return (Vec::new(), malformed, errors);
+            }
+        };
+
+        for dir in tab_dirs {
+            let dir = match dir {
+                Ok(dir) => dir,
+                Err(e) => {
+                    eprintln!("Error listing a tab of {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            if is_hidden(&dir.file_name()) {
+                continue;
+            }
+            if !dir.path().is_dir() {
+                let name = dir.file_name().to_string_lossy().to_string();
+                println!("Skipping malformed tab, not a directory: {}", name);
+                malformed.push(name);
+                continue;
+            }
+            let name = dir.file_name().to_string_lossy().to_string();
+            match Workspace::read_tab_from_dir(&dir.path(), layout) {
+                Ok(tab) => tabs.push((tab_sort_key(&dir.path(), &name, layout), tab)),
+                Err(e) => {
+                    eprintln!("Skipping tab {}, it can't be read: {}", name, e);
+                    errors.insert(name, e.to_string());
+                }
+            }
+        }
+
+        // Equal order values fall back to the name so the order is still deterministic
+        tabs.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for pair in tabs.windows(2) {
+            let ((a, first), (b, second)) = (&pair[0], &pair[1]);
+            if a.0 == b.0 && a.0 != usize::MAX {
+                eprintln!(
+                    "Tabs {} and {} in {} have the same order {}, repair the order to fix it",
+                    first.name,
+                    second.name,
+                    path.display(),
+                    a.0
+                );
+            }
+        }
+        let tabs = tabs.into_iter().map(|(_, tab)| tab).collect();
+        (tabs, malformed, errors)
+    }
+
+    fn read_tab_from_dir(tab_dir: &Path, layout: &TabLayout) -> io::Result<Tab> {
+        let tab_name = tab_dir.components().next_back().unwrap();
+        let state = layout.read_state(tab_dir)?;
+        Ok(Tab {
             name: tab_name.as_os_str().to_str().unwrap().to_string(),
-            is_open: fs::read_to_string(is_open_file).unwrap() == "1",
-            url: fs::read_to_string(url_file).unwrap(),
+            is_open: state.is_open,
+            url: state.url,
+            incognito: state.incognito,
+            meta: fs::read_to_string(tab_dir.join(&layout.meta_file))
+                .ok()
+                .and_then(|meta| serde_json::from_str(&meta).ok()),
+            notes: fs::read_to_string(tab_dir.join(&layout.notes_file)).ok(),
+            favicon: fs::read_to_string(tab_dir.join(&layout.favicon_file)).ok(),
+            scroll: read_scroll(&tab_dir.join(&layout.scroll_file)),
+            is_blank: layout.is_blank(tab_dir),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MetaType;
+    use crate::test_util::{write_tab, TempDir};
+
+    fn tab(path: &Path, name: &str) -> Tab {
+        let (tabs, _, _) = Workspace::read_tabs(path, &TabLayout::default());
+        tabs.into_iter().find(|tab| tab.name == name).unwrap()
+    }
+
+    #[test]
+    fn incognito_round_trips() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "a", "https://example.com/", true);
+        let config = WorkspaceConfig::default();
+        assert!(!tab(&path, "a").incognito);
+
+        let private = WorkspaceAction::SetTabIncognito("a".into(), true);
+        apply_action_to_fs(&path, &config, &private).unwrap();
+        assert!(path.join("a").join("incognito").exists());
+        assert!(tab(&path, "a").incognito);
+
+        let public = WorkspaceAction::SetTabIncognito("a".into(), false);
+        apply_action_to_fs(&path, &config, &public).unwrap();
+        assert!(!tab(&path, "a").incognito);
+    }
+
+    #[test]
+    fn incognito_marker_becomes_an_action() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "a", "https://example.com/", true);
+        let marker = path.join("a").join("incognito");
+        let changed = |change| {
+            TabLayout::default().file_changed(
+                "a",
+                OsStr::new("incognito"),
+                &marker,
+                change,
+                &mut HashMap::new(),
+            )
+        };
+        assert_eq!(
+            changed(FileChange::Created),
+            vec![WorkspaceAction::SetTabIncognito("a".into(), true)]
+        );
+        assert_eq!(
+            changed(FileChange::Removed),
+            vec![WorkspaceAction::SetTabIncognito("a".into(), false)]
+        );
+    }
+
+    fn options(config: &WorkspaceConfig) -> WatchOptions {
+        WatchOptions {
+            url_settle: Duration::ZERO,
+            ..WatchOptions::from_config(config)
+        }
+    }
+
+    // Collects actions until one matches, None if none does within a few seconds
+    async fn wait_for(
+        rx: &mut ActionReceiver,
+        mut pick: impl FnMut(&WorkspaceAction) -> bool,
+    ) -> Option<WorkspaceAction> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let actions = tokio::time::timeout_at(deadline, rx.recv()).await.ok()??;
+            if let Some(action) = actions.into_iter().find(|action| pick(action)) {
+                return Some(action);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn changes_after_ready_are_seen() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "a", "https://example.com/", true);
+        let options = options(&WorkspaceConfig::default());
+
+        for round in 0..5 {
+            let (tx, mut rx) = action_channel(16, WatcherOverflow::Wait, Arc::default());
+            let (ready_tx, ready) = oneshot::channel();
+            let watch_path = path.clone();
+            let watch_options = options.clone();
+            let watcher =
+                tokio::spawn(
+                    async move { async_watch(&watch_path, watch_options, tx, ready_tx).await },
+                );
+            ready.await.unwrap();
+
+            let url = format!("https://example.com/{}", round);
+            fs::write(path.join("a").join("url"), &url).unwrap();
+            // A write can be seen twice, as the truncate and as the write
+            let changed = WorkspaceAction::ChangeTabUrl("a".into(), url);
+            let seen = wait_for(&mut rx, |action| *action == changed).await;
+            assert_eq!(seen, Some(changed));
+            watcher.abort();
+        }
+    }
+
+    #[test]
+    fn a_file_posing_as_a_tab_is_malformed() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "a", "https://example.com/", true);
+        fs::write(path.join("mytab"), "not a tab").unwrap();
+
+        let workspace = Workspace::new_from_fs(&path);
+        assert_eq!(workspace.malformed_tabs, vec!["mytab".to_string()]);
+        let names: Vec<&str> = workspace.tabs.iter().map(|tab| tab.name.as_str()).collect();
+        assert_eq!(names, vec!["a"]);
+
+        let create = WorkspaceAction::CreateTab("mytab".into());
+        let err = apply_action_to_fs(&path, &workspace.config, &create).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(path.join("mytab")).unwrap(), "not a tab");
+    }
+
+    // Starts watching and waits until the watches are registered
+    async fn watch(
+        path: &Path,
+        options: WatchOptions,
+    ) -> (ActionReceiver, tokio::task::JoinHandle<()>) {
+        let (tx, rx) = action_channel(16, WatcherOverflow::Wait, Arc::default());
+        let (ready_tx, ready) = oneshot::channel();
+        let path = path.to_path_buf();
+        let watcher = tokio::spawn(async move {
+            let _ = async_watch(&path, options, tx, ready_tx).await;
+        });
+        ready.await.unwrap();
+        (rx, watcher)
+    }
+
+    // Everything the watcher sends until it's quiet for `quiet`
+    async fn drain(rx: &mut ActionReceiver, quiet: Duration) -> Vec<WorkspaceAction> {
+        let mut actions = Vec::new();
+        while let Ok(Some(batch)) = tokio::time::timeout(quiet, rx.recv()).await {
+            actions.extend(batch);
+        }
+        actions
+    }
+
+    #[tokio::test]
+    async fn typing_a_url_sends_one_change() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "a", "https://example.com/", true);
+        let options = WatchOptions {
+            url_settle: Duration::from_millis(200),
+            max_tab_events_per_sec: 0,
+            ..WatchOptions::from_config(&WorkspaceConfig::default())
+        };
+        let (mut rx, watcher) = watch(&path, options).await;
+
+        let url = "https://typed.example/";
+        for end in 1..=url.len() {
+            fs::write(path.join("a").join("url"), &url[..end]).unwrap();
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        let actions = drain(&mut rx, Duration::from_millis(600)).await;
+        assert_eq!(
+            actions,
+            vec![WorkspaceAction::ChangeTabUrl("a".into(), url.into())]
+        );
+        watcher.abort();
+    }
+
+    #[test]
+    fn an_oversized_note_is_rejected() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "a", "https://example.com/", true);
+        let config = WorkspaceConfig {
+            max_notes_bytes: 16,
+            ..WorkspaceConfig::default()
+        };
+
+        let fits = WorkspaceAction::SetTabNotes("a".into(), "short".into());
+        apply_action_to_fs(&path, &config, &fits).unwrap();
+        let oversized = WorkspaceAction::SetTabNotes("a".into(), "x".repeat(17));
+        let err = apply_action_to_fs(&path, &config, &oversized).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(
+            fs::read_to_string(path.join("a").join("notes.md")).unwrap(),
+            "short"
+        );
+
+        let favicon = WorkspaceAction::SetTabFavicon("a".into(), "x".repeat(17));
+        let config = WorkspaceConfig {
+            max_favicon_bytes: 16,
+            ..WorkspaceConfig::default()
+        };
+        assert!(apply_action_to_fs(&path, &config, &favicon).is_err());
+    }
+
+    #[tokio::test]
+    async fn only_pinned_tabs_are_watched_deeply() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "pinned", "https://example.com/", true);
+        write_tab(&path, "other", "https://example.com/", true);
+        let options = WatchOptions {
+            deep_tabs: Some(HashSet::from(["pinned".to_string()])),
+            ..options(&WorkspaceConfig::default())
+        };
+        let (mut rx, watcher) = watch(&path, options).await;
+
+        fs::write(path.join("other").join("url"), "https://other.example/").unwrap();
+        assert_eq!(drain(&mut rx, Duration::from_millis(300)).await, vec![]);
+
+        let url = "https://pinned.example/";
+        fs::write(path.join("pinned").join("url"), url).unwrap();
+        let changed = WorkspaceAction::ChangeTabUrl("pinned".into(), url.into());
+        assert_eq!(
+            wait_for(&mut rx, |action| *action == changed).await,
+            Some(changed)
+        );
+
+        // Tabs coming and going are still seen through the root
+        fs::create_dir(path.join("new")).unwrap();
+        let created = WorkspaceAction::CreateTab("new".into());
+        assert_eq!(
+            wait_for(&mut rx, |action| *action == created).await,
+            Some(created)
+        );
+        watcher.abort();
+    }
+
+    #[test]
+    fn verification_catches_a_dropped_write() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "a", "https://example.com/", true);
+        let config = WorkspaceConfig {
+            paranoid_writes: true,
+            ..WorkspaceConfig::default()
+        };
+        let change = WorkspaceAction::ChangeTabUrl("a".into(), "https://new.example/".into());
+        apply_action_to_fs(&path, &config, &change).unwrap();
+
+        // A filesystem that acked the write and never stored it
+        fs::write(path.join("a").join("url"), "https://example.com/").unwrap();
+        assert!(verify_action_on_fs(&path, &config, &change).is_err());
+        let close = WorkspaceAction::CloseTab("a".into());
+        apply_action_to_fs(&path, &config, &close).unwrap();
+        fs::write(path.join("a").join("is_open"), "1").unwrap();
+        assert!(verify_action_on_fs(&path, &config, &close).is_err());
+
+        let open = WorkspaceAction::OpenTab("a".into());
+        assert!(verify_action_on_fs(&path, &config, &open).is_ok());
+    }
+
+    #[test]
+    fn colliding_orders_sort_by_name_and_repair() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        let layout = TabLayout::default();
+        for (name, order) in [("c", "1"), ("b", "1"), ("a", "2"), ("d", "0")] {
+            write_tab(&path, name, "https://example.com/", true);
+            fs::write(path.join(name).join(&layout.order_file), order).unwrap();
+        }
+
+        let names = |workspace: &Workspace| -> Vec<String> {
+            workspace.tabs.iter().map(|tab| tab.name.clone()).collect()
+        };
+        let workspace = Workspace::new_from_fs(&path);
+        assert_eq!(names(&workspace), vec!["d", "b", "c", "a"]);
+        assert_eq!(
+            order_collisions(&path, &layout).unwrap(),
+            vec![vec!["b".to_string(), "c".to_string()]]
+        );
+
+        repair_tab_order(&path, &layout).unwrap();
+        assert!(order_collisions(&path, &layout).unwrap().is_empty());
+        assert_eq!(
+            names(&Workspace::new_from_fs(&path)),
+            vec!["d", "b", "c", "a"]
+        );
+        let order = fs::read_to_string(path.join("a").join(&layout.order_file)).unwrap();
+        assert_eq!(order, "3");
+    }
+
+    #[tokio::test]
+    async fn touching_the_toggle_flips_the_tab_once() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "a", "https://example.com/", true);
+        let (mut rx, watcher) = watch(&path, options(&WorkspaceConfig::default())).await;
+
+        let trigger = path.join("a").join("toggle");
+        fs::write(&trigger, "").unwrap();
+        // The write to is_open can be seen twice, as the truncate and as the write
+        let close = WorkspaceAction::CloseTab("a".into());
+        let actions = drain(&mut rx, Duration::from_millis(500)).await;
+        assert!(!actions.is_empty() && actions.iter().all(|action| *action == close));
+        assert!(!trigger.exists());
+        assert!(!tab(&path, "a").is_open);
+
+        fs::write(&trigger, "").unwrap();
+        let open = WorkspaceAction::OpenTab("a".into());
+        let actions = drain(&mut rx, Duration::from_millis(500)).await;
+        assert!(!actions.is_empty() && actions.iter().all(|action| *action == open));
+        assert!(tab(&path, "a").is_open);
+        watcher.abort();
+    }
+
+    #[test]
+    fn a_meta_schema_rejects_keys_it_doesnt_know() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "a", "https://example.com/", true);
+        let config = WorkspaceConfig {
+            meta_schema: Some(BTreeMap::from([
+                ("title".to_string(), MetaType::String),
+                ("stars".to_string(), MetaType::Number),
+            ])),
+            ..WorkspaceConfig::default()
+        };
+        let meta = |meta| WorkspaceAction::SetTabMeta("a".into(), meta);
+
+        let fits = meta(serde_json::json!({ "title": "Docs", "stars": 3 }));
+        apply_action_to_fs(&path, &config, &fits).unwrap();
+        let meta_file = path.join("a").join("meta.json");
+        let written = fs::read_to_string(&meta_file).unwrap();
+
+        for rejected in [
+            serde_json::json!({ "title": "Docs", "colour": "red" }),
+            serde_json::json!({ "stars": "three" }),
+            serde_json::json!(["not", "an", "object"]),
+        ] {
+            let err = apply_action_to_fs(&path, &config, &meta(rejected)).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            assert_eq!(fs::read_to_string(&meta_file).unwrap(), written);
+        }
+
+        // Without a schema anything goes
+        let anything = meta(serde_json::json!({ "colour": "red" }));
+        apply_action_to_fs(&path, &WorkspaceConfig::default(), &anything).unwrap();
+    }
+
+    #[test]
+    fn an_unreadable_tab_doesnt_fail_the_workspace() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "good", "https://example.com/", true);
+        write_tab(&path, "bad", "https://example.com/", true);
+        // Permissions don't stop root from reading, a url that isn't utf-8 fails for anyone
+        fs::write(path.join("bad").join("url"), [0xff, 0xfe, 0xfd]).unwrap();
+
+        let workspace = Workspace::new_from_fs(&path);
+        let names: Vec<&str> = workspace.tabs.iter().map(|tab| tab.name.as_str()).collect();
+        assert_eq!(names, vec!["good"]);
+        assert_eq!(workspace.tab_errors.keys().collect::<Vec<_>>(), vec!["bad"]);
+        assert!(workspace.malformed_tabs.is_empty());
+    }
+
+    #[tokio::test]
+    async fn new_tabs_start_with_the_default_open_state() {
+        let dir = TempDir::new();
+        for default_open in [true, false] {
+            let path = dir.dir(&format!("ws-{}", default_open));
+            let config = WorkspaceConfig {
+                default_open,
+                ..WorkspaceConfig::default()
+            };
+            let create = WorkspaceAction::CreateTab("made".into());
+            apply_action_to_fs(&path, &config, &create).unwrap();
+            assert_eq!(tab(&path, "made").is_open, default_open);
+
+            // A directory made by hand gets it from the watcher
+            let (mut rx, watcher) = watch(&path, options(&config)).await;
+            fs::create_dir(path.join("by-hand")).unwrap();
+            let created = WorkspaceAction::CreateTab("by-hand".into());
+            assert_eq!(
+                wait_for(&mut rx, |action| *action == created).await,
+                Some(created)
+            );
+            assert_eq!(tab(&path, "by-hand").is_open, default_open);
+            watcher.abort();
+        }
+    }
+
+    #[test]
+    fn tab_names_are_cleaned_up() {
+        assert_eq!(normalize_tab_name("  My Tab  "), "My Tab");
+        assert_eq!(normalize_tab_name("My   Tab..."), "My Tab");
+        assert_eq!(normalize_tab_name("a//b\\c"), "a-b-c");
+        assert_eq!(normalize_tab_name(" .. "), "tab");
+    }
+
+    #[test]
+    fn scroll_round_trips() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "a", "https://example.com/", true);
+        let config = WorkspaceConfig::default();
+        assert_eq!(tab(&path, "a").scroll, None);
+
+        let scroll = WorkspaceAction::SetTabScroll("a".into(), 1234.5);
+        apply_action_to_fs(&path, &config, &scroll).unwrap();
+        let read = tab(&path, "a");
+        assert_eq!(read.scroll, Some(1234.5));
+        let json = serde_json::to_value(&read).unwrap();
+        assert_eq!(json["scroll"], serde_json::json!(1234.5));
+
+        for invalid in [-1.0, f64::NAN, f64::INFINITY] {
+            let scroll = WorkspaceAction::SetTabScroll("a".into(), invalid);
+            assert!(apply_action_to_fs(&path, &config, &scroll).is_err());
+        }
+        assert_eq!(tab(&path, "a").scroll, Some(1234.5));
+    }
+
+    #[tokio::test]
+    async fn a_flood_drops_the_oldest_batches_and_counts_them() {
+        let dropped = Arc::new(AtomicU64::new(0));
+        let (tx, mut rx) = action_channel(4, WatcherOverflow::DropOldest, Arc::clone(&dropped));
+        let batch = |i: usize| vec![WorkspaceAction::ChangeTabUrl("a".into(), i.to_string())];
+        for i in 0..10 {
+            // Never waits, however full the channel is
+            tx.send(batch(i)).await.unwrap();
+        }
+        drop(tx);
+
+        let mut received = Vec::new();
+        while let Some(actions) = rx.recv().await {
+            received.push(actions);
+        }
+        assert_eq!(received, (6..10).map(batch).collect::<Vec<_>>());
+        assert_eq!(dropped.load(Ordering::Relaxed), 6);
+    }
+
+    #[tokio::test]
+    async fn a_churning_tab_is_throttled_while_others_flow() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "noisy", "https://example.com/", true);
+        write_tab(&path, "quiet", "https://rust-lang.org/", true);
+        let options = WatchOptions {
+            max_tab_events_per_sec: 5,
+            ..options(&WorkspaceConfig::default())
+        };
+        let (mut rx, watcher) = watch(&path, options).await;
+
+        for n in 0..60 {
+            let url = format!("https://example.com/{}", n);
+            fs::write(path.join("noisy").join("url"), url).unwrap();
+            if n == 30 {
+                fs::write(path.join("quiet").join("url"), "https://crates.io/").unwrap();
+            }
+            tokio::time::sleep(Duration::from_millis(2)).await;
+        }
+        let actions = drain(&mut rx, Duration::from_millis(1500)).await;
+        watcher.abort();
+
+        let urls = |name: &str| -> Vec<String> {
+            actions
+                .iter()
+                .filter_map(|action| match action {
+                    WorkspaceAction::ChangeTabUrl(tab, url) if tab == name => Some(url.clone()),
+                    _ => None,
+                })
+                .collect()
+        };
+        let noisy = urls("noisy");
+        // At most the limit, then the one read once the second is over
+        assert!(noisy.len() <= 6, "{:?}", noisy);
+        assert_eq!(
+            noisy.last().map(String::as_str),
+            Some("https://example.com/59")
+        );
+        assert!(urls("quiet").contains(&"https://crates.io/".to_string()));
+    }
+
+    #[tokio::test]
+    async fn a_blank_tab_sends_no_url_until_it_gets_a_real_one() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "a", "https://example.com/", true);
+        let config = WorkspaceConfig::default();
+        apply_action_to_fs(&path, &config, &WorkspaceAction::CreateTab("new".into())).unwrap();
+        let blank = WorkspaceAction::ChangeTabUrl("new".into(), "about:newtab".into());
+        apply_action_to_fs(&path, &config, &blank).unwrap();
+        assert!(tab(&path, "new").is_blank);
+        let (mut rx, watcher) = watch(&path, options(&config)).await;
+
+        for url in ["about:blank", "", "chrome://newtab/"] {
+            fs::write(path.join("new").join("url"), url).unwrap();
+        }
+        let actions = drain(&mut rx, Duration::from_millis(500)).await;
+        assert!(
+            !actions
+                .iter()
+                .any(|action| matches!(action, WorkspaceAction::ChangeTabUrl(..))),
+            "{:?}",
+            actions
+        );
+        assert!(tab(&path, "new").is_blank);
+
+        fs::write(path.join("new").join("url"), "https://rust-lang.org/").unwrap();
+        let real = wait_for(&mut rx, |action| {
+            matches!(action, WorkspaceAction::ChangeTabUrl(..))
+        })
+        .await;
+        watcher.abort();
+        assert_eq!(
+            real,
+            Some(WorkspaceAction::ChangeTabUrl(
+                "new".into(),
+                "https://rust-lang.org/".into()
+            ))
+        );
+        assert!(!tab(&path, "new").is_blank);
+    }
+
+    #[test]
+    fn equivalent_urls_are_stored_the_same() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "a", "https://example.com/", true);
+        let stored = |config: &WorkspaceConfig, url: &str| {
+            let change = WorkspaceAction::ChangeTabUrl("a".into(), url.into());
+            apply_action_to_fs(&path, config, &change).unwrap();
+            tab(&path, "a").url
+        };
+
+        let canonical = WorkspaceConfig {
+            canonical_urls: true,
+            ..WorkspaceConfig::default()
+        };
+        for url in [
+            "https://Example.com:443",
+            "https://example.com/",
+            "HTTPS://EXAMPLE.COM",
+        ] {
+            assert_eq!(stored(&canonical, url), "https://example.com/");
+        }
+        assert_eq!(stored(&canonical, "not a url"), "not a url");
+        // Off by default, the url is kept as the browser sent it
+        let plain = WorkspaceConfig::default();
+        assert_eq!(
+            stored(&plain, "https://Example.com:443"),
+            "https://Example.com:443"
+        );
+    }
+
+    #[tokio::test]
+    async fn a_released_tab_only_sends_what_changed_while_throttled() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "noisy", "https://example.com/2", true);
+        write_tab(&path, "blank", "", true);
+        fs::write(path.join("blank").join("blank"), "").unwrap();
+        let options = WatchOptions {
+            max_tab_events_per_sec: 2,
+            ..options(&WorkspaceConfig::default())
+        };
+        let mut watch = WorkspaceWatch::start(&path, options).unwrap();
+        let url = |tab: &str, url: &str| WorkspaceAction::ChangeTabUrl(tab.into(), url.into());
+        let notes = WorkspaceAction::SetTabNotes("noisy".into(), "read later".into());
+
+        let sent = vec![
+            url("noisy", "https://example.com/2"),
+            WorkspaceAction::OpenTab("noisy".into()),
+        ];
+        assert_eq!(watch.throttle(sent.clone()), sent);
+        // Back where they started by the time the second is over, except the notes
+        let held = vec![
+            url("noisy", "https://example.com/3"),
+            WorkspaceAction::CloseTab("noisy".into()),
+            WorkspaceAction::OpenTab("noisy".into()),
+            url("noisy", "https://example.com/2"),
+            notes.clone(),
+        ];
+        assert!(watch.throttle(held).is_empty());
+        // A blank tab that churned doesn't send its url
+        let blank = vec![
+            WorkspaceAction::OpenTab("blank".into()),
+            WorkspaceAction::OpenTab("blank".into()),
+            url("blank", "https://example.com/typing"),
+        ];
+        assert_eq!(watch.throttle(blank).len(), 2);
+
+        for rate in watch.rates.values_mut() {
+            rate.since -= RATE_WINDOW * 2;
         }
+        assert_eq!(watch.end_throttling(), [notes]);
+        assert!(watch.rates.values().all(|rate| !rate.throttled));
     }
 }
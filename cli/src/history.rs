@@ -0,0 +1,85 @@
+use crate::config::HISTORY_FILE;
+use crate::model::WorkspaceAction;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fs, io, path::Path};
+
+/// Why a tab was closed or removed
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseReason {
+    // Someone closed it in the browser or on disk
+    User,
+    // It was open longer than it was allowed to be
+    Expired,
+    // It stayed closed long enough to be moved to `.archive`
+    Archived,
+}
+
+/** A tab that was closed or removed, one json object per line in `.history` in the
+ * workspace root. Only ever appended to, the oldest entries come first. */
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    // Milliseconds since the epoch
+    pub at: u64,
+    pub tab: String,
+    // "CloseTab" or "RemoveTab"
+    pub kind: String,
+    pub close_reason: CloseReason,
+}
+
+impl HistoryEntry {
+    pub fn new(tab: &str, kind: &str, close_reason: CloseReason, at: SystemTime) -> HistoryEntry {
+        HistoryEntry {
+            at: at
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            tab: tab.to_string(),
+            kind: kind.to_string(),
+            close_reason,
+        }
+    }
+
+    /// The entry for a close or remove, actions that don't give a reason were the user's
+    pub fn for_action(action: &WorkspaceAction, at: SystemTime) -> Option<HistoryEntry> {
+        let (tab, kind, reason) = match action {
+            WorkspaceAction::CloseTab(tab) => (tab, "CloseTab", CloseReason::User),
+            WorkspaceAction::CloseTabBecause(tab, reason) => (tab, "CloseTab", *reason),
+            WorkspaceAction::RemoveTab(tab) => (tab, "RemoveTab", CloseReason::User),
+            _ => return None,
+        };
+        Some(HistoryEntry::new(tab, kind, reason, at))
+    }
+}
+
+pub fn record(path: &Path, entry: &HistoryEntry) -> io::Result<()> {
+    let mut line =
+        serde_json::to_string(entry).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    line.push('\n');
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path.join(HISTORY_FILE))?
+        .write_all(line.as_bytes())
+}
+
+/// Every entry, a workspace without a history has none. Lines that don't parse are skipped
+pub fn read(path: &Path) -> io::Result<Vec<HistoryEntry>> {
+    let contents = match fs::read_to_string(path.join(HISTORY_FILE)) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+    Ok(contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                eprintln!("Skipping history entry {:?}: {}", line, e);
+                None
+            }
+        })
+        .collect())
+}
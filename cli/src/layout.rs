@@ -0,0 +1,392 @@
+use crate::model::WorkspaceAction;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// The fields of a tab every layout has to store
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct TabState {
+    pub url: String,
+    pub is_open: bool,
+    #[serde(default)]
+    pub incognito: bool,
+}
+
+/// What happened to a file inside a tab directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileChange {
+    Created,
+    Modified,
+    Removed,
+}
+
+/** How a tab is stored in its directory, set per workspace with `layout` in
+ * `.mounttab.json`. The default is the original layout: `url`, `is_open` holding 1 or
+ * 0 and an `incognito` marker file. With `state_file` set those three live together
+ * in a single json file instead. Everything that reads or writes tab files goes
+ * through here. */
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct TabLayout {
+    pub url_file: String,
+    pub is_open_file: String,
+    // What the is_open file holds for open and closed tabs
+    pub open_value: String,
+    pub closed_value: String,
+    // Only exists while the tab opens in a private window
+    pub incognito_file: String,
+    pub meta_file: String,
+    pub notes_file: String,
+    pub favicon_file: String,
+    pub scroll_file: String,
+    pub order_file: String,
+    // Only exists while the tab is a blank new tab page that never had a real url
+    pub blank_file: String,
+    // Keeps the url, is_open and incognito of a tab in this one json file
+    pub state_file: Option<String>,
+    // Creating or touching this file in a tab flips is_open, the file is deleted right
+    // after. None turns it off
+    pub toggle_file: Option<String>,
+}
+
+impl Default for TabLayout {
+    fn default() -> Self {
+        TabLayout {
+            url_file: "url".to_owned(),
+            is_open_file: "is_open".to_owned(),
+            open_value: "1".to_owned(),
+            closed_value: "0".to_owned(),
+            incognito_file: "incognito".to_owned(),
+            meta_file: "meta.json".to_owned(),
+            notes_file: "notes.md".to_owned(),
+            favicon_file: "favicon".to_owned(),
+            scroll_file: "scroll".to_owned(),
+            blank_file: "blank".to_owned(),
+            order_file: "order".to_owned(),
+            state_file: None,
+            toggle_file: Some("toggle".to_owned()),
+        }
+    }
+}
+
+impl TabLayout {
+    pub fn read_state(&self, tab_dir: &Path) -> io::Result<TabState> {
+        if let Some(state_file) = &self.state_file {
+            let contents = fs::read_to_string(tab_dir.join(state_file))?;
+            return serde_json::from_str(&contents)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+        }
+        // A tab that never got a url has an empty one
+        let url = match fs::read_to_string(tab_dir.join(&self.url_file)) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            url => url?,
+        };
+        Ok(TabState {
+            url,
+            is_open: fs::read_to_string(tab_dir.join(&self.is_open_file))?.trim()
+                == self.open_value,
+            incognito: tab_dir.join(&self.incognito_file).exists(),
+        })
+    }
+
+    /// Gives a tab directory without an open state one, what's already there is kept
+    pub fn init_tab(&self, tab_dir: &Path, default_open: bool) -> io::Result<()> {
+        if self.is_open_path(tab_dir).exists() {
+            return Ok(());
+        }
+        self.write_is_open(tab_dir, default_open)
+    }
+
+    pub fn write_is_open(&self, tab_dir: &Path, is_open: bool) -> io::Result<()> {
+        if self.state_file.is_some() {
+            return self.update_state(tab_dir, |state| state.is_open = is_open);
+        }
+        let value = match is_open {
+            true => &self.open_value,
+            false => &self.closed_value,
+        };
+        fs::write(tab_dir.join(&self.is_open_file), value)
+    }
+
+    pub fn write_url(&self, tab_dir: &Path, url: &str) -> io::Result<()> {
+        if self.state_file.is_some() {
+            return self.update_state(tab_dir, |state| state.url = url.to_owned());
+        }
+        fs::write(tab_dir.join(&self.url_file), url)
+    }
+
+    pub fn is_blank(&self, tab_dir: &Path) -> bool {
+        tab_dir.join(&self.blank_file).exists()
+    }
+
+    pub fn write_blank(&self, tab_dir: &Path, blank: bool) -> io::Result<()> {
+        let blank_file = tab_dir.join(&self.blank_file);
+        if blank {
+            fs::write(blank_file, "")
+        } else if blank_file.exists() {
+            fs::remove_file(blank_file)
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn write_incognito(&self, tab_dir: &Path, incognito: bool) -> io::Result<()> {
+        if self.state_file.is_some() {
+            return self.update_state(tab_dir, |state| state.incognito = incognito);
+        }
+        let incognito_file = tab_dir.join(&self.incognito_file);
+        if incognito {
+            fs::write(incognito_file, "")
+        } else if incognito_file.exists() {
+            fs::remove_file(incognito_file)
+        } else {
+            Ok(())
+        }
+    }
+
+    // A missing or broken state file starts over from an empty tab
+    fn update_state(&self, tab_dir: &Path, change: impl FnOnce(&mut TabState)) -> io::Result<()> {
+        let mut state = self.read_state(tab_dir).unwrap_or_default();
+        change(&mut state);
+        let contents = serde_json::to_string_pretty(&state)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(self.is_open_path(tab_dir), contents)
+    }
+
+    /// The file that changes when the tab is opened or closed
+    pub fn is_open_path(&self, tab_dir: &Path) -> PathBuf {
+        match &self.state_file {
+            Some(state_file) => tab_dir.join(state_file),
+            None => tab_dir.join(&self.is_open_file),
+        }
+    }
+
+    // Only the event that still finds the trigger toggles, deleting it first makes the
+    // create, touch and delete events of the same trigger count once
+    fn toggle(&self, tab: &str, trigger: &Path) {
+        let Some(dir) = trigger.parent() else {
+            return;
+        };
+        if fs::remove_file(trigger).is_err() {
+            return;
+        }
+        let toggled = self
+            .read_state(dir)
+            .and_then(|state| self.write_is_open(dir, !state.is_open));
+        match toggled {
+            Ok(()) => println!("Toggled tab {}", tab),
+            Err(e) => println!("Error toggling tab {}: {}", tab, e),
+        }
+    }
+
+    /** The actions a change to `file_name` in the tab stands for. For the json layout
+     * `snapshots` holds the last state seen of every tab so only the fields that
+     * changed turn into actions. */
+    pub fn file_changed(
+        &self,
+        tab: &str,
+        file_name: &OsStr,
+        file_path: &Path,
+        change: FileChange,
+        snapshots: &mut HashMap<String, TabState>,
+    ) -> Vec<WorkspaceAction> {
+        let tab = tab.to_string();
+
+        if self.toggle_file.as_deref().map(OsStr::new) == Some(file_name) {
+            if change != FileChange::Removed {
+                self.toggle(&tab, file_path);
+            }
+            // The write to is_open comes back as its own event
+            return vec![];
+        }
+
+        if let Some(state_file) = &self.state_file {
+            if file_name != OsStr::new(state_file) || change == FileChange::Removed {
+                return vec![];
+            }
+            let Some(dir) = file_path.parent() else {
+                return vec![];
+            };
+            let Ok(state) = self.read_state(dir) else {
+                println!("Ignoring unreadable state file of tab {}", tab);
+                return vec![];
+            };
+            let old = snapshots
+                .insert(tab.clone(), state.clone())
+                .unwrap_or_default();
+            let mut actions = Vec::new();
+            if old.url.trim() != state.url.trim() {
+                actions.push(WorkspaceAction::ChangeTabUrl(
+                    tab.clone(),
+                    state.url.trim().to_string(),
+                ));
+            }
+            if old.is_open != state.is_open {
+                actions.push(match state.is_open {
+                    true => WorkspaceAction::OpenTab(tab.clone()),
+                    false => WorkspaceAction::CloseTab(tab.clone()),
+                });
+            }
+            if old.incognito != state.incognito {
+                actions.push(WorkspaceAction::SetTabIncognito(tab, state.incognito));
+            }
+            return actions;
+        }
+
+        match change {
+            FileChange::Created if file_name == OsStr::new(&self.incognito_file) => {
+                vec![WorkspaceAction::SetTabIncognito(tab, true)]
+            }
+            FileChange::Removed if file_name == OsStr::new(&self.incognito_file) => {
+                vec![WorkspaceAction::SetTabIncognito(tab, false)]
+            }
+            FileChange::Modified if file_name == OsStr::new(&self.is_open_file) => {
+                let Ok(is_open) = fs::read_to_string(file_path) else {
+                    return vec![];
+                };
+                let is_open = is_open.trim();
+                println!("Is open contents: {}", is_open);
+                if is_open == self.open_value {
+                    vec![WorkspaceAction::OpenTab(tab)]
+                } else if is_open == self.closed_value {
+                    vec![WorkspaceAction::CloseTab(tab)]
+                } else {
+                    vec![]
+                }
+            }
+            FileChange::Modified if file_name == OsStr::new(&self.url_file) => {
+                let Ok(url) = fs::read_to_string(file_path) else {
+                    return vec![];
+                };
+                // Blank tabs only change once they get a real url, then they stop being blank
+                if let Some(dir) = file_path.parent().filter(|dir| self.is_blank(dir)) {
+                    if is_blank_url(&url) {
+                        return vec![];
+                    }
+                    if let Err(e) = self.write_blank(dir, false) {
+                        println!("Error promoting blank tab {}: {:?}", tab, e);
+                    }
+                }
+                vec![WorkspaceAction::ChangeTabUrl(tab, url.trim().to_string())]
+            }
+            _ => {
+                println!("No action found");
+                vec![]
+            }
+        }
+    }
+}
+
+/// Urls of an empty new tab page, none of them is worth keeping
+pub fn is_blank_url(url: &str) -> bool {
+    let url = url.trim();
+    url.is_empty()
+        || url == "about:blank"
+        || url == "about:newtab"
+        || url
+            .strip_suffix('/')
+            .map_or(url, |url| url)
+            .ends_with("://newtab")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::WorkspaceConfig;
+    use crate::file_watcher::apply_action_to_fs;
+    use crate::model::Workspace;
+    use crate::test_util::TempDir;
+
+    // Writes a tab through the layout and reads the workspace back
+    fn round_trip(dir: &TempDir, layout: TabLayout) -> (PathBuf, Workspace) {
+        let path = dir.dir("ws");
+        let config = WorkspaceConfig::update(&path, |config| config.layout = layout).unwrap();
+        for action in [
+            WorkspaceAction::CreateTab("a".into()),
+            WorkspaceAction::ChangeTabUrl("a".into(), "https://example.com/".into()),
+            WorkspaceAction::OpenTab("a".into()),
+            WorkspaceAction::SetTabIncognito("a".into(), true),
+            WorkspaceAction::CreateTab("b".into()),
+            WorkspaceAction::ChangeTabUrl("b".into(), "https://b.example/".into()),
+            WorkspaceAction::CloseTab("b".into()),
+        ] {
+            apply_action_to_fs(&path, &config, &action).unwrap();
+        }
+        let workspace = Workspace::new_from_fs(&path);
+        (path, workspace)
+    }
+
+    fn assert_tabs(workspace: &Workspace) {
+        let tabs: Vec<(&str, &str, bool, bool)> = workspace
+            .tabs
+            .iter()
+            .map(|tab| {
+                (
+                    tab.name.as_str(),
+                    tab.url.as_str(),
+                    tab.is_open,
+                    tab.incognito,
+                )
+            })
+            .collect();
+        assert_eq!(
+            tabs,
+            vec![
+                ("a", "https://example.com/", true, true),
+                ("b", "https://b.example/", false, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn renamed_files_round_trip() {
+        let dir = TempDir::new();
+        let (path, workspace) = round_trip(
+            &dir,
+            TabLayout {
+                url_file: "url.txt".into(),
+                is_open_file: "open".into(),
+                open_value: "yes".into(),
+                closed_value: "no".into(),
+                ..TabLayout::default()
+            },
+        );
+        assert_tabs(&workspace);
+        let tab = path.join("a");
+        assert_eq!(
+            fs::read_to_string(tab.join("url.txt")).unwrap(),
+            "https://example.com/"
+        );
+        assert_eq!(fs::read_to_string(tab.join("open")).unwrap(), "yes");
+        assert!(!tab.join("url").exists());
+    }
+
+    #[test]
+    fn a_state_file_round_trips() {
+        let dir = TempDir::new();
+        let (path, workspace) = round_trip(
+            &dir,
+            TabLayout {
+                state_file: Some("tab.json".into()),
+                ..TabLayout::default()
+            },
+        );
+        assert_tabs(&workspace);
+        let tab = path.join("a");
+        let state: TabState =
+            serde_json::from_str(&fs::read_to_string(tab.join("tab.json")).unwrap()).unwrap();
+        assert_eq!(
+            state,
+            TabState {
+                url: "https://example.com/".into(),
+                is_open: true,
+                incognito: true,
+            }
+        );
+        assert!(!tab.join("url").exists() && !tab.join("is_open").exists());
+    }
+}
@@ -1,12 +1,49 @@
-use crate::daemon::start_daemon;
+use crate::commands::Command;
+use crate::config::ManagerConfig;
 
+pub mod clock;
+mod commands;
+pub mod config;
 pub mod daemon;
+#[cfg(feature = "fault-injection")]
+pub mod faults;
 pub mod file_watcher;
+pub mod history;
+pub mod layout;
+pub mod manifest;
 pub mod model;
+pub mod protocol;
+pub mod reconcile;
+pub mod schema;
+pub mod subscriptions;
+#[cfg(test)]
+mod test_util;
+pub mod transaction;
 
-#[tokio::main]
-async fn main() {
-    println!("⛰️ Mount Tab");
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let command = match Command::parse(&args) {
+        Ok(command) => command,
+        Err(usage) => {
+            eprintln!("{}", usage);
+            std::process::exit(2);
+        }
+    };
 
-    let _ = start_daemon().await;
+    let mut builder = match ManagerConfig::load_default().single_threaded {
+        true => tokio::runtime::Builder::new_current_thread(),
+        false => tokio::runtime::Builder::new_multi_thread(),
+    };
+    let runtime = match builder.enable_all().build() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            eprintln!("Error starting the runtime: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if let Err(e) = runtime.block_on(command.run()) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,98 @@
+use crate::config::MANIFEST_FILE;
+use crate::file_watcher::ordered_tab_names;
+use crate::layout::TabLayout;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::collections::BTreeMap;
+use std::{fs, io, path::Path};
+
+/** SHA-1 of every file in every tab, keyed by the path relative to the workspace root
+ * with `/` between the parts. Kept in `.manifest` in the workspace root so corruption
+ * from sync tools or the disk can be found later. */
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    pub files: BTreeMap<String, String>,
+}
+
+/// What `verify` found, every list is sorted. Nothing in any of them means all is well
+#[derive(Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct ManifestReport {
+    // The contents changed since the manifest was written
+    pub mismatched: Vec<String>,
+    pub missing: Vec<String>,
+    // Files that aren't in the manifest
+    pub added: Vec<String>,
+}
+
+impl ManifestReport {
+    pub fn is_clean(&self) -> bool {
+        self.mismatched.is_empty() && self.missing.is_empty() && self.added.is_empty()
+    }
+}
+
+impl Manifest {
+    /// Hashes what's in the workspace right now
+    pub fn scan(path: &Path, layout: &TabLayout) -> io::Result<Manifest> {
+        let mut files = BTreeMap::new();
+        for tab in ordered_tab_names(path, layout)? {
+            hash_dir(&path.join(&tab), &tab, layout, &mut files)?;
+        }
+        Ok(Manifest { files })
+    }
+
+    pub fn read(path: &Path) -> io::Result<Manifest> {
+        let contents = fs::read_to_string(path.join(MANIFEST_FILE))?;
+        serde_json::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(path.join(MANIFEST_FILE), contents)
+    }
+
+    /// Compares against the workspace as it is now
+    pub fn verify(&self, path: &Path, layout: &TabLayout) -> io::Result<ManifestReport> {
+        let now = Manifest::scan(path, layout)?;
+        let mut report = ManifestReport::default();
+        for (file, sum) in &self.files {
+            match now.files.get(file) {
+                None => report.missing.push(file.clone()),
+                Some(current) if current != sum => report.mismatched.push(file.clone()),
+                Some(_) => {}
+            }
+        }
+        report.added = now
+            .files
+            .keys()
+            .filter(|file| !self.files.contains_key(*file))
+            .cloned()
+            .collect();
+        Ok(report)
+    }
+}
+
+// The toggle file only lives for a moment, it's never part of the manifest
+fn hash_dir(
+    dir: &Path,
+    prefix: &str,
+    layout: &TabLayout,
+    files: &mut BTreeMap<String, String>,
+) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if layout.toggle_file.as_deref() == Some(name.as_str()) {
+            continue;
+        }
+        let relative = format!("{}/{}", prefix, name);
+        if entry.file_type()?.is_dir() {
+            hash_dir(&entry.path(), &relative, layout, files)?;
+            continue;
+        }
+        let digest = Sha1::digest(fs::read(entry.path())?);
+        let sum: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+        files.insert(relative, sum);
+    }
+    Ok(())
+}
@@ -1,18 +1,119 @@
+use crate::clock::SharedClock;
+use crate::config::{
+    ManagerConfig, OverflowPolicy, WorkspaceConfig, ALIVE_FILE, START_HOOK_FILE, WINDOWS_FILE,
+};
 use crate::file_watcher;
-use crate::file_watcher::apply_action_to_fs;
+use crate::file_watcher::{
+    apply_action_to_fs, apply_action_to_fs_batched, archive_tab, order_collisions,
+    repair_tab_order, tab_closed_since, tab_open_since, ActionReceiver, RawFsEvent, SyncBatch,
+    WatchCommand, WatchOptions, WatchedPaths,
+};
+use crate::history::{self, CloseReason, HistoryEntry};
+use crate::layout::is_blank_url;
+use crate::manifest::{Manifest, ManifestReport};
+use crate::protocol::{ProtocolVersion, PROTOCOL_VERSION};
+use crate::reconcile::{ActionSource, Admission, Reconciler};
+use crate::subscriptions::{Subscriptions, SUBSCRIPTIONS_FILE};
+use crate::transaction::FsTransaction;
+use futures_util::Stream;
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
-use tokio::sync::{mpsc, RwLock};
-use tokio_stream::wrappers::UnboundedReceiverStream;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, oneshot, watch, Mutex, Notify, RwLock};
+use tokio::task::AbortHandle;
+use tokio_stream::wrappers::{ReceiverStream, UnboundedReceiverStream};
 use tokio_stream::StreamExt;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub enum ToBrowserMessage {
-    AllWorkspaces(Vec<Workspace>),
+    AllWorkspaces(Vec<Arc<Workspace>>),
     // Only send to the browser when it is "connected" to a workspace
     WorkspaceAction(WorkspaceAction),
     LoadWorkspace(ApiWorkspace),
+    // A single field of a tab changed, the browser patches it in place
+    TabFieldChanged {
+        tab: String,
+        field: TabField,
+        value: serde_json::Value,
+    },
+    Status(BrowserStatus),
+    // Actions that belong together, the browser applies them in one go
+    Transaction {
+        id: usize,
+        actions: Vec<WorkspaceAction>,
+    },
+    // Reply to a Hello with the protocol version this connection uses from now on
+    Welcome {
+        protocol_version: u32,
+        // Send this back in the next Hello to get the same workspaces started again
+        token: String,
+    },
+    // Session name to the ids of its workspaces
+    Sessions(BTreeMap<String, Vec<String>>),
+    // A WorkspaceAction from this browser couldn't be applied
+    ActionError {
+        workspace_id: String,
+        action: WorkspaceAction,
+        error: String,
+    },
+    // Free form text for the extension to show, like asking to reload after an update
+    Notice(String),
+    // The workspace's watcher stopped and changes on disk won't come through anymore
+    WatcherError {
+        workspace_id: String,
+        error: String,
+    },
+    // Reply to ChangesSince. No actions means the browser missed more than the daemon
+    // remembers and has to start the workspace again
+    Changes {
+        workspace_id: String,
+        seq: u64,
+        actions: Option<Vec<WorkspaceAction>>,
+    },
+}
+
+static NEXT_TRANSACTION_ID: AtomicUsize = AtomicUsize::new(1);
+
+impl ToBrowserMessage {
+    /// One action goes out on its own, more than one as a transaction
+    pub fn from_actions(mut actions: Vec<WorkspaceAction>) -> ToBrowserMessage {
+        if actions.len() == 1 {
+            return ToBrowserMessage::from_action(actions.remove(0));
+        }
+        ToBrowserMessage::Transaction {
+            id: NEXT_TRANSACTION_ID.fetch_add(1, Ordering::Relaxed),
+            actions,
+        }
+    }
+
+    /// Actions that only set one field of a tab go out as a field change
+    pub fn from_action(action: WorkspaceAction) -> ToBrowserMessage {
+        match action {
+            WorkspaceAction::ChangeTabUrl(tab, url) => ToBrowserMessage::TabFieldChanged {
+                tab,
+                field: TabField::Url,
+                value: url.into(),
+            },
+            WorkspaceAction::SetTabIncognito(tab, incognito) => ToBrowserMessage::TabFieldChanged {
+                tab,
+                field: TabField::Incognito,
+                value: incognito.into(),
+            },
+            action => ToBrowserMessage::WorkspaceAction(action),
+        }
+    }
+}
+
+/// The fields of a `Tab` that can be sent on their own, named like the `Tab` fields
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TabField {
+    Url,
+    Incognito,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -20,35 +121,386 @@ pub enum FromBrowserMessage {
     // User wants to start sending actions from this worksapce to this browser.
     StartWorkspace(String),
     WorkspaceAction(String, WorkspaceAction),
+    AppAction(AppAction),
+    GetStatus,
+    // First thing a browser should send, browsers that don't get protocol version 1
+    Hello(Hello),
+    // Only send workspace actions of these kinds, like "OpenTab". None sends all of them
+    SetActionFilter(Option<Vec<String>>),
+    // Workspace id and the last seq the browser saw, asks for what it missed
+    ChangesSince(String, u64),
+    // Workspace id, action, what the tab has to look like on disk for the action to be
+    // applied. A tab that changed since the browser last saw it gets an ActionError
+    WorkspaceActionIf(String, WorkspaceAction, TabPrecondition),
+    // Workspace id, the browser is done with its LoadWorkspace and wants the actions
+    // that came in meanwhile
+    LoadAcknowledged(String),
+}
+
+impl FromBrowserMessage {
+    /// Ids of the workspaces the message acts on
+    fn workspace_ids(&self) -> Vec<&str> {
+        match self {
+            FromBrowserMessage::StartWorkspace(id)
+            | FromBrowserMessage::WorkspaceAction(id, _)
+            | FromBrowserMessage::ChangesSince(id, _)
+            | FromBrowserMessage::WorkspaceActionIf(id, _, _)
+            | FromBrowserMessage::LoadAcknowledged(id) => vec![id],
+            FromBrowserMessage::AppAction(action) => match action {
+                AppAction::WorkspaceAction(id, _)
+                | AppAction::CloseWorkspace(id)
+                | AppAction::ReorderWorkspace(id, _)
+                | AppAction::ReloadWorkspace(id)
+                | AppAction::PinWatchedTabs(id, _)
+                | AppAction::SetWindowGeometry(id, _, _) => vec![id],
+                AppAction::MoveTab(from, _, to) | AppAction::MergeWorkspaces(from, to) => {
+                    vec![from, to]
+                }
+                AppAction::SaveSession(_, ids) => ids.iter().map(String::as_str).collect(),
+                // Sessions are checked workspace by workspace when they're started
+                AppAction::OpenWorkspace(_)
+                | AppAction::DeleteSession(_)
+                | AppAction::OpenSession(_)
+                | AppAction::CloseSession(_) => vec![],
+            },
+            FromBrowserMessage::GetStatus
+            | FromBrowserMessage::Hello(_)
+            | FromBrowserMessage::SetActionFilter(_) => vec![],
+        }
+    }
+}
+
+/// The values a tab is expected to have right now, fields left out aren't checked
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct TabPrecondition {
+    pub url: Option<String>,
+    pub is_open: Option<bool>,
+    pub incognito: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Hello {
+    pub protocol_version: u32,
+    // Token from an earlier Welcome, used to restore the workspaces it had started
+    #[serde(default)]
+    pub token: Option<String>,
+    // Who is connecting, browsers only see the workspaces this client owns and the
+    // shared ones
+    #[serde(default)]
+    pub client: Option<String>,
+}
+
+/// Something that happened to a browser connection, see `connection_events`
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    Connected,
+    // The protocol version the browser asked for and the one it got
+    HelloReceived { asked: u32, using: u32 },
+    WorkspaceStarted(String),
+    Disconnected,
+    // A message from the browser that couldn't be read or handled
+    Error(String),
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionLifecycle {
+    pub browser_id: usize,
+    pub event: ConnectionEvent,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BrowserStatus {
+    pub browser_id: usize,
+    // Workspace actions are only sent to a browser for the workspaces it started
+    pub connected_workspaces: Vec<String>,
 }
 
 #[derive(Clone, Debug)]
 pub struct Browser {
     pub id: usize,
     pub tx: mpsc::Sender<ToBrowserMessage>,
+    // Negotiated protocol version, read by the socket task when encoding
+    pub protocol: Arc<AtomicU32>,
+    // What happens when the browser doesn't keep up with its queue
+    pub overflow: OverflowPolicy,
+    // Notified to drop the connection of a browser that stopped reading
+    pub kicked: Arc<Notify>,
+}
+
+impl Browser {
+    /** Queues the message without waiting, so a browser that stopped reading can't hold
+     * up the others. When its queue is full the overflow policy decides between
+     * dropping the message and dropping the browser. Returns whether it was queued. */
+    pub fn send(&self, message: ToBrowserMessage) -> bool {
+        match self.tx.try_send(message) {
+            Ok(()) => true,
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                match self.overflow {
+                    OverflowPolicy::DropNewest => {
+                        eprintln!("Browser {} is falling behind, dropping a message", self.id)
+                    }
+                    OverflowPolicy::Disconnect => {
+                        eprintln!("Browser {} is falling behind, disconnecting it", self.id);
+                        self.kicked.notify_one();
+                    }
+                }
+                false
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => {
+                eprintln!("Error sending to browser {}: it's gone", self.id);
+                false
+            }
+        }
+    }
+
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        ProtocolVersion::negotiate(self.protocol.load(Ordering::Acquire))
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-enum AppAction {
+pub enum AppAction {
     OpenWorkspace(String),
     WorkspaceAction(String, WorkspaceAction),
     CloseWorkspace(String),
+    // Workspace id, new position in the list
+    ReorderWorkspace(String, usize),
+    // Read the workspace from disk again and send it to the browsers that started it
+    ReloadWorkspace(String),
+    // Workspace id, the only tabs to watch for edits or None to watch all of them
+    PinWatchedTabs(String, Option<Vec<String>>),
+    // Session name, the ids of its workspaces. Replaces a session with the same name
+    SaveSession(String, Vec<String>),
+    DeleteSession(String),
+    // Start or stop every workspace of the session for this browser
+    OpenSession(String),
+    CloseSession(String),
+    // Source workspace id, tab name, destination workspace id
+    MoveTab(String, String, String),
+    // Moves every tab of the first workspace into the second
+    MergeWorkspaces(String, String),
+    // Workspace id, window key, where the window is now or None when it was closed
+    SetWindowGeometry(String, String, Option<WindowGeometry>),
+}
+
+/// Shared workspaces are visible to everyone, owned ones only to their owner
+fn visible_to(workspace: &Workspace, client: Option<&str>) -> bool {
+    match &workspace.config.owner {
+        None => true,
+        Some(owner) => client == Some(owner.as_str()),
+    }
 }
 
 /** A workspace is a directory on the computer that contains all the tabs */
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Workspace {
+    // Stable, kept in the workspace config. Never derived from the path
     pub id: String,
     pub name: String,
+    // Where the workspace is right now, changes when it's relocated
     pub path: String,
     pub tabs: Vec<Tab>,
+    // Entries in the workspace that look like tabs but aren't directories
+    #[serde(default)]
+    pub malformed_tabs: Vec<String>,
+    // Tabs that couldn't be read and why, they're not in `tabs`
+    #[serde(default)]
+    pub tab_errors: BTreeMap<String, String>,
+    #[serde(skip)]
+    pub config: WorkspaceConfig,
+    // The tabs were dropped from memory and `tabs` is empty, they get read from disk on
+    // the next access. Sent so browsers don't take the workspace for an empty one
+    #[serde(default)]
+    pub evicted: bool,
+}
+
+impl Workspace {
+    pub fn open_count(&self) -> usize {
+        self.tabs.iter().filter(|tab| tab.is_open).count()
+    }
+
+    /// Applies an action that was written to disk to the tabs kept in memory
+    pub fn apply_action(&mut self, action: &WorkspaceAction) {
+        match action {
+            WorkspaceAction::OpenTab(tab) => self.tab_mut(tab).is_open = true,
+            WorkspaceAction::CloseTab(tab) | WorkspaceAction::CloseTabBecause(tab, _) => {
+                self.tab_mut(tab).is_open = false
+            }
+            WorkspaceAction::ChangeTabUrl(tab, url) => {
+                let url = self.config.stored_url(url);
+                let tab = self.tab_mut(tab);
+                // A blank tab stays blank until it gets a real url
+                if !(tab.is_blank && is_blank_url(&url)) {
+                    tab.url = url;
+                    tab.is_blank = false;
+                }
+            }
+            WorkspaceAction::CreateTab(tab) => {
+                let default_open = self.config.default_open;
+                let tab = self.tab_mut(tab);
+                tab.is_open = default_open;
+                tab.url = String::new();
+                tab.is_blank = true;
+            }
+            WorkspaceAction::RemoveTab(tab) => self.tabs.retain(|t| t.name != *tab),
+            WorkspaceAction::SetTabIncognito(tab, incognito) => {
+                self.tab_mut(tab).incognito = *incognito
+            }
+            WorkspaceAction::SetTabMeta(tab, meta) => self.tab_mut(tab).meta = Some(meta.clone()),
+            WorkspaceAction::SetTabNotes(tab, notes) => {
+                self.tab_mut(tab).notes = Some(notes.clone())
+            }
+            WorkspaceAction::SetTabFavicon(tab, favicon) => {
+                self.tab_mut(tab).favicon = Some(favicon.clone())
+            }
+            WorkspaceAction::SetTabScroll(tab, scroll) => self.tab_mut(tab).scroll = Some(*scroll),
+            WorkspaceAction::ReorderTab(tab, index) => {
+                if let Some(from) = self.tabs.iter().position(|t| t.name == *tab) {
+                    let tab = self.tabs.remove(from);
+                    let index = (*index).min(self.tabs.len());
+                    self.tabs.insert(index, tab);
+                }
+            }
+            WorkspaceAction::RenameTab(from, to) => {
+                if self.tabs.iter().any(|t| t.name == *from) {
+                    self.tabs.retain(|t| t.name != *to);
+                }
+                if let Some(tab) = self.tabs.iter_mut().find(|t| t.name == *from) {
+                    tab.name = to.clone();
+                }
+            }
+            // Only the MRU file changes
+            WorkspaceAction::ActivateTab(_) => {}
+        }
+    }
+
+    // Writing to a tab that doesn't exist creates its directory
+    fn tab_mut(&mut self, name: &str) -> &mut Tab {
+        let index = match self.tabs.iter().position(|t| t.name == name) {
+            Some(index) => index,
+            None => {
+                self.tabs.push(Tab {
+                    name: name.to_string(),
+                    ..Tab::default()
+                });
+                self.tabs.len() - 1
+            }
+        };
+        &mut self.tabs[index]
+    }
+
+    /// Everything that differs between these tabs and `other`'s
+    pub fn diff(&self, other: &Workspace) -> WorkspaceDiff {
+        let mut diff = WorkspaceDiff {
+            workspace_id: self.id.clone(),
+            ..WorkspaceDiff::default()
+        };
+        for tab in &self.tabs {
+            match other.tabs.iter().find(|t| t.name == tab.name) {
+                None => diff.only_in_memory.push(tab.name.clone()),
+                Some(on_disk) => {
+                    let fields = tab.changed_fields(on_disk);
+                    if !fields.is_empty() {
+                        diff.changed.push(TabDrift {
+                            tab: tab.name.clone(),
+                            fields,
+                        });
+                    }
+                }
+            }
+        }
+        for tab in &other.tabs {
+            if !self.tabs.iter().any(|t| t.name == tab.name) {
+                diff.only_on_disk.push(tab.name.clone());
+            }
+        }
+        diff
+    }
+}
+
+/// How the tabs kept in memory differ from what's on disk
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct WorkspaceDiff {
+    pub workspace_id: String,
+    pub only_in_memory: Vec<String>,
+    pub only_on_disk: Vec<String>,
+    pub changed: Vec<TabDrift>,
+}
+
+impl WorkspaceDiff {
+    pub fn is_empty(&self) -> bool {
+        self.only_in_memory.is_empty() && self.only_on_disk.is_empty() && self.changed.is_empty()
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct TabDrift {
+    pub tab: String,
+    // Names of the `Tab` fields that differ
+    pub fields: Vec<&'static str>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct ApiWorkspace {
     pub id: String,
     pub name: String,
     pub tabs: Vec<Tab>,
+    // Only there once a browser saved its windows
+    #[serde(default)]
+    pub windows: Option<WindowLayout>,
+}
+
+/// Where a browser window is on screen, in pixels
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WindowGeometry {
+    pub left: i32,
+    pub top: i32,
+    pub width: u32,
+    pub height: u32,
+    // normal, minimized, maximized or fullscreen, as the browser calls it
+    #[serde(default)]
+    pub state: Option<String>,
+}
+
+/** What `.windows.json` in the workspace root holds, the browser's own key for every
+ * window to where it was. Files with a newer version than this daemon knows are left
+ * alone and read as no layout. */
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct WindowLayout {
+    pub version: u32,
+    pub windows: BTreeMap<String, WindowGeometry>,
+}
+
+impl WindowLayout {
+    pub const VERSION: u32 = 1;
+
+    pub fn read(workspace_path: &Path) -> Option<WindowLayout> {
+        let file = workspace_path.join(WINDOWS_FILE);
+        let contents = std::fs::read_to_string(&file).ok()?;
+        match serde_json::from_str::<WindowLayout>(&contents) {
+            Ok(layout) if layout.version <= WindowLayout::VERSION => Some(layout),
+            Ok(layout) => {
+                eprintln!(
+                    "Ignoring {}, version {} is newer than {}",
+                    file.display(),
+                    layout.version,
+                    WindowLayout::VERSION
+                );
+                None
+            }
+            Err(e) => {
+                eprintln!("Error reading {}: {}", file.display(), e);
+                None
+            }
+        }
+    }
+
+    pub fn write(&self, workspace_path: &Path) -> io::Result<()> {
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        std::fs::write(workspace_path.join(WINDOWS_FILE), contents)
+    }
 }
 
 /** Each tab is a directory of config
@@ -57,38 +509,652 @@ pub struct ApiWorkspace {
  * - $(tab.name)
  *  - url.txt: contians the url string
  *  - is_open: contains true or false
+ *  - incognito: only there when the tab should open in a private window
+ *  - meta.json: any json a client wants to keep with the tab
+ *  - notes.md: free form notes
+ *  - favicon: the favicon url, usually a data url
+ *  - scroll: how far down the page was scrolled, in pixels
+ *  - blank: only there while the tab is a new tab page that never had a real url
+ *  - order: position of the tab in the workspace, tabs without one go last by name
+ *  - toggle: touch it to open or close the tab, it's deleted right away
  * */
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Default)]
 pub struct Tab {
     // The name should be unique across all tabs functions as an id
     pub name: String,
     pub url: String,
     pub is_open: bool,
+    #[serde(default)]
+    pub incognito: bool,
+    #[serde(default)]
+    pub meta: Option<serde_json::Value>,
+    #[serde(default)]
+    pub notes: Option<String>,
+    #[serde(default)]
+    pub favicon: Option<String>,
+    #[serde(default)]
+    pub scroll: Option<f64>,
+    // A new tab page, its url is empty until ChangeTabUrl gives it a real one
+    #[serde(default)]
+    pub is_blank: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub enum WorkspaceAction {
     OpenTab(String),
     CloseTab(String),
+    // A close with the reason kept in the workspace history, a plain CloseTab is the user's
+    CloseTabBecause(String, CloseReason),
     // Tab name , Tab url
     ChangeTabUrl(String, String),
     CreateTab(String),
     RemoveTab(String),
+    // Tab name, whether it opens in a private window
+    SetTabIncognito(String, bool),
+    // Tab name, contents. These are capped by the workspace config
+    SetTabMeta(String, serde_json::Value),
+    SetTabNotes(String, String),
+    SetTabFavicon(String, String),
+    // Tab name, pixels from the top of the page
+    SetTabScroll(String, f64),
+    // Tab name, new position among the tabs
+    ReorderTab(String, usize),
+    // Old tab name, new tab name. The tab's directory was renamed
+    RenameTab(String, String),
+    // The tab got focus in the browser, moves it to the front of the workspace's MRU list
+    ActivateTab(String),
+}
+
+impl Tab {
+    fn changed_fields(&self, other: &Tab) -> Vec<&'static str> {
+        let mut fields = Vec::new();
+        if self.url != other.url {
+            fields.push("url");
+        }
+        if self.is_open != other.is_open {
+            fields.push("is_open");
+        }
+        if self.incognito != other.incognito {
+            fields.push("incognito");
+        }
+        if self.meta != other.meta {
+            fields.push("meta");
+        }
+        if self.notes != other.notes {
+            fields.push("notes");
+        }
+        if self.favicon != other.favicon {
+            fields.push("favicon");
+        }
+        if self.scroll != other.scroll {
+            fields.push("scroll");
+        }
+        if self.is_blank != other.is_blank {
+            fields.push("is_blank");
+        }
+        fields
+    }
+}
+
+impl WorkspaceAction {
+    /// Every kind of action, what `kind` returns
+    pub const KINDS: &'static [&'static str] = &[
+        "OpenTab",
+        "CloseTab",
+        "CloseTabBecause",
+        "ChangeTabUrl",
+        "CreateTab",
+        "RemoveTab",
+        "SetTabIncognito",
+        "SetTabMeta",
+        "SetTabNotes",
+        "SetTabFavicon",
+        "SetTabScroll",
+        "ReorderTab",
+        "RenameTab",
+        "ActivateTab",
+    ];
+
+    /// The variant name, used to filter the actions a browser gets
+    pub fn kind(&self) -> &'static str {
+        match self {
+            WorkspaceAction::OpenTab(_) => "OpenTab",
+            WorkspaceAction::CloseTab(_) => "CloseTab",
+            WorkspaceAction::CloseTabBecause(..) => "CloseTabBecause",
+            WorkspaceAction::ChangeTabUrl(..) => "ChangeTabUrl",
+            WorkspaceAction::CreateTab(_) => "CreateTab",
+            WorkspaceAction::RemoveTab(_) => "RemoveTab",
+            WorkspaceAction::SetTabIncognito(..) => "SetTabIncognito",
+            WorkspaceAction::SetTabMeta(..) => "SetTabMeta",
+            WorkspaceAction::SetTabNotes(..) => "SetTabNotes",
+            WorkspaceAction::SetTabFavicon(..) => "SetTabFavicon",
+            WorkspaceAction::SetTabScroll(..) => "SetTabScroll",
+            WorkspaceAction::ReorderTab(..) => "ReorderTab",
+            WorkspaceAction::RenameTab(..) => "RenameTab",
+            WorkspaceAction::ActivateTab(_) => "ActivateTab",
+        }
+    }
+
+    /// The name of the tab this action touches, the old name for a rename
+    pub fn tab_name(&self) -> &str {
+        match self {
+            WorkspaceAction::OpenTab(tab)
+            | WorkspaceAction::CloseTab(tab)
+            | WorkspaceAction::CloseTabBecause(tab, _)
+            | WorkspaceAction::ChangeTabUrl(tab, _)
+            | WorkspaceAction::CreateTab(tab)
+            | WorkspaceAction::RemoveTab(tab)
+            | WorkspaceAction::SetTabIncognito(tab, _)
+            | WorkspaceAction::SetTabMeta(tab, _)
+            | WorkspaceAction::SetTabNotes(tab, _)
+            | WorkspaceAction::SetTabFavicon(tab, _)
+            | WorkspaceAction::SetTabScroll(tab, _)
+            | WorkspaceAction::ReorderTab(tab, _)
+            | WorkspaceAction::RenameTab(tab, _)
+            | WorkspaceAction::ActivateTab(tab) => tab,
+        }
+    }
+
+    fn tab_name_mut(&mut self) -> &mut String {
+        match self {
+            WorkspaceAction::OpenTab(tab)
+            | WorkspaceAction::CloseTab(tab)
+            | WorkspaceAction::CloseTabBecause(tab, _)
+            | WorkspaceAction::ChangeTabUrl(tab, _)
+            | WorkspaceAction::CreateTab(tab)
+            | WorkspaceAction::RemoveTab(tab)
+            | WorkspaceAction::SetTabIncognito(tab, _)
+            | WorkspaceAction::SetTabMeta(tab, _)
+            | WorkspaceAction::SetTabNotes(tab, _)
+            | WorkspaceAction::SetTabFavicon(tab, _)
+            | WorkspaceAction::SetTabScroll(tab, _)
+            | WorkspaceAction::ReorderTab(tab, _)
+            | WorkspaceAction::RenameTab(tab, _)
+            | WorkspaceAction::ActivateTab(tab) => tab,
+        }
+    }
+
+    /// Points the action at `to` if it was meant for the tab called `from`
+    pub fn rename_tab(&mut self, from: &str, to: &str) {
+        if self.tab_name() == from {
+            *self.tab_name_mut() = to.to_string();
+        }
+    }
 }
 
+/// (browser id, workspace id) to the actions waiting for the browser to acknowledge the
+/// workspace's LoadWorkspace
+type PendingLoads = Arc<RwLock<HashMap<(usize, String), Vec<WorkspaceAction>>>>;
+
 #[derive(Default, Clone)]
 pub struct WorkspaceManager {
-    workspaces: Arc<RwLock<Vec<Workspace>>>,
+    config: Arc<ManagerConfig>,
+    // Each entry sits behind its own Arc so readers clone pointers, not tabs
+    workspaces: Arc<RwLock<Vec<Arc<Workspace>>>>,
+    // One per workspace id, shared by the browser and the file watcher side
+    reconcilers: Arc<RwLock<HashMap<String, Reconciler>>>,
+    activity: Arc<RwLock<HashMap<String, WorkspaceActivity>>>,
+    // Every connected browser by id
+    browsers: Arc<RwLock<HashMap<usize, Browser>>>,
+    // An AllWorkspaces broadcast is already scheduled
+    registry_notify_pending: Arc<AtomicBool>,
+    // Paths of workspaces the user explicitly ordered, saved to the config
+    workspace_order: Arc<RwLock<Vec<String>>>,
+    // Browser id to the ids of the workspaces it started
+    connections: Arc<RwLock<HashMap<usize, HashSet<String>>>>,
+    // Workspace id to its running file watcher
+    watchers: Arc<RwLock<HashMap<String, WatcherTask>>>,
+    // Workspace id to the only tabs its watcher watches deeply
+    pinned_tabs: Arc<RwLock<HashMap<String, HashSet<String>>>>,
+    // Browser id to the reconnection token it got in its Welcome
+    tokens: Arc<RwLock<HashMap<usize, String>>>,
+    // Browser id to the client it said it is in its Hello
+    clients: Arc<RwLock<HashMap<usize, String>>>,
+    subscriptions: Arc<RwLock<Subscriptions>>,
+    clock: SharedClock,
+    // Workspace id to when it was last reloaded and whether another reload is queued
+    reloads: Arc<RwLock<HashMap<String, ReloadState>>>,
+    // Workspace id to the lock that serializes its writes to disk, actions like
+    // ReorderTab read and rewrite files shared by every tab
+    write_locks: Arc<RwLock<HashMap<String, Arc<Mutex<()>>>>>,
+    // Workspace id to its open tab count, only there once someone asked for the stream
+    open_counts: Arc<RwLock<HashMap<String, watch::Sender<usize>>>>,
+    // Saved to the config like the workspace order
+    sessions: Arc<RwLock<BTreeMap<String, Vec<String>>>>,
+    // Browser id to the action kinds it asked for, browsers without one get everything
+    action_filters: Arc<RwLock<HashMap<usize, HashSet<String>>>>,
+    // Workspace id to the old names of tabs renamed on disk and what they're called now,
+    // so actions browsers send for an old name still find the tab
+    renamed_tabs: Arc<RwLock<HashMap<String, HashMap<String, String>>>>,
+    // Workspace id to its latest changes, for browsers catching up after a reconnect
+    change_logs: Arc<RwLock<HashMap<String, ChangeLog>>>,
+    // Runs every watcher when the config asks for a single thread, started by the first
+    // watch. The ids count up so `watch_only` streams don't collide with the watchers
+    multiplexer: Arc<std::sync::Mutex<Option<mpsc::UnboundedSender<WatchCommand>>>>,
+    next_watch_id: Arc<AtomicUsize>,
+    // Batches of watcher actions thrown away because the queue was full
+    dropped_watcher_batches: Arc<AtomicU64>,
+    pending_loads: PendingLoads,
+    tab_io: TabIoMap,
+    pending_deletes: PendingDeletes,
+    // Everyone who asked for `connection_events`, dropped once they stop listening
+    connection_subscribers: Arc<std::sync::Mutex<Vec<mpsc::UnboundedSender<ConnectionLifecycle>>>>,
+    #[cfg(feature = "fault-injection")]
+    faults: Arc<crate::faults::Faults>,
+}
+
+#[derive(Default)]
+struct ReloadState {
+    // Tokio's clock so reloads follow paused time in tests
+    last: Option<tokio::time::Instant>,
+    pending: bool,
+}
+
+/// A file watcher on its own task or on the shared multiplexer
+enum WatchHandle {
+    Task(AbortHandle),
+    Multiplexed(usize, mpsc::UnboundedSender<WatchCommand>),
+}
+
+impl WatchHandle {
+    fn abort(&self) {
+        match self {
+            WatchHandle::Task(handle) => handle.abort(),
+            WatchHandle::Multiplexed(id, commands) => {
+                let _ = commands.send(WatchCommand::Unwatch(*id));
+            }
+        }
+    }
+}
+
+/// The stream `WorkspaceManager::watch_only` returns, its watcher stops when it's dropped
+struct WatchOnly {
+    actions: std::pin::Pin<Box<dyn Stream<Item = WorkspaceAction> + Send>>,
+    watch: WatchHandle,
+}
+
+impl Stream for WatchOnly {
+    type Item = WorkspaceAction;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<WorkspaceAction>> {
+        self.actions.as_mut().poll_next(cx)
+    }
+}
+
+// Neither a multiplexed watch nor a waiting one notices that nobody reads it anymore
+impl Drop for WatchOnly {
+    fn drop(&mut self) {
+        self.watch.abort();
+    }
+}
+
+/// The tasks of a running file watcher and the browsers that need it
+struct WatcherTask {
+    browsers: HashSet<usize>,
+    watcher: WatchHandle,
+    forwarder: Option<AbortHandle>,
+    // Kept up to date by the watcher itself
+    paths: WatchedPaths,
+}
+
+impl WatcherTask {
+    fn watched_paths(&self) -> Vec<PathBuf> {
+        match self.paths.read() {
+            Ok(paths) => paths.iter().cloned().collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    fn abort(&self) {
+        self.watcher.abort();
+        if let Some(forwarder) = &self.forwarder {
+            forwarder.abort();
+        }
+    }
+}
+
+/// A running file watcher as shown by `WorkspaceManager::watcher_tasks`
+#[derive(Serialize, Debug, Clone)]
+pub struct WatcherInfo {
+    pub workspace_id: String,
+    pub browser_ids: Vec<usize>,
+    pub paths: Vec<PathBuf>,
+    pub tab_io: BTreeMap<String, TabIoCounts>,
+}
+
+/// Workspace id to the tabs being removed, archived or moved away right now
+type PendingDeletes = Arc<std::sync::Mutex<HashMap<String, HashSet<String>>>>;
+
+/// Workspace id to every tab's file reads and writes, see `tab_io_counts`
+type TabIoMap = Arc<std::sync::RwLock<HashMap<String, HashMap<String, Arc<TabIo>>>>>;
+
+/// The tab stops counting as being deleted when this is dropped
+struct PendingDelete {
+    deletes: PendingDeletes,
+    id: String,
+    tab: String,
+}
+
+impl PendingDelete {
+    fn error(id: &str, tab: &str) -> io::Error {
+        io::Error::other(format!(
+            "Tab {} of workspace {} is being deleted, try again once it's gone",
+            tab, id
+        ))
+    }
+}
+
+impl Drop for PendingDelete {
+    fn drop(&mut self) {
+        let mut deletes = self
+            .deletes
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(tabs) = deletes.get_mut(&self.id) {
+            tabs.remove(&self.tab);
+            if tabs.is_empty() {
+                deletes.remove(&self.id);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct TabIo {
+    reads: AtomicU64,
+    writes: AtomicU64,
+}
+
+#[derive(Clone, Copy)]
+enum TabIoKind {
+    Read,
+    Write,
+}
+
+/// How often a tab's files were read and written, see `WorkspaceManager::tab_io_counts`
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TabIoCounts {
+    pub reads: u64,
+    pub writes: u64,
+}
+
+/// What `WorkspaceManager::metrics` counted since the manager started
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ManagerMetrics {
+    // Only goes up with the DropOldest watcher overflow
+    pub dropped_watcher_batches: u64,
+}
+
+/// How far along a scan for workspaces is, `loaded` counts up to `total` including the
+/// directories that failed to load
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadProgress {
+    pub loaded: usize,
+    pub total: usize,
+}
+
+/** What `WorkspaceManager::dehydrate` keeps of a manager: which workspaces are
+ * registered and watched and what browsers subscribed to. Channels, connections and
+ * cached tabs are left out, they're rebuilt from disk. */
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct ManagerSnapshot {
+    config: ManagerConfig,
+    config_path: Option<PathBuf>,
+    // Registered workspace paths in their order
+    workspaces: Vec<String>,
+    // Ids of the workspaces that had a watcher running
+    active: Vec<String>,
+    workspace_order: Vec<String>,
+    sessions: BTreeMap<String, Vec<String>>,
+    pinned_tabs: HashMap<String, HashSet<String>>,
+    subscriptions: BTreeMap<String, Vec<String>>,
+}
+
+/// One tab going from one workspace to another in `WorkspaceManager::move_batch`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TabMove {
+    pub from: String,
+    pub tab: String,
+    // What the tab is called in `to`
+    pub new_name: String,
+    pub to: String,
+}
+
+impl TabMove {
+    // Workspace ids around a circle the moves make, like [a, b, a]
+    fn find_cycle(moves: &[TabMove]) -> Option<Vec<String>> {
+        let mut edges: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+        for tab_move in moves {
+            edges
+                .entry(&tab_move.from)
+                .or_default()
+                .insert(&tab_move.to);
+        }
+
+        fn visit<'a>(
+            id: &'a str,
+            edges: &BTreeMap<&'a str, BTreeSet<&'a str>>,
+            path: &mut Vec<&'a str>,
+            done: &mut HashSet<&'a str>,
+        ) -> Option<Vec<String>> {
+            if let Some(start) = path.iter().position(|on_path| *on_path == id) {
+                let mut cycle: Vec<String> =
+                    path[start..].iter().map(|id| id.to_string()).collect();
+                cycle.push(id.to_string());
+                return Some(cycle);
+            }
+            if !done.insert(id) {
+                return None;
+            }
+            path.push(id);
+            for next in edges.get(id).into_iter().flatten() {
+                if let Some(cycle) = visit(next, edges, path, done) {
+                    return Some(cycle);
+                }
+            }
+            path.pop();
+            None
+        }
+
+        let mut done = HashSet::new();
+        for id in edges.keys() {
+            if let Some(cycle) = visit(id, &edges, &mut Vec::new(), &mut done) {
+                return Some(cycle);
+            }
+        }
+        None
+    }
+}
+
+/// What `WorkspaceManager::import_urls` did
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ImportReport {
+    // Names of the new tabs in the order of the lines
+    pub created: Vec<String>,
+    // Lines that weren't urls
+    pub skipped: usize,
+}
+
+/// How many changes of a workspace are remembered for `changes_since`
+const CHANGE_LOG_LEN: usize = 256;
+
+/** The latest changes of a workspace, numbered. Numbers start at the time the log was
+ * made in milliseconds so numbers from before a daemon restart are always older than
+ * anything in the new log. */
+struct ChangeLog {
+    seq: u64,
+    actions: VecDeque<(u64, WorkspaceAction)>,
+}
+
+impl ChangeLog {
+    fn new(now: SystemTime) -> ChangeLog {
+        let millis = now
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        ChangeLog {
+            seq: millis as u64,
+            actions: VecDeque::with_capacity(CHANGE_LOG_LEN),
+        }
+    }
+
+    fn record(&mut self, action: &WorkspaceAction) {
+        self.seq += 1;
+        if self.actions.len() == CHANGE_LOG_LEN {
+            self.actions.pop_front();
+        }
+        self.actions.push_back((self.seq, action.clone()));
+    }
+
+    // None when some of the changes after `seq` were already dropped, or `seq` is
+    // from a log this one doesn't continue
+    fn since(&self, seq: u64) -> Option<Vec<WorkspaceAction>> {
+        if seq > self.seq {
+            return None;
+        }
+        let oldest = self.actions.front().map_or(self.seq + 1, |(seq, _)| *seq);
+        if seq + 1 < oldest {
+            return None;
+        }
+        Some(
+            self.actions
+                .iter()
+                .filter(|(at, _)| *at > seq)
+                .map(|(_, action)| action.clone())
+                .collect(),
+        )
+    }
+}
+
+/// Used to decide which workspaces can drop their tabs from memory
+struct WorkspaceActivity {
+    browsers: usize,
+    // From the manager's clock
+    last_used: SystemTime,
 }
 
 impl WorkspaceManager {
+    pub fn new(config: ManagerConfig) -> WorkspaceManager {
+        let subscriptions = Subscriptions::load(config.state_file(SUBSCRIPTIONS_FILE));
+        WorkspaceManager {
+            workspace_order: Arc::new(RwLock::new(config.workspace_order.clone())),
+            subscriptions: Arc::new(RwLock::new(subscriptions)),
+            sessions: Arc::new(RwLock::new(config.sessions.clone())),
+            #[cfg(feature = "fault-injection")]
+            faults: Arc::new(crate::faults::Faults::new(config.faults.clone())),
+            config: Arc::new(config),
+            ..Default::default()
+        }
+    }
+
+    /// A browser with its own outbound queue sized by the config, the socket task reads
+    /// the other end
+    pub fn make_browser(
+        &self,
+        id: usize,
+        protocol: Arc<AtomicU32>,
+    ) -> (Browser, mpsc::Receiver<ToBrowserMessage>) {
+        let (tx, rx) = mpsc::channel(self.config.outbound_queue_len.max(1));
+        let browser = Browser {
+            id,
+            tx,
+            protocol,
+            overflow: self.config.outbound_overflow,
+            kicked: Arc::new(Notify::new()),
+        };
+        (browser, rx)
+    }
+
+    /// The faults injected into this manager, change them with `Faults::set`
+    #[cfg(feature = "fault-injection")]
+    pub fn faults(&self) -> Arc<crate::faults::Faults> {
+        Arc::clone(&self.faults)
+    }
+
+    /// Same as `new` but reads the time from `clock`
+    pub fn with_clock(config: ManagerConfig, clock: SharedClock) -> WorkspaceManager {
+        WorkspaceManager {
+            clock,
+            ..WorkspaceManager::new(config)
+        }
+    }
+
     pub async fn load_workspaces(&self) {
         println!("Loading workspaces");
-        let mut workspaces = self.workspaces.write().await;
-        workspaces.push(Workspace::new_from_fs(
-            "/home/tylord/dev/tabfs-rs/test".as_ref(),
-        ));
-        println!("Loaded {} workspaces", workspaces.len());
+        let paths = if self.config.workspaces.is_empty() && self.config.workspace_roots.is_empty() {
+            vec![PathBuf::from("/home/tylord/dev/tabfs-rs/test")]
+        } else {
+            self.config.workspaces.clone()
+        };
+
+        for configured in paths {
+            let path = self.config.resolve_path(&configured);
+            if !path.exists() {
+                eprintln!(
+                    "Warning: workspace path {} doesn't exist. If the config is shared with \
+                     another machine, use a path relative to the config file instead",
+                    path.display()
+                );
+                continue;
+            }
+            if !path.is_dir() {
+                eprintln!(
+                    "Warning: {} is not a workspace, workspaces are directories",
+                    path.display()
+                );
+                continue;
+            }
+            if let Err(e) = self.make_worksapce(&path).await {
+                eprintln!("Error loading workspace {}: {}", path.display(), e);
+            }
+        }
+        for root in &self.config.workspace_roots {
+            let root = self.config.resolve_path(root);
+            if let Err(e) = self.load_workspaces_from_root(&root, None).await {
+                eprintln!("Error scanning workspace root {}: {}", root.display(), e);
+            }
+        }
+        println!("Loaded {} workspaces", self.workspaces.read().await.len());
+    }
+
+    /** Loads every directory directly inside `root` as a workspace and returns how many
+     * loaded. When `progress` is given it gets a `LoadProgress` before the first
+     * workspace and after every one, so the last one has `loaded == total`. */
+    pub async fn load_workspaces_from_root(
+        &self,
+        root: &Path,
+        progress: Option<mpsc::UnboundedSender<LoadProgress>>,
+    ) -> io::Result<usize> {
+        let mut dirs = Vec::new();
+        for entry in std::fs::read_dir(root)? {
+            let entry = entry?;
+            if file_watcher::is_hidden(&entry.file_name()) || !entry.path().is_dir() {
+                continue;
+            }
+            dirs.push(entry.path());
+        }
+        dirs.sort();
+
+        let total = dirs.len();
+        let report = |loaded| {
+            if let Some(progress) = &progress {
+                let _ = progress.send(LoadProgress { loaded, total });
+            }
+        };
+        report(0);
+
+        let mut made = 0;
+        for (i, dir) in dirs.iter().enumerate() {
+            match self.make_worksapce(dir).await {
+                Ok(_) => made += 1,
+                Err(e) => eprintln!("Error loading workspace {}: {}", dir.display(), e),
+            }
+            report(i + 1);
+        }
+        Ok(made)
     }
 
     pub async fn browser_connected(
@@ -96,106 +1162,972 @@ impl WorkspaceManager {
         browser: &Browser,
         browser_rx: &mut UnboundedReceiverStream<FromBrowserMessage>,
     ) {
-        let workspaces = self.get_all_workspaces().await;
+        self.browsers
+            .write()
+            .await
+            .insert(browser.id, browser.clone());
+        self.connection_event(browser.id, ConnectionEvent::Connected);
 
-        let all_workspaces_message = ToBrowserMessage::AllWorkspaces(workspaces.clone());
+        // Looked up when sent instead of kept around for the whole connection
+        let all_workspaces_message =
+            ToBrowserMessage::AllWorkspaces(self.workspaces_for(browser.id).await);
 
-        match browser.tx.send(all_workspaces_message).await {
-            Ok(()) => {
-                println!("Sending message");
-            }
-            Err(err) => {
-                println!("Error sending: {}", err);
-            }
-        };
+        browser.send(all_workspaces_message);
+
+        let sessions = ToBrowserMessage::Sessions(self.sessions.read().await.clone());
+        browser.send(sessions);
 
         let ignore_next_action = Arc::<RwLock<bool>>::new(RwLock::new(false));
 
-        while let Some(from_browser_message) = browser_rx.next().await {
-            println!("Got message from browser: {:?}", from_browser_message);
+        loop {
+            let from_browser_message = tokio::select! {
+                message = browser_rx.next() => match message {
+                    Some(message) => message,
+                    None => break,
+                },
+                _ = browser.kicked.notified() => break,
+            };
+            if let Err(e) = self.check_access(browser.id, &from_browser_message).await {
+                println!("Refusing message from browser {}: {}", browser.id, e);
+                self.connection_event(browser.id, ConnectionEvent::Error(e.to_string()));
+                let message = match from_browser_message {
+                    FromBrowserMessage::WorkspaceAction(id, action)
+                    | FromBrowserMessage::WorkspaceActionIf(id, action, _) => {
+                        ToBrowserMessage::ActionError {
+                            workspace_id: id,
+                            action,
+                            error: e.to_string(),
+                        }
+                    }
+                    _ => ToBrowserMessage::Notice(e.to_string()),
+                };
+                browser.send(message);
+                continue;
+            }
             match from_browser_message {
                 FromBrowserMessage::StartWorkspace(id) => {
                     let lock = Arc::clone(&ignore_next_action);
                     // maybe launch this in a thread
                     self.start(id, browser, lock).await;
                 }
+                FromBrowserMessage::WorkspaceActionIf(id, action, precondition) => {
+                    let applied = self
+                        .apply_browser_action_if(&id, action.clone(), Some(&precondition))
+                        .await;
+                    if let Err(e) = applied {
+                        println!("Error applying action to fs {}", e);
+                        self.connection_event(browser.id, ConnectionEvent::Error(e.to_string()));
+                        let message = ToBrowserMessage::ActionError {
+                            workspace_id: id,
+                            action,
+                            error: e.to_string(),
+                        };
+                        browser.send(message);
+                    }
+                }
                 FromBrowserMessage::WorkspaceAction(id, action) => {
                     let lock = Arc::clone(&ignore_next_action);
-                    let workspace = workspaces
-                        .clone()
-                        .iter()
-                        .find(|workspace| workspace.id == id)
-                        .unwrap_or_else(|| {
-                            panic!("Couldn't find workspace with id: {}", id.clone())
-                        })
-                        .clone();
                     // we should stop the file watcher when we send this, or at least tell it to
                     // ignore the next event
                     let mut w = lock.write().await;
                     *w = true;
-                    match apply_action_to_fs(&workspace.path.as_ref(), &action) {
-                        Ok(()) => {
-                            println!("Applied action to fs");
-                        }
-                        Err(err) => {
-                            println!("Error applying action to fs {}", err);
-                        }
+                    if let Err(e) = self.apply_browser_action(&id, action.clone()).await {
+                        println!("Error applying action to fs {}", e);
+                        self.connection_event(browser.id, ConnectionEvent::Error(e.to_string()));
+                        let message = ToBrowserMessage::ActionError {
+                            workspace_id: id,
+                            action,
+                            error: e.to_string(),
+                        };
+                        browser.send(message);
                     }
                 }
-            }
+                FromBrowserMessage::AppAction(action) => {
+                    self.handle_app_action(browser, action).await;
+                }
+                FromBrowserMessage::Hello(hello) => {
+                    let version = ProtocolVersion::negotiate(hello.protocol_version);
+                    println!(
+                        "Browser {} asked for protocol {}, using {} (newest is {})",
+                        browser.id,
+                        hello.protocol_version,
+                        version.number(),
+                        PROTOCOL_VERSION
+                    );
+                    browser.protocol.store(version.number(), Ordering::Release);
+                    self.connection_event(
+                        browser.id,
+                        ConnectionEvent::HelloReceived {
+                            asked: hello.protocol_version,
+                            using: version.number(),
+                        },
+                    );
+
+                    let token = hello
+                        .token
+                        .unwrap_or_else(|| Subscriptions::new_token(browser.id));
+                    self.tokens.write().await.insert(browser.id, token.clone());
+
+                    // The list sent on connect only had the shared workspaces
+                    if let Some(client) = hello.client {
+                        self.clients.write().await.insert(browser.id, client);
+                        let workspaces = self.workspaces_for(browser.id).await;
+                        browser.send(ToBrowserMessage::AllWorkspaces(workspaces));
+                    }
+
+                    let welcome = ToBrowserMessage::Welcome {
+                        protocol_version: version.number(),
+                        token: token.clone(),
+                    };
+                    browser.send(welcome);
+
+                    let resumed = self.subscriptions.read().await.workspaces(&token);
+                    for id in resumed {
+                        if self.get_workspace(&id).await.is_none() {
+                            println!("Not resuming workspace {}, it's not loaded", id);
+                            continue;
+                        }
+                        println!("Resuming workspace {} for browser {}", id, browser.id);
+                        self.start(id, browser, Arc::clone(&ignore_next_action))
+                            .await;
+                    }
+                }
+                FromBrowserMessage::GetStatus => {
+                    let status = ToBrowserMessage::Status(self.browser_status(browser.id).await);
+                    browser.send(status);
+                }
+                FromBrowserMessage::SetActionFilter(kinds) => {
+                    self.set_action_filter(browser.id, kinds).await;
+                }
+                FromBrowserMessage::LoadAcknowledged(workspace_id) => {
+                    self.load_acknowledged(browser, &workspace_id).await;
+                }
+                FromBrowserMessage::ChangesSince(workspace_id, seq) => {
+                    let (seq, actions) = self.changes_since(&workspace_id, seq).await;
+                    let changes = ToBrowserMessage::Changes {
+                        workspace_id,
+                        seq,
+                        actions,
+                    };
+                    browser.send(changes);
+                }
+            }
+        }
+
+        self.action_filters.write().await.remove(&browser.id);
+        self.pending_loads
+            .write()
+            .await
+            .retain(|(browser_id, _), _| *browser_id != browser.id);
+
+        self.tokens.write().await.remove(&browser.id);
+        self.clients.write().await.remove(&browser.id);
+        let started = self.connections.write().await.remove(&browser.id);
+        for id in started.unwrap_or_default() {
+            self.touch(&id, -1).await;
+        }
+        self.release_watchers(browser.id).await;
+
+        self.browsers.write().await.remove(&browser.id);
+        self.connection_event(browser.id, ConnectionEvent::Disconnected);
+    }
+
+    /// Logs the event and sends it to everyone listening to `connection_events`
+    pub fn connection_event(&self, browser_id: usize, event: ConnectionEvent) {
+        println!("Browser {}: {:?}", browser_id, event);
+        let lifecycle = ConnectionLifecycle { browser_id, event };
+        let mut subscribers = self
+            .connection_subscribers
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        subscribers.retain(|tx| tx.send(lifecycle.clone()).is_ok());
+    }
+
+    /// What every browser connection does from now on, in the order it happens
+    pub fn connection_events(&self) -> impl Stream<Item = ConnectionLifecycle> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.connection_subscribers
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(tx);
+        UnboundedReceiverStream::new(rx)
+    }
+
+    pub async fn browser_status(&self, browser_id: usize) -> BrowserStatus {
+        let mut connected_workspaces: Vec<String> = self
+            .connections
+            .read()
+            .await
+            .get(&browser_id)
+            .map(|workspaces| workspaces.iter().cloned().collect())
+            .unwrap_or_default();
+        connected_workspaces.sort();
+
+        BrowserStatus {
+            browser_id,
+            connected_workspaces,
+        }
+    }
+
+    /// Writes the action to disk, an action that lost a conflict fails without being written
+    async fn apply_browser_action(&self, id: &str, action: WorkspaceAction) -> io::Result<()> {
+        self.apply_browser_action_if(id, action, None).await
+    }
+
+    /** Same as `apply_browser_action` but only once the tab on disk matches the
+     * precondition, checked while the workspace's write lock is held so nothing can
+     * change the tab in between. A tab that doesn't match is left alone. */
+    pub async fn apply_browser_action_if(
+        &self,
+        id: &str,
+        mut action: WorkspaceAction,
+        precondition: Option<&TabPrecondition>,
+    ) -> io::Result<()> {
+        let workspace = self.get_workspace(id).await.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No workspace with id: {}", id),
+            )
+        })?;
+        // Before anything joins the name onto the workspace path
+        file_watcher::check_tab_names(&action)?;
+        self.touch(id, 0).await;
+        self.follow_renames(id, &mut action).await;
+        file_watcher::normalize_action_names(Path::new(&workspace.path), &mut action);
+        // Checked on the name that gets written and before waiting for the lock, an
+        // action queued behind a remove would otherwise make the tab again right after
+        // it's gone
+        let _deleting = match &action {
+            WorkspaceAction::RemoveTab(tab) => Some(self.begin_delete(id, tab)?),
+            action => {
+                self.check_not_deleting(id, action.tab_name())?;
+                None
+            }
+        };
+        let write_lock = self.write_lock(id).await;
+        let _writing = write_lock.lock().await;
+        if let Some(precondition) = precondition {
+            file_watcher::check_precondition(
+                workspace.path.as_ref(),
+                &workspace.config,
+                action.tab_name(),
+                precondition,
+            )?;
+        }
+        let reconciler = self.reconciler(&workspace).await;
+        let admission = reconciler.admit(ActionSource::Browser, &action).await;
+        if !admission.is_admitted() {
+            return Err(io::Error::other(format!(
+                "{} lost a conflict with a recent edit on disk",
+                action.kind()
+            )));
+        }
+        #[cfg(feature = "fault-injection")]
+        self.faults.before_fs_write()?;
+        apply_action_to_fs(workspace.path.as_ref(), &workspace.config, &action)?;
+        self.count_tab_io(id, action.tab_name(), TabIoKind::Write);
+        if admission == Admission::Admitted {
+            self.record_history(&workspace, std::slice::from_ref(&action));
+        }
+        self.apply_to_cache(id, std::slice::from_ref(&action)).await;
+        self.send_reorders(id, std::slice::from_ref(&action)).await;
+        Ok(())
+    }
+
+    pub async fn handle_app_action(&self, browser: &Browser, action: AppAction) {
+        match action {
+            AppAction::OpenWorkspace(path) => {
+                if let Err(e) = self.make_worksapce(path.as_ref()).await {
+                    eprintln!("Error opening workspace {}: {}", path, e);
+                }
+            }
+            AppAction::WorkspaceAction(id, action) => {
+                if let Err(e) = self.apply_browser_action(&id, action.clone()).await {
+                    eprintln!("Error applying action to fs {}", e);
+                    browser.send(ToBrowserMessage::ActionError {
+                        workspace_id: id,
+                        action,
+                        error: e.to_string(),
+                    });
+                }
+            }
+            AppAction::CloseWorkspace(id) => {
+                self.workspaces.write().await.retain(|w| w.id != id);
+                self.stop_watcher(&id).await;
+                self.notify_workspaces_changed();
+            }
+            AppAction::ReorderWorkspace(id, index) => {
+                if let Err(e) = self.reorder_workspace(&id, index).await {
+                    eprintln!("Error reordering workspace {}: {}", id, e);
+                }
+            }
+            AppAction::ReloadWorkspace(id) => {
+                self.request_reload(&id).await;
+            }
+            AppAction::PinWatchedTabs(id, tabs) => {
+                if let Err(e) = self.pin_watched_tabs(&id, tabs).await {
+                    eprintln!("Error pinning watched tabs of {}: {}", id, e);
+                }
+            }
+            AppAction::SaveSession(name, workspace_ids) => {
+                if let Err(e) = self.save_session(&name, Some(workspace_ids)).await {
+                    eprintln!("Error saving session {}: {}", name, e);
+                }
+            }
+            AppAction::DeleteSession(name) => {
+                if let Err(e) = self.save_session(&name, None).await {
+                    eprintln!("Error deleting session {}: {}", name, e);
+                }
+            }
+            AppAction::OpenSession(name) => {
+                if let Err(e) = self.open_session(&name, browser).await {
+                    eprintln!("Error opening session {}: {}", name, e);
+                }
+            }
+            AppAction::CloseSession(name) => {
+                if let Err(e) = self.close_session(&name, browser).await {
+                    eprintln!("Error closing session {}: {}", name, e);
+                }
+            }
+            AppAction::MoveTab(from, tab, to) => {
+                if let Err(e) = self.move_tab(&from, &tab, &to).await {
+                    eprintln!("Error moving tab {} from {} to {}: {}", tab, from, to, e);
+                }
+            }
+            AppAction::MergeWorkspaces(from, into) => {
+                if let Err(e) = self.merge_workspaces(&from, &into).await {
+                    eprintln!("Error merging workspace {} into {}: {}", from, into, e);
+                }
+            }
+            AppAction::SetWindowGeometry(id, window, geometry) => {
+                if let Err(e) = self.set_window_geometry(&id, &window, geometry).await {
+                    eprintln!("Error saving window {} of {}: {}", window, id, e);
+                }
+            }
+        }
+    }
+
+    /// Records the checksum of every tab file in `.manifest`, returns how many files
+    pub async fn write_manifest(&self, id: &str) -> io::Result<usize> {
+        let workspace = WorkspaceManager::workspace_or_not_found(self.get_workspace(id).await, id)?;
+        let write_lock = self.write_lock(id).await;
+        let _writing = write_lock.lock().await;
+        let path = Path::new(&workspace.path);
+        let manifest = Manifest::scan(path, &workspace.config.layout)?;
+        manifest.write(path)?;
+        println!(
+            "Wrote manifest of {} files for workspace {}",
+            manifest.files.len(),
+            id
+        );
+        Ok(manifest.files.len())
+    }
+
+    /// The files that changed, went missing or showed up since `write_manifest`
+    pub async fn verify_manifest(&self, id: &str) -> io::Result<ManifestReport> {
+        let workspace = WorkspaceManager::workspace_or_not_found(self.get_workspace(id).await, id)?;
+        let write_lock = self.write_lock(id).await;
+        let _writing = write_lock.lock().await;
+        let path = Path::new(&workspace.path);
+        Manifest::read(path)?.verify(path, &workspace.config.layout)
+    }
+
+    /// Saves where the window is to the workspace's `.windows.json`, None forgets it
+    pub async fn set_window_geometry(
+        &self,
+        id: &str,
+        window: &str,
+        geometry: Option<WindowGeometry>,
+    ) -> io::Result<()> {
+        let workspace = WorkspaceManager::workspace_or_not_found(self.get_workspace(id).await, id)?;
+        let write_lock = self.write_lock(id).await;
+        let _writing = write_lock.lock().await;
+        let path = Path::new(&workspace.path);
+        let mut layout = WindowLayout::read(path).unwrap_or_default();
+        layout.version = WindowLayout::VERSION;
+        match geometry {
+            Some(geometry) => {
+                layout.windows.insert(window.to_string(), geometry);
+            }
+            None => {
+                layout.windows.remove(window);
+            }
+        }
+        layout.write(path)
+    }
+
+    /// Moves the workspace to `index` and saves the order to the config
+    pub async fn reorder_workspace(&self, id: &str, index: usize) -> io::Result<()> {
+        let mut workspaces = self.workspaces.write().await;
+        let Some(from) = workspaces.iter().position(|w| w.id == id) else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No workspace with id: {}", id),
+            ));
+        };
+        let workspace = workspaces.remove(from);
+        let index = index.min(workspaces.len());
+        workspaces.insert(index, workspace);
+
+        let order: Vec<String> = workspaces.iter().map(|w| w.path.clone()).collect();
+        *self.workspace_order.write().await = order.clone();
+        drop(workspaces);
+
+        self.config
+            .update(|config| config.workspace_order = order)?;
+        self.notify_workspaces_changed();
+        Ok(())
+    }
+
+    /// Saves the session to the config, None deletes it. Every browser gets the new list
+    pub async fn save_session(
+        &self,
+        name: &str,
+        workspace_ids: Option<Vec<String>>,
+    ) -> io::Result<()> {
+        let mut sessions = self.sessions.write().await;
+        match workspace_ids {
+            Some(workspace_ids) => sessions.insert(name.to_string(), workspace_ids),
+            None => sessions.remove(name),
+        };
+        let saved = sessions.clone();
+        drop(sessions);
+
+        self.config
+            .update(|config| config.sessions = saved.clone())?;
+        for browser in self.browsers.read().await.values() {
+            let message = ToBrowserMessage::Sessions(saved.clone());
+            browser.send(message);
+        }
+        Ok(())
+    }
+
+    async fn session(&self, name: &str) -> io::Result<Vec<String>> {
+        self.sessions
+            .read()
+            .await
+            .get(name)
+            .cloned()
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No session named {}", name),
+                )
+            })
+    }
+
+    /// Starts every workspace of the session for the browser, ones that aren't loaded
+    /// are skipped
+    pub async fn open_session(&self, name: &str, browser: &Browser) -> io::Result<()> {
+        for id in self.session(name).await? {
+            if self.get_workspace(&id).await.is_none() {
+                eprintln!(
+                    "Skipping workspace {} of session {}, it's not loaded",
+                    id, name
+                );
+                continue;
+            }
+            self.start(id, browser, Arc::default()).await;
+        }
+        Ok(())
+    }
+
+    /// Stops every workspace of the session for the browser and sends it its new status
+    pub async fn close_session(&self, name: &str, browser: &Browser) -> io::Result<()> {
+        for id in self.session(name).await? {
+            self.stop(&id, browser).await;
+        }
+        let status = ToBrowserMessage::Status(self.browser_status(browser.id).await);
+        browser.send(status);
+        Ok(())
+    }
+
+    /// Workspaces the user ordered come first in that order, the rest by name then id
+    fn sort_workspaces(workspaces: &mut [Arc<Workspace>], order: &[String]) {
+        workspaces.sort_by(|a, b| {
+            let position = |w: &Workspace| order.iter().position(|p| *p == w.path);
+            match (position(a), position(b)) {
+                (Some(a), Some(b)) => a.cmp(&b),
+                (Some(_), None) => std::cmp::Ordering::Less,
+                (None, Some(_)) => std::cmp::Ordering::Greater,
+                (None, None) => (&a.name, &a.id).cmp(&(&b.name, &b.id)),
+            }
+        });
+    }
+
+    /// Tells every browser the workspace list changed. Calls close together are
+    /// coalesced into one AllWorkspaces message
+    pub fn notify_workspaces_changed(&self) {
+        if self.registry_notify_pending.swap(true, Ordering::AcqRel) {
+            return;
         }
+        let manager = self.clone();
+        let window = Duration::from_millis(self.config.registry_notify_window_ms);
+
+        tokio::spawn(async move {
+            tokio::time::sleep(window).await;
+            manager
+                .registry_notify_pending
+                .store(false, Ordering::Release);
+
+            let browsers: Vec<Browser> = manager.browsers.read().await.values().cloned().collect();
+            println!("Sending workspace list to {} browsers", browsers.len());
+            for browser in browsers {
+                let workspaces = manager.workspaces_for(browser.id).await;
+                browser.send(ToBrowserMessage::AllWorkspaces(workspaces));
+            }
+        });
     }
 
     async fn start(
         &self,
         workspace_id: String,
         browser: &Browser,
-        ignore_next_action: Arc<RwLock<bool>>,
+        _ignore_next_action: Arc<RwLock<bool>>,
     ) {
         println!("Starting workspace: {:?}", workspace_id);
 
-        let workspaces = self.get_all_workspaces().await;
+        let Some(workspace) = self.get_workspace(&workspace_id).await else {
+            let error = format!("No workspace with id: {}", workspace_id);
+            println!("Not starting workspace: {}", error);
+            self.connection_event(browser.id, ConnectionEvent::Error(error.clone()));
+            browser.send(ToBrowserMessage::Notice(error));
+            return;
+        };
 
-        let workspace = workspaces
-            .iter()
-            .find(|workspace| workspace.id == workspace_id)
-            .unwrap_or_else(|| panic!("Couldn't find workspace with id: {}", workspace_id.clone()))
-            .clone();
+        // Sessions and resumed subscriptions start workspaces without a message naming them
+        let client = self.clients.read().await.get(&browser.id).cloned();
+        if !visible_to(&workspace, client.as_deref()) {
+            println!(
+                "Not starting workspace {}, another client owns it",
+                workspace_id
+            );
+            let error = format!("No workspace with id: {}", workspace_id);
+            self.connection_event(browser.id, ConnectionEvent::Error(error));
+            return;
+        }
 
-        let browser_clone = browser.clone();
+        self.run_start_hook(&workspace).await;
 
-        // check if the workspace path is real
-        // if !workspace.path.exists() {
-        //     eprintln!("File path doesn't exist");
-        // }
+        // Only the first browser to start a workspace spawns its watcher
+        let watcher_rx = self.spawn_watcher(&workspace, browser.id).await;
+
+        // The browser counts as connected before the workspace is read, so nothing the
+        // watcher sees from here on is lost. Until the browser acknowledges the load
+        // those actions wait, otherwise they could land before the tabs they touch
+        let wait_for_ack = self.config.load_ack_timeout_ms != 0;
+        if wait_for_ack {
+            self.pending_loads
+                .write()
+                .await
+                .insert((browser.id, workspace_id.clone()), Vec::new());
+        }
+        let newly_connected = self
+            .connections
+            .write()
+            .await
+            .entry(browser.id)
+            .or_default()
+            .insert(workspace_id.clone());
 
-        // let workspace = Workspace::new_from_fs(path);
+        // Anything that changed on disk before the watcher was up is only caught by
+        // reading the workspace again now
+        let workspace = self.refresh_workspace(&workspace).await;
 
         let b_action = ToBrowserMessage::LoadWorkspace(ApiWorkspace {
             tabs: workspace.tabs.clone(),
             id: workspace.id.clone(),
             name: workspace.name.clone(),
+            windows: WindowLayout::read(Path::new(&workspace.path)),
         });
 
-        browser.tx.send(b_action).await.unwrap_or_else(|e| {
-            eprintln!("Error sending to browser: {}", e);
-        });
+        browser.send(b_action);
 
         println!("Sent load workspace message");
+        self.connection_event(
+            browser.id,
+            ConnectionEvent::WorkspaceStarted(workspace_id.clone()),
+        );
 
-        tokio::spawn(async move {
-            let (tx, mut rx) = mpsc::channel::<WorkspaceAction>(101);
-            println!("spawning file watcher");
+        if wait_for_ack {
+            let manager = self.clone();
+            let browser = browser.clone();
+            let workspace_id = workspace_id.clone();
+            let timeout = Duration::from_millis(self.config.load_ack_timeout_ms);
             tokio::spawn(async move {
-                let res = file_watcher::async_watch(&workspace.path.as_ref(), tx).await;
-                if let Err(e) = res {
-                    eprintln!("error watching file: {}", e);
+                tokio::time::sleep(timeout).await;
+                if manager.load_acknowledged(&browser, &workspace_id).await {
+                    println!(
+                        "Browser {} never acknowledged loading {}, sent its actions anyway",
+                        browser.id, workspace_id
+                    );
                 }
-                println!("Watch ended");
             });
+        }
+
+        if newly_connected {
+            self.touch(&workspace_id, 1).await;
+        }
+
+        if let Some(token) = self.tokens.read().await.get(&browser.id) {
+            self.subscriptions
+                .write()
+                .await
+                .subscribe(token, &workspace_id);
+        }
+
+        if let Some(rx) = watcher_rx {
+            self.spawn_forwarder(&workspace, rx).await;
+        }
+    }
+
+    /// Sends the actions held back since the workspace was loaded, false if nothing was
+    /// held back for the browser
+    async fn load_acknowledged(&self, browser: &Browser, workspace_id: &str) -> bool {
+        let key = (browser.id, workspace_id.to_string());
+        let Some(actions) = self.pending_loads.write().await.remove(&key) else {
+            return false;
+        };
+        if !actions.is_empty() {
+            println!(
+                "Sending {} actions held back while browser {} loaded {}",
+                actions.len(),
+                browser.id,
+                workspace_id
+            );
+            browser.send(ToBrowserMessage::from_actions(actions));
+        }
+        true
+    }
+
+    /// Stops sending the workspace's actions to the browser, the undo of `start`
+    async fn stop(&self, workspace_id: &str, browser: &Browser) {
+        let mut connections = self.connections.write().await;
+        let was_connected = connections
+            .get_mut(&browser.id)
+            .is_some_and(|workspaces| workspaces.remove(workspace_id));
+        if connections.get(&browser.id).is_some_and(HashSet::is_empty) {
+            connections.remove(&browser.id);
+        }
+        drop(connections);
+        self.pending_loads
+            .write()
+            .await
+            .remove(&(browser.id, workspace_id.to_string()));
+        if !was_connected {
+            return;
+        }
+        println!(
+            "Stopping workspace {} for browser {}",
+            workspace_id, browser.id
+        );
+        self.touch(workspace_id, -1).await;
+
+        if let Some(token) = self.tokens.read().await.get(&browser.id) {
+            self.subscriptions
+                .write()
+                .await
+                .unsubscribe(token, workspace_id);
+        }
+
+        let mut watchers = self.watchers.write().await;
+        if let Some(task) = watchers.get_mut(workspace_id) {
+            task.browsers.remove(&browser.id);
+            if task.browsers.is_empty() {
+                task.abort();
+                watchers.remove(workspace_id);
+            }
+        }
+    }
+
+    /// Starts watching the workspace unless it's already watched or watching is off,
+    /// returns the watcher's actions once the watches are registered
+    async fn spawn_watcher(
+        &self,
+        workspace: &Workspace,
+        browser_id: usize,
+    ) -> Option<ActionReceiver> {
+        if !workspace.config.watch {
+            println!("Not watching workspace {}, watching is off", workspace.id);
+            return None;
+        }
+        let mut watchers = self.watchers.write().await;
+        if let Some(task) = watchers.get_mut(&workspace.id) {
+            task.browsers.insert(browser_id);
+            return None;
+        }
+
+        let options = self.watch_options(workspace).await;
+        let paths = Arc::clone(&options.watched);
+        let (rx, ready, watcher) = self.spawn_watch_task(workspace, options);
+        watchers.insert(
+            workspace.id.clone(),
+            WatcherTask {
+                browsers: HashSet::from([browser_id]),
+                watcher,
+                forwarder: None,
+                paths,
+            },
+        );
+        drop(watchers);
+
+        WorkspaceManager::wait_ready(ready).await;
+        Some(rx)
+    }
+
+    async fn watch_options(&self, workspace: &Workspace) -> WatchOptions {
+        WatchOptions {
+            deep_tabs: self.pinned_tabs.read().await.get(&workspace.id).cloned(),
+            ..WatchOptions::from_config(&workspace.config)
+        }
+    }
+
+    /** Watches only these tabs deeply, edits inside other tabs are ignored while tabs
+     * being created or removed are still seen. None goes back to watching every tab.
+     * A running watcher is restarted with the new set. */
+    pub async fn pin_watched_tabs(&self, id: &str, tabs: Option<Vec<String>>) -> io::Result<()> {
+        let workspace = self.get_workspace(id).await.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No workspace with id: {}", id),
+            )
+        })?;
+        match tabs {
+            Some(tabs) => {
+                self.pinned_tabs
+                    .write()
+                    .await
+                    .insert(id.to_string(), tabs.into_iter().collect());
+            }
+            None => {
+                self.pinned_tabs.write().await.remove(id);
+            }
+        }
+        self.restart_watcher(&workspace).await;
+        Ok(())
+    }
+
+    // Keeps the browsers of the old watcher
+    async fn restart_watcher(&self, workspace: &Workspace) {
+        let Some(task) = self.watchers.write().await.remove(&workspace.id) else {
+            return;
+        };
+        println!("Restarting watcher of workspace {}", workspace.id);
+        task.abort();
+        self.resume_watcher(workspace, task.browsers).await;
+    }
+
+    // A new watcher for the browsers of one that was stopped
+    async fn resume_watcher(&self, workspace: &Workspace, browsers: HashSet<usize>) {
+        let options = self.watch_options(workspace).await;
+        let paths = Arc::clone(&options.watched);
+        let (rx, ready, watcher) = self.spawn_watch_task(workspace, options);
+        self.watchers.write().await.insert(
+            workspace.id.clone(),
+            WatcherTask {
+                browsers,
+                watcher,
+                forwarder: None,
+                paths,
+            },
+        );
+
+        WorkspaceManager::wait_ready(ready).await;
+        self.spawn_forwarder(workspace, rx).await;
+    }
+
+    /** Tells the browsers when a watcher stopped because its directory went away. A
+     * workspace moved with `relocate_workspace` gets a new watcher there instead, so
+     * this only fires for directories moved or deleted behind the daemon's back. */
+    async fn watcher_ended(&self, id: &str, watched_path: &str) {
+        if Path::new(watched_path).is_dir() {
+            return;
+        }
+        let error = format!(
+            "The directory {} of workspace {} is gone, changes on disk aren't watched \
+             anymore. Use relocate_workspace if it was moved",
+            watched_path, id
+        );
+        eprintln!("{}", error);
+        let message = ToBrowserMessage::WatcherError {
+            workspace_id: id.to_string(),
+            error,
+        };
+        self.send_to_connected(id, message).await;
+    }
+
+    fn spawn_watch_task(
+        &self,
+        workspace: &Workspace,
+        options: WatchOptions,
+    ) -> (ActionReceiver, oneshot::Receiver<()>, WatchHandle) {
+        let (tx, rx) = file_watcher::action_channel(
+            self.config.watcher_queue_len,
+            self.config.watcher_overflow,
+            Arc::clone(&self.dropped_watcher_batches),
+        );
+        let (ready_tx, ready_rx) = oneshot::channel();
+        let path = workspace.path.clone();
+
+        if self.config.single_threaded {
+            let id = self.next_watch_id.fetch_add(1, Ordering::SeqCst);
+            let commands = self.multiplexer();
+            println!("adding file watcher {} to the multiplexer", id);
+            let _ = commands.send(WatchCommand::Watch {
+                id,
+                path: PathBuf::from(path),
+                options: Box::new(options),
+                action_tx: tx,
+                ready: ready_tx,
+            });
+            return (rx, ready_rx, WatchHandle::Multiplexed(id, commands));
+        }
+
+        println!("spawning file watcher");
+        let handle = tokio::spawn(async move {
+            let res = file_watcher::async_watch(path.as_ref(), options, tx, ready_tx).await;
+            if let Err(e) = res {
+                eprintln!("error watching file: {}", e);
+            }
+            println!("Watch ended");
+        });
+
+        (rx, ready_rx, WatchHandle::Task(handle.abort_handle()))
+    }
+
+    // The running multiplexer, or a new one if there's none yet or it stopped
+    fn multiplexer(&self) -> mpsc::UnboundedSender<WatchCommand> {
+        let mut multiplexer = self
+            .multiplexer
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(commands) = multiplexer.as_ref().filter(|tx| !tx.is_closed()) {
+            return commands.clone();
+        }
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(file_watcher::multiplex_watches(rx));
+        *multiplexer = Some(tx.clone());
+        tx
+    }
+
+    async fn wait_ready(ready: oneshot::Receiver<()>) {
+        if ready.await.is_err() {
+            eprintln!("File watcher stopped before it was ready");
+        }
+    }
+
+    /** The workspace directory and tab directories the workspace's watcher covers
+     * right now, for finding out why an edit wasn't noticed. Empty when nothing
+     * watches the workspace. */
+    pub async fn watched_paths(&self, id: &str) -> Vec<PathBuf> {
+        self.watchers
+            .read()
+            .await
+            .get(id)
+            .map(WatcherTask::watched_paths)
+            .unwrap_or_default()
+    }
+
+    /// Counters of things that went wrong without an error being returned anywhere
+    pub fn metrics(&self) -> ManagerMetrics {
+        ManagerMetrics {
+            dropped_watcher_batches: self.dropped_watcher_batches.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Every running file watcher and the browsers it's kept alive for
+    pub async fn watcher_tasks(&self) -> Vec<WatcherInfo> {
+        let mut tasks: Vec<WatcherInfo> = self
+            .watchers
+            .read()
+            .await
+            .iter()
+            .map(|(workspace_id, task)| {
+                let mut browser_ids: Vec<usize> = task.browsers.iter().copied().collect();
+                browser_ids.sort();
+                WatcherInfo {
+                    workspace_id: workspace_id.clone(),
+                    browser_ids,
+                    paths: task.watched_paths(),
+                    tab_io: self.tab_io_counts(workspace_id),
+                }
+            })
+            .collect();
+        tasks.sort_by(|a, b| a.workspace_id.cmp(&b.workspace_id));
+        tasks
+    }
+
+    /// Stops the watchers that only the browser needed
+    async fn release_watchers(&self, browser_id: usize) {
+        let mut watchers = self.watchers.write().await;
+        watchers.retain(|workspace_id, task| {
+            task.browsers.remove(&browser_id);
+            if !task.browsers.is_empty() {
+                return true;
+            }
+            println!(
+                "Stopping watcher of workspace {}, no browsers left",
+                workspace_id
+            );
+            task.abort();
+            false
+        });
+    }
+
+    async fn stop_watcher(&self, workspace_id: &str) {
+        if let Some(task) = self.watchers.write().await.remove(workspace_id) {
+            println!("Stopping watcher of workspace {}", workspace_id);
+            task.abort();
+        }
+    }
 
-            while let Some(action) = rx.recv().await {
-                println!("Got message from file watcher");
+    /// Watches the workspace on its own, without any browser. The stream yields every
+    /// action made on disk and ends if the watcher stops, dropping it stops the watcher
+    pub async fn watch_only(&self, id: &str) -> io::Result<impl Stream<Item = WorkspaceAction>> {
+        let workspace = self.get_workspace(id).await.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No workspace with id: {}", id),
+            )
+        })?;
+        let options = self.watch_options(&workspace).await;
+        let (rx, ready, watch) = self.spawn_watch_task(&workspace, options);
+        WorkspaceManager::wait_ready(ready).await;
+        Ok(WatchOnly {
+            actions: Box::pin(futures_util::StreamExt::flat_map(
+                rx.into_stream(),
+                tokio_stream::iter,
+            )),
+            watch,
+        })
+    }
+
+    /** Every filesystem event in the workspace as notify reports it, before any of them
+     * become actions. For integrations that map files their own way. The stream has
+     * its own watcher, dropping it stops the watcher */
+    pub async fn raw_fs_events(&self, id: &str) -> io::Result<impl Stream<Item = RawFsEvent>> {
+        let workspace = WorkspaceManager::workspace_or_not_found(self.get_workspace(id).await, id)?;
+        let (tx, rx) = mpsc::channel(101);
+        let (ready_tx, ready_rx) = oneshot::channel();
+        let path = workspace.path.clone();
+        tokio::spawn(async move {
+            if let Err(e) = file_watcher::raw_watch(path.as_ref(), tx, ready_tx).await {
+                eprintln!("error watching file: {}", e);
+            }
+        });
+        WorkspaceManager::wait_ready(ready_rx).await;
+        Ok(ReceiverStream::new(rx))
+    }
+
+    /// Sends the actions from the watcher to every browser connected to the workspace
+    async fn spawn_forwarder(&self, workspace: &Workspace, mut rx: ActionReceiver) {
+        let reconciler = self.reconciler(workspace).await;
+        let manager = self.clone();
+        let workspace_id = workspace.id.clone();
+        let watched_path = workspace.path.clone();
+
+        let forwarder = tokio::spawn(async move {
+            while let Some(actions) = rx.recv().await {
                 // let should_ignore = ignore_next_action.read().await;
                 //
                 // if *should_ignore {
@@ -203,21 +2135,3668 @@ impl WorkspaceManager {
                 //     println!("Ignoring action from file watcher: {:?}", action);
                 //     *ignore_lock = false;
                 // }
-                println!("Received action from file watcher: {:?}", action);
 
-                let b_action = ToBrowserMessage::WorkspaceAction(action.to_owned());
+                let mut admitted = Vec::with_capacity(actions.len());
+                for action in actions {
+                    manager.count_tab_io(&workspace_id, action.tab_name(), TabIoKind::Read);
+                    let admission = reconciler.admit(ActionSource::Filesystem, &action).await;
+                    if !admission.is_admitted() {
+                        continue;
+                    }
+                    if let WorkspaceAction::RenameTab(from, to) = &action {
+                        manager.tab_renamed(&workspace_id, from, to).await;
+                    }
+                    // Echoes of the daemon's own writes were recorded when they were made
+                    if admission == Admission::Admitted {
+                        manager.record_history_at(&watched_path, std::slice::from_ref(&action));
+                    }
+                    admitted.push(action);
+                }
+                if admitted.is_empty() {
+                    continue;
+                }
+                manager.apply_to_cache(&workspace_id, &admitted).await;
 
-                browser_clone.tx.send(b_action).await.unwrap_or_else(|e| {
-                    eprintln!("Error sending to browser: {}", e);
-                });
+                manager
+                    .send_actions_to_connected(&workspace_id, admitted)
+                    .await;
             }
+            manager.watchers.write().await.remove(&workspace_id);
+            manager.watcher_ended(&workspace_id, &watched_path).await;
         });
+
+        if let Some(task) = self.watchers.write().await.get_mut(&workspace.id) {
+            task.forwarder = Some(forwarder.abort_handle());
+        }
     }
 
-    pub async fn get_all_workspaces(&self) -> Vec<Workspace> {
-        self.workspaces.read().await.to_vec()
+    /** Moves everything kept under the tab's old name to the new one: its recent
+     * writes, its pin and the name browsers still use for it. Holds the workspace's
+     * write lock so no browser action slips in between. */
+    async fn tab_renamed(&self, workspace_id: &str, from: &str, to: &str) {
+        let write_lock = self.write_lock(workspace_id).await;
+        let _writing = write_lock.lock().await;
+        println!("Tab {} of {} was renamed to {}", from, workspace_id, to);
+
+        if let Some(reconciler) = self.reconcilers.read().await.get(workspace_id) {
+            reconciler.rename(from, to).await;
+        }
+
+        let mut renamed_tabs = self.renamed_tabs.write().await;
+        let renames = renamed_tabs.entry(workspace_id.to_string()).or_default();
+        // Names that pointed at the old name point at the new one, and a tab that got
+        // its old name back isn't renamed anymore
+        for current in renames.values_mut() {
+            if current == from {
+                *current = to.to_string();
+            }
+        }
+        renames.insert(from.to_string(), to.to_string());
+        renames.remove(to);
+        drop(renamed_tabs);
+
+        // The running watcher moves its own watch over, this is for the next one
+        if let Some(pinned) = self.pinned_tabs.write().await.get_mut(workspace_id) {
+            if pinned.remove(from) {
+                pinned.insert(to.to_string());
+            }
+        }
+    }
+
+    // Points an action for a renamed tab at its new name, unless a tab with the old name
+    // exists again
+    async fn follow_renames(&self, workspace_id: &str, action: &mut WorkspaceAction) {
+        let renamed_tabs = self.renamed_tabs.read().await;
+        let Some(to) = renamed_tabs
+            .get(workspace_id)
+            .and_then(|renames| renames.get(action.tab_name()))
+        else {
+            return;
+        };
+        let from = action.tab_name().to_string();
+        if let Some(workspace) = self.get_workspace(workspace_id).await {
+            if workspace.tabs.iter().any(|tab| tab.name == from) {
+                return;
+            }
+        }
+        println!("Tab {} was renamed, sending {:?} to {}", from, action, to);
+        action.rename_tab(&from, to);
+    }
+
+    /// Sends a Notice to every connected browser, started workspaces or not
+    pub async fn broadcast(&self, msg: String) {
+        let browsers: Vec<Browser> = self.browsers.read().await.values().cloned().collect();
+        for browser in browsers {
+            let notice = ToBrowserMessage::Notice(msg.clone());
+            browser.send(notice);
+        }
+    }
+
+    /// Browsers that started the workspace
+    async fn connected_browsers(&self, workspace_id: &str) -> Vec<Browser> {
+        let connections = self.connections.read().await;
+        let browsers = self.browsers.read().await;
+        connections
+            .iter()
+            .filter(|(_, workspaces)| workspaces.contains(workspace_id))
+            .filter_map(|(browser_id, _)| browsers.get(browser_id).cloned())
+            .collect()
+    }
+
+    async fn send_to_connected(&self, workspace_id: &str, message: ToBrowserMessage) {
+        let browsers = self.connected_browsers(workspace_id).await;
+        for browser in browsers {
+            browser.send(message.clone());
+        }
+    }
+
+    /// Sends the actions to the browsers connected to the workspace, leaving out the kinds
+    /// each browser filtered away. Filtering happens here so narrow clients don't pay for
+    /// actions they ignore
+    async fn send_actions_to_connected(&self, workspace_id: &str, actions: Vec<WorkspaceAction>) {
+        let browsers = self.connected_browsers(workspace_id).await;
+        let filters = self.action_filters.read().await;
+        let mut pending_loads = self.pending_loads.write().await;
+        for browser in browsers {
+            let actions: Vec<WorkspaceAction> = match filters.get(&browser.id) {
+                Some(kinds) => actions
+                    .iter()
+                    .filter(|action| kinds.contains(action.kind()))
+                    .cloned()
+                    .collect(),
+                None => actions.clone(),
+            };
+            if actions.is_empty() {
+                continue;
+            }
+            if let Some(held) = pending_loads.get_mut(&(browser.id, workspace_id.to_string())) {
+                held.extend(actions);
+                continue;
+            }
+            let message = ToBrowserMessage::from_actions(actions);
+            browser.send(message);
+        }
+    }
+
+    pub async fn set_action_filter(&self, browser_id: usize, kinds: Option<Vec<String>>) {
+        let mut filters = self.action_filters.write().await;
+        let Some(kinds) = kinds else {
+            filters.remove(&browser_id);
+            return;
+        };
+        for kind in &kinds {
+            if !WorkspaceAction::KINDS.contains(&kind.as_str()) {
+                eprintln!(
+                    "Browser {} filters for unknown action kind {}, known kinds are {:?}",
+                    browser_id,
+                    kind,
+                    WorkspaceAction::KINDS
+                );
+            }
+        }
+        filters.insert(browser_id, kinds.into_iter().collect());
+    }
+
+    /// Runs the workspace's `.on-start` script. A failing script only gets a warning
+    async fn run_start_hook(&self, workspace: &Workspace) {
+        let hook = Path::new(&workspace.path).join(START_HOOK_FILE);
+        if !hook.exists() {
+            return;
+        }
+        if !self.config.allow_scripts {
+            println!(
+                "Not running {}, scripts are not allowed in the config",
+                hook.display()
+            );
+            return;
+        }
+
+        println!("Running start hook: {}", hook.display());
+        let status = tokio::process::Command::new(&hook)
+            .current_dir(&workspace.path)
+            .env("MOUNTTAB_WORKSPACE", &workspace.path)
+            .env("MOUNTTAB_WORKSPACE_ID", &workspace.id)
+            .status()
+            .await;
+
+        match status {
+            Ok(status) if status.success() => {}
+            Ok(status) => eprintln!(
+                "Warning: start hook {} exited with {}",
+                hook.display(),
+                status
+            ),
+            Err(e) => eprintln!("Warning: couldn't run start hook {}: {}", hook.display(), e),
+        }
     }
 
-    // Add the workspace to a list on a file
-    pub fn make_worksapce(&mut self, path: &Path) {}
+    /** Queues a scan of the workspace. Only one reload is queued at a time and scans of
+     * a workspace are at least `min_reload_interval_ms` apart, so a burst of requests
+     * ends up as a single scan. */
+    pub async fn request_reload(&self, id: &str) {
+        let mut reloads = self.reloads.write().await;
+        let state = reloads.entry(id.to_string()).or_default();
+        if state.pending {
+            println!("Reload of workspace {} already queued", id);
+            return;
+        }
+        state.pending = true;
+        let interval = Duration::from_millis(self.config.min_reload_interval_ms);
+        let at = state
+            .last
+            .map_or_else(tokio::time::Instant::now, |last| last + interval);
+        drop(reloads);
+
+        let manager = self.clone();
+        let id = id.to_string();
+        tokio::spawn(async move {
+            tokio::time::sleep_until(at).await;
+            manager.reload_workspace(&id).await;
+            let mut reloads = manager.reloads.write().await;
+            let state = reloads.entry(id).or_default();
+            state.pending = false;
+            state.last = Some(tokio::time::Instant::now());
+        });
+    }
+
+    async fn reload_workspace(&self, id: &str) {
+        let Some(workspace) = self.get_workspace(id).await else {
+            println!("Not reloading workspace {}, it's not loaded", id);
+            return;
+        };
+        println!("Reloading workspace: {}", id);
+        let workspace = self.refresh_workspace(&workspace).await;
+        let message = ToBrowserMessage::LoadWorkspace(ApiWorkspace {
+            tabs: workspace.tabs.clone(),
+            id: workspace.id.clone(),
+            name: workspace.name.clone(),
+            windows: WindowLayout::read(Path::new(&workspace.path)),
+        });
+        self.send_to_connected(id, message).await;
+        self.notify_workspaces_changed();
+    }
+
+    /** Runs `writes` holding the write locks of every workspace in `ids`. Locks are
+     * always taken in id order so two transactions over the same workspaces can't
+     * deadlock. When `writes` fails everything it did is rolled back. */
+    async fn transaction<T>(
+        &self,
+        ids: &[&str],
+        writes: impl FnOnce(&mut FsTransaction) -> io::Result<T>,
+    ) -> io::Result<T> {
+        let mut ids = ids.to_vec();
+        ids.sort();
+        ids.dedup();
+        let mut locks = Vec::with_capacity(ids.len());
+        for id in ids {
+            locks.push(self.write_lock(id).await);
+        }
+        let mut guards = Vec::with_capacity(locks.len());
+        for lock in &locks {
+            guards.push(lock.lock().await);
+        }
+
+        let mut transaction = FsTransaction::new();
+        match writes(&mut transaction) {
+            Ok(value) => {
+                transaction.commit();
+                Ok(value)
+            }
+            Err(e) => {
+                println!("Rolling back transaction: {}", e);
+                transaction.rollback();
+                Err(e)
+            }
+        }
+    }
+
+    fn workspace_or_not_found(
+        workspace: Option<Arc<Workspace>>,
+        id: &str,
+    ) -> io::Result<Arc<Workspace>> {
+        workspace.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No workspace with id: {}", id),
+            )
+        })
+    }
+
+    /** Applies the actions in order as one transaction: when one fails the tabs are put
+     * back the way they were and the error says which action it was. */
+    pub async fn apply_actions(&self, id: &str, actions: &[WorkspaceAction]) -> io::Result<()> {
+        let workspace =
+            WorkspaceManager::workspace_or_not_found(self.loaded_workspace(id).await, id)?;
+        let path = Path::new(&workspace.path);
+        for action in actions {
+            file_watcher::check_tab_names(action)?;
+        }
+        let mut normalized = actions.to_vec();
+        for action in &mut normalized {
+            file_watcher::normalize_action_names(path, action);
+        }
+        let actions = &normalized[..];
+        let mut deleting = Vec::new();
+        for action in actions {
+            if let WorkspaceAction::RemoveTab(tab) = action {
+                deleting.push(self.begin_delete(id, tab)?);
+            }
+        }
+        // Removing a tab and touching it in the same batch is fine, it's in order
+        for action in actions {
+            if !deleting.iter().any(|d| d.tab == action.tab_name()) {
+                self.check_not_deleting(id, action.tab_name())?;
+            }
+        }
+        // Durable workspaces sync once after the last action instead of after each
+        let mut batch = SyncBatch::new();
+        self.transaction(&[id], |transaction| {
+            for (i, action) in actions.iter().enumerate() {
+                let mut touched = vec![action.tab_name().to_string()];
+                match action {
+                    WorkspaceAction::RenameTab(_, to) => touched.push(to.clone()),
+                    // Every tab gets a new order file
+                    WorkspaceAction::ReorderTab(..) => {
+                        touched = file_watcher::ordered_tab_names(path, &workspace.config.layout)?
+                    }
+                    _ => {}
+                }
+                for tab in touched {
+                    transaction.back_up_dir(&path.join(tab))?;
+                }
+                apply_action_to_fs_batched(path, &workspace.config, action, &mut batch).map_err(
+                    |e| match actions.len() {
+                        1 => e,
+                        _ => io::Error::new(
+                            e.kind(),
+                            format!("action {} ({:?}) failed: {}", i, action, e),
+                        ),
+                    },
+                )?;
+            }
+            if workspace.config.durable_writes {
+                batch.sync()?;
+            }
+            Ok(())
+        })
+        .await?;
+        let reconciler = self.reconciler(&workspace).await;
+        for action in actions {
+            self.count_tab_io(id, action.tab_name(), TabIoKind::Write);
+            reconciler.wrote(action).await;
+        }
+        self.record_history(&workspace, actions);
+        self.apply_to_cache(id, actions).await;
+        self.send_reorders(id, actions).await;
+        Ok(())
+    }
+
+    // The watcher doesn't report order files, so reorders reach the browsers from here.
+    // Several of them go out as one transaction
+    async fn send_reorders(&self, id: &str, actions: &[WorkspaceAction]) {
+        let reorders: Vec<WorkspaceAction> = actions
+            .iter()
+            .filter(|action| matches!(action, WorkspaceAction::ReorderTab(..)))
+            .cloned()
+            .collect();
+        if !reorders.is_empty() {
+            self.send_actions_to_connected(id, reorders).await;
+        }
+    }
+
+    /// Adds the closes and removes among the actions to the workspace history
+    fn record_history(&self, workspace: &Workspace, actions: &[WorkspaceAction]) {
+        self.record_history_at(&workspace.path, actions);
+    }
+
+    // A history that can't be written only gets logged, the tabs are closed either way
+    fn record_history_at(&self, path: &str, actions: &[WorkspaceAction]) {
+        let now = self.clock.now();
+        for entry in actions
+            .iter()
+            .filter_map(|action| HistoryEntry::for_action(action, now))
+        {
+            if let Err(e) = history::record(Path::new(path), &entry) {
+                eprintln!("Error recording history of {}: {}", path, e);
+            }
+        }
+    }
+
+    /** Marks the tab as being deleted until the returned guard is dropped. Actions for it
+     * are refused meanwhile, so nothing writes into a directory that's half gone. Errors
+     * if the tab is already being deleted. */
+    fn begin_delete(&self, id: &str, tab: &str) -> io::Result<PendingDelete> {
+        let mut deletes = self
+            .pending_deletes
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if !deletes
+            .entry(id.to_string())
+            .or_default()
+            .insert(tab.to_string())
+        {
+            return Err(PendingDelete::error(id, tab));
+        }
+        Ok(PendingDelete {
+            deletes: Arc::clone(&self.pending_deletes),
+            id: id.to_string(),
+            tab: tab.to_string(),
+        })
+    }
+
+    fn check_not_deleting(&self, id: &str, tab: &str) -> io::Result<()> {
+        let deletes = self
+            .pending_deletes
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        match deletes.get(id).is_some_and(|tabs| tabs.contains(tab)) {
+            true => Err(PendingDelete::error(id, tab)),
+            false => Ok(()),
+        }
+    }
+
+    // Lock free once the tab has a counter, only the first count takes the write lock
+    fn count_tab_io(&self, id: &str, tab: &str, kind: TabIoKind) {
+        let existing = match self.tab_io.read() {
+            Ok(workspaces) => workspaces.get(id).and_then(|tabs| tabs.get(tab)).cloned(),
+            Err(_) => None,
+        };
+        let counter = existing.unwrap_or_else(|| {
+            let mut workspaces = self
+                .tab_io
+                .write()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            Arc::clone(
+                workspaces
+                    .entry(id.to_string())
+                    .or_default()
+                    .entry(tab.to_string())
+                    .or_default(),
+            )
+        });
+        match kind {
+            TabIoKind::Read => counter.reads.fetch_add(1, Ordering::Relaxed),
+            TabIoKind::Write => counter.writes.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    /** How often the files of every tab in the workspace were read and written since the
+     * daemon started. A tab that's written and read far more than the rest is often
+     * stuck in an echo between the browser and the watcher. */
+    pub fn tab_io_counts(&self, id: &str) -> BTreeMap<String, TabIoCounts> {
+        let Ok(workspaces) = self.tab_io.read() else {
+            return BTreeMap::new();
+        };
+        workspaces
+            .get(id)
+            .map(|tabs| {
+                tabs.iter()
+                    .map(|(tab, counter)| {
+                        let counts = TabIoCounts {
+                            reads: counter.reads.load(Ordering::Relaxed),
+                            writes: counter.writes.load(Ordering::Relaxed),
+                        };
+                        (tab.clone(), counts)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Moves the tab to another workspace. If anything fails the tab stays where it was
+    pub async fn move_tab(&self, from_id: &str, tab: &str, to_id: &str) -> io::Result<()> {
+        self.move_tabs(from_id, &[(tab.to_string(), tab.to_string())], to_id)
+            .await
+    }
+
+    /// Moves every tab into `into_id`, tabs whose name is taken there get a numbered
+    /// suffix. Returns the new names
+    pub async fn merge_workspaces(&self, from_id: &str, into_id: &str) -> io::Result<Vec<String>> {
+        let from = WorkspaceManager::workspace_or_not_found(
+            self.loaded_workspace(from_id).await,
+            from_id,
+        )?;
+        let into = WorkspaceManager::workspace_or_not_found(
+            self.loaded_workspace(into_id).await,
+            into_id,
+        )?;
+
+        let mut taken: HashSet<String> = into.tabs.iter().map(|t| t.name.clone()).collect();
+        let mut renames = Vec::new();
+        for tab in &from.tabs {
+            let mut name = tab.name.clone();
+            let mut n = 2;
+            while taken.contains(&name) {
+                name = format!("{}-{}", tab.name, n);
+                n += 1;
+            }
+            taken.insert(name.clone());
+            renames.push((tab.name.clone(), name));
+        }
+
+        self.move_tabs(from_id, &renames, into_id).await?;
+        Ok(renames.into_iter().map(|(_, name)| name).collect())
+    }
+
+    // (old name, new name) pairs, all of them move or none do
+    async fn move_tabs(
+        &self,
+        from_id: &str,
+        tabs: &[(String, String)],
+        to_id: &str,
+    ) -> io::Result<()> {
+        let moves: Vec<TabMove> = tabs
+            .iter()
+            .map(|(tab, new_name)| TabMove {
+                from: from_id.to_string(),
+                tab: tab.clone(),
+                new_name: new_name.clone(),
+                to: to_id.to_string(),
+            })
+            .collect();
+        self.move_batch(&moves).await
+    }
+
+    /** Moves tabs between any number of workspaces in one transaction, all of them move
+     * or none do. The locks of every workspace involved are taken in id order, so
+     * batches going opposite ways can run at the same time without deadlocking.
+     * Batches where tabs would go around in a circle (A to B and B to A) are refused. */
+    pub async fn move_batch(&self, moves: &[TabMove]) -> io::Result<()> {
+        if let Some(tab_move) = moves.iter().find(|m| m.from == m.to) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Can't move {} into the workspace it's in", tab_move.tab),
+            ));
+        }
+        if let Some(cycle) = TabMove::find_cycle(moves) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Moves go in a circle: {}", cycle.join(" -> ")),
+            ));
+        }
+
+        let mut workspaces: HashMap<&str, Arc<Workspace>> = HashMap::new();
+        for id in moves.iter().flat_map(|m| [m.from.as_str(), m.to.as_str()]) {
+            if !workspaces.contains_key(id) {
+                let workspace =
+                    WorkspaceManager::workspace_or_not_found(self.get_workspace(id).await, id)?;
+                workspaces.insert(id, workspace);
+            }
+        }
+        let ids: Vec<&str> = workspaces.keys().copied().collect();
+        let mut deleting = Vec::with_capacity(moves.len());
+        for tab_move in moves {
+            deleting.push(self.begin_delete(&tab_move.from, &tab_move.tab)?);
+        }
+
+        self.transaction(&ids, |transaction| {
+            for tab_move in moves {
+                let source =
+                    Path::new(&workspaces[tab_move.from.as_str()].path).join(&tab_move.tab);
+                if !source.is_dir() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!(
+                            "No tab named {} in workspace {}",
+                            tab_move.tab, tab_move.from
+                        ),
+                    ));
+                }
+                let target =
+                    Path::new(&workspaces[tab_move.to.as_str()].path).join(&tab_move.new_name);
+                transaction.copy_dir(&source, &target)?;
+                transaction.remove_dir(&source)?;
+            }
+            Ok(())
+        })
+        .await?;
+
+        println!("Moved {} tabs", moves.len());
+        let mut refreshed = HashMap::new();
+        for (id, workspace) in &workspaces {
+            refreshed.insert(*id, self.refresh_workspace(workspace).await);
+        }
+        // A move isn't a close, the watchers seeing the tabs go and come are echoes
+        for tab_move in moves {
+            let from = self.reconciler(&workspaces[tab_move.from.as_str()]).await;
+            from.wrote(&WorkspaceAction::RemoveTab(tab_move.tab.clone()))
+                .await;
+            let to = self.reconciler(&workspaces[tab_move.to.as_str()]).await;
+            to.wrote(&WorkspaceAction::CreateTab(tab_move.new_name.clone()))
+                .await;
+        }
+
+        let mut removed: BTreeMap<&str, Vec<WorkspaceAction>> = BTreeMap::new();
+        let mut created: BTreeMap<&str, Vec<WorkspaceAction>> = BTreeMap::new();
+        for tab_move in moves {
+            removed
+                .entry(&tab_move.from)
+                .or_default()
+                .push(WorkspaceAction::RemoveTab(tab_move.tab.clone()));
+
+            let to = &refreshed[tab_move.to.as_str()];
+            let Some(tab) = to.tabs.iter().find(|t| t.name == tab_move.new_name) else {
+                continue;
+            };
+            let created = created.entry(&tab_move.to).or_default();
+            created.push(WorkspaceAction::CreateTab(tab.name.clone()));
+            created.push(WorkspaceAction::ChangeTabUrl(
+                tab.name.clone(),
+                tab.url.clone(),
+            ));
+            if tab.is_open {
+                created.push(WorkspaceAction::OpenTab(tab.name.clone()));
+            }
+        }
+        for (id, actions) in removed.into_iter().chain(created) {
+            self.send_actions_to_connected(id, actions).await;
+        }
+        self.notify_workspaces_changed();
+        Ok(())
+    }
+
+    async fn write_lock(&self, id: &str) -> Arc<Mutex<()>> {
+        if let Some(lock) = self.write_locks.read().await.get(id) {
+            return Arc::clone(lock);
+        }
+        Arc::clone(
+            self.write_locks
+                .write()
+                .await
+                .entry(id.to_string())
+                .or_default(),
+        )
+    }
+
+    /// Keeps the tabs in memory in step with actions that were written to disk
+    async fn apply_to_cache(&self, id: &str, actions: &[WorkspaceAction]) {
+        let mut change_logs = self.change_logs.write().await;
+        let log = change_logs
+            .entry(id.to_string())
+            .or_insert_with(|| ChangeLog::new(self.clock.now()));
+        for action in actions {
+            log.record(action);
+        }
+        drop(change_logs);
+
+        let mut workspaces = self.workspaces.write().await;
+        let Some(entry) = workspaces.iter_mut().find(|w| w.id == id) else {
+            return;
+        };
+        if entry.evicted {
+            return;
+        }
+        let mut workspace = (**entry).clone();
+        for action in actions {
+            workspace.apply_action(action);
+        }
+        *entry = Arc::new(workspace);
+        let open = entry.open_count();
+        drop(workspaces);
+        self.publish_open_count(id, open).await;
+    }
+
+    async fn publish_open_count(&self, id: &str, open: usize) {
+        if let Some(tx) = self.open_counts.read().await.get(id) {
+            tx.send_if_modified(|count| {
+                let changed = *count != open;
+                *count = open;
+                changed
+            });
+        }
+    }
+
+    /// The number of open tabs in the workspace, first the current one and then every
+    /// time it changes
+    pub async fn open_count_stream(&self, id: &str) -> io::Result<impl Stream<Item = usize>> {
+        let workspace = self.loaded_workspace(id).await.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No workspace with id: {}", id),
+            )
+        })?;
+        let mut rx = self
+            .open_counts
+            .write()
+            .await
+            .entry(id.to_string())
+            .or_insert_with(|| watch::channel(workspace.open_count()).0)
+            .subscribe();
+
+        let current = *rx.borrow_and_update();
+        let changes = futures_util::stream::unfold(rx, |mut rx| async move {
+            rx.changed().await.ok()?;
+            let count = *rx.borrow_and_update();
+            Some((count, rx))
+        });
+        Ok(tokio_stream::once(current).chain(changes))
+    }
+
+    /** Reads the workspace from disk and compares it to the tabs in memory. Anything
+     * in the report means an event was missed or applied wrong. Evicted workspaces
+     * have nothing in memory to compare, their report is always empty. */
+    pub async fn drift_report(&self, id: &str) -> io::Result<WorkspaceDiff> {
+        let workspace = self.get_workspace(id).await.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No workspace with id: {}", id),
+            )
+        })?;
+        if workspace.evicted {
+            return Ok(WorkspaceDiff {
+                workspace_id: workspace.id.clone(),
+                ..WorkspaceDiff::default()
+            });
+        }
+        let on_disk = workspace.reload_from_fs();
+        Ok(workspace.diff(&on_disk))
+    }
+
+    /// Renumbers the workspace's order files so no two tabs share a position
+    pub async fn repair_tab_order(&self, id: &str) -> io::Result<()> {
+        let workspace =
+            WorkspaceManager::workspace_or_not_found(self.loaded_workspace(id).await, id)?;
+        let write_lock = self.write_lock(id).await;
+        let _writing = write_lock.lock().await;
+        let path = Path::new(&workspace.path);
+        let collisions = order_collisions(path, &workspace.config.layout)?;
+        if collisions.is_empty() {
+            return Ok(());
+        }
+        println!("Repairing tab order of {}: {:?}", id, collisions);
+        repair_tab_order(path, &workspace.config.layout)?;
+        self.refresh_workspace(&workspace).await;
+        Ok(())
+    }
+
+    /** The workspace's current seq and the actions after `seq`, in order. None instead
+     * of the actions when they can't all be given anymore, the browser has to load
+     * the whole workspace then. A browser that hasn't seen any seq yet gets the
+     * current one from asking with 0. Unknown workspaces get seq 0 and None. */
+    pub async fn changes_since(&self, id: &str, seq: u64) -> (u64, Option<Vec<WorkspaceAction>>) {
+        if let Some(log) = self.change_logs.read().await.get(id) {
+            return (log.seq, log.since(seq));
+        }
+        if self.get_workspace(id).await.is_none() {
+            return (0, None);
+        }
+        // A workspace nothing happened in yet starts its log here, so the seq it hands
+        // out stays valid for the next ask
+        let mut change_logs = self.change_logs.write().await;
+        let log = change_logs
+            .entry(id.to_string())
+            .or_insert_with(|| ChangeLog::new(self.clock.now()));
+        (log.seq, log.since(seq))
+    }
+
+    /// The workspace's tabs from the most recently activated on, tabs that were never
+    /// activated aren't in it
+    pub async fn mru_tabs(&self, id: &str) -> io::Result<Vec<String>> {
+        let workspace = WorkspaceManager::workspace_or_not_found(self.get_workspace(id).await, id)?;
+        let path = Path::new(&workspace.path);
+        Ok(file_watcher::read_mru(path)
+            .into_iter()
+            .filter(|tab| path.join(tab).is_dir())
+            .collect())
+    }
+
+    /** Closed tabs nobody opened for at least `older_than`, going by the last write to
+     * their is_open. Tabs that were never opened count from when they were made. For
+     * suggesting what to archive, nothing is archived here. */
+    pub async fn stale_tabs(&self, id: &str, older_than: Duration) -> io::Result<Vec<Tab>> {
+        let workspace = WorkspaceManager::workspace_or_not_found(self.get_workspace(id).await, id)?;
+        let now = self.clock.now();
+        let path = Path::new(&workspace.path);
+        let (tabs, _, _) = Workspace::read_tabs(path, &workspace.config.layout);
+        Ok(tabs
+            .into_iter()
+            .filter(|tab| {
+                tab_closed_since(path, &tab.name, &workspace.config.layout).is_some_and(
+                    |closed_at| now.duration_since(closed_at).unwrap_or_default() >= older_than,
+                )
+            })
+            .collect())
+    }
+
+    /// Re-reads the workspace from disk and swaps it into the list
+    async fn refresh_workspace(&self, workspace: &Workspace) -> Arc<Workspace> {
+        let refreshed = Arc::new(workspace.reload_from_fs());
+        for tab in &refreshed.tabs {
+            self.count_tab_io(&refreshed.id, &tab.name, TabIoKind::Read);
+        }
+        let mut workspaces = self.workspaces.write().await;
+        if let Some(entry) = workspaces.iter_mut().find(|w| w.id == workspace.id) {
+            *entry = Arc::clone(&refreshed);
+        }
+        drop(workspaces);
+        self.publish_open_count(&refreshed.id, refreshed.open_count())
+            .await;
+        refreshed
+    }
+
+    async fn reconciler(&self, workspace: &Workspace) -> Reconciler {
+        if let Some(reconciler) = self.reconcilers.read().await.get(&workspace.id) {
+            return reconciler.clone();
+        }
+        self.reconcilers
+            .write()
+            .await
+            .entry(workspace.id.clone())
+            .or_insert_with(|| Reconciler::new(&workspace.config))
+            .clone()
+    }
+
+    /// Marks the workspace as used and adjusts how many browsers have it started
+    async fn touch(&self, id: &str, browsers: isize) {
+        let mut activity = self.activity.write().await;
+        let entry = activity
+            .entry(id.to_string())
+            .or_insert_with(|| WorkspaceActivity {
+                browsers: 0,
+                last_used: self.clock.now(),
+            });
+        entry.browsers = entry.browsers.saturating_add_signed(browsers);
+        entry.last_used = self.clock.now();
+    }
+
+    /// Gets the workspace with its tabs, reading them from disk again if they were evicted
+    pub async fn loaded_workspace(&self, id: &str) -> Option<Arc<Workspace>> {
+        let workspace = self.get_workspace(id).await?;
+        self.touch(id, 0).await;
+        if !workspace.evicted {
+            return Some(workspace);
+        }
+        println!("Reloading evicted workspace: {}", id);
+        Some(self.refresh_workspace(&workspace).await)
+    }
+
+    /// Every close and remove of the workspace's tabs with its reason, oldest first
+    pub async fn export_history(&self, id: &str) -> io::Result<Vec<HistoryEntry>> {
+        let workspace = self.get_workspace(id).await.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No workspace with id: {}", id),
+            )
+        })?;
+        history::read(Path::new(&workspace.path))
+    }
+
+    /// The workspace's urls one per line in tab order, tabs without a url are left out
+    pub async fn export_urls(&self, id: &str, open_only: bool) -> io::Result<String> {
+        let workspace = self.loaded_workspace(id).await.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No workspace with id: {}", id),
+            )
+        })?;
+        let urls: Vec<&str> = workspace
+            .tabs
+            .iter()
+            .filter(|tab| tab.is_open || !open_only)
+            .map(|tab| tab.url.trim())
+            .filter(|url| !url.is_empty())
+            .collect();
+        Ok(urls.join("\n"))
+    }
+
+    /// Creates a tab for every url in `text`, one per line. Lines that aren't urls are
+    /// skipped and counted, names come from the host with a counter when it's taken
+    pub async fn import_urls(
+        &self,
+        dest_id: &str,
+        text: &str,
+        open: bool,
+    ) -> io::Result<ImportReport> {
+        let workspace = self.loaded_workspace(dest_id).await.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No workspace with id: {}", dest_id),
+            )
+        })?;
+        let mut taken: HashSet<String> = workspace.tabs.iter().map(|t| t.name.clone()).collect();
+        let mut report = ImportReport::default();
+
+        for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let url = match url::Url::parse(line) {
+                Ok(url) => url,
+                Err(e) => {
+                    println!("Skipping import of {}, not a url: {}", line, e);
+                    report.skipped += 1;
+                    continue;
+                }
+            };
+            let host = url.host_str().unwrap_or("tab").to_string();
+            let mut name = host.clone();
+            let mut n = 2;
+            while taken.contains(&name) {
+                name = format!("{}-{}", host, n);
+                n += 1;
+            }
+
+            let mut actions = vec![
+                WorkspaceAction::CreateTab(name.clone()),
+                WorkspaceAction::ChangeTabUrl(name.clone(), url.to_string()),
+            ];
+            // CreateTab leaves the tab in the workspace's default state
+            if open != workspace.config.default_open {
+                actions.push(match open {
+                    true => WorkspaceAction::OpenTab(name.clone()),
+                    false => WorkspaceAction::CloseTab(name.clone()),
+                });
+            }
+            for action in actions {
+                self.apply_browser_action(dest_id, action).await?;
+            }
+            taken.insert(name.clone());
+            report.created.push(name);
+        }
+
+        Ok(report)
+    }
+
+    /// Periodically drops the tabs of workspaces nobody is using
+    pub fn spawn_evictor(&self) {
+        if self.config.idle_evict_secs == 0 && self.config.max_loaded_workspaces == 0 {
+            return;
+        }
+        let manager = self.clone();
+        let idle_timeout = Duration::from_secs(self.config.idle_evict_secs);
+        let sweep_every = if idle_timeout.is_zero() {
+            Duration::from_secs(60)
+        } else {
+            (idle_timeout / 2).max(Duration::from_secs(1))
+        };
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(sweep_every);
+            loop {
+                ticker.tick().await;
+                manager.evict_idle(idle_timeout).await;
+            }
+        });
+    }
+
+    async fn evict_idle(&self, idle_timeout: Duration) {
+        let now = self.clock.now();
+        let mut activity = self.activity.write().await;
+        let mut workspaces = self.workspaces.write().await;
+
+        // Least recently used first, workspaces with a browser are never evicted.
+        // One that was never used starts its idle clock at the first sweep
+        let mut idle: Vec<(SystemTime, usize)> = workspaces
+            .iter()
+            .enumerate()
+            .filter(|(_, workspace)| !workspace.evicted)
+            .filter_map(|(i, workspace)| {
+                let a = activity
+                    .entry(workspace.id.clone())
+                    .or_insert_with(|| WorkspaceActivity {
+                        browsers: 0,
+                        last_used: now,
+                    });
+                (a.browsers == 0).then_some((a.last_used, i))
+            })
+            .collect();
+        idle.sort();
+
+        let loaded = workspaces.iter().filter(|w| !w.evicted).count();
+        let over_limit = match self.config.max_loaded_workspaces {
+            0 => 0,
+            max => loaded.saturating_sub(max),
+        };
+
+        for (n, (last_used, i)) in idle.into_iter().enumerate() {
+            let timed_out = !idle_timeout.is_zero()
+                && now.duration_since(last_used).unwrap_or_default() >= idle_timeout;
+            if !timed_out && n >= over_limit {
+                continue;
+            }
+            println!("Evicting tabs of idle workspace: {}", workspaces[i].id);
+            workspaces[i] = Arc::new(Workspace {
+                tabs: Vec::new(),
+                evicted: true,
+                ..(*workspaces[i]).clone()
+            });
+        }
+    }
+
+    /// Periodically archives the tabs that stayed closed longer than their workspace allows
+    pub fn spawn_archiver(&self) {
+        let manager = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(Duration::from_secs(60));
+            loop {
+                ticker.tick().await;
+                manager.expire_open_tabs().await;
+                manager.archive_closed_tabs().await;
+            }
+        });
+    }
+
+    /** Closes the tabs that stayed open longer than their workspace allows, with the
+     * Expired reason, and tells the browsers. Returns the closed tabs as (workspace id,
+     * tab name) */
+    pub async fn expire_open_tabs(&self) -> Vec<(String, String)> {
+        let now = self.clock.now();
+        let mut expired = Vec::new();
+
+        for workspace in self.get_all_workspaces().await {
+            if workspace.config.close_open_tabs_after_secs == 0 {
+                continue;
+            }
+            let limit = Duration::from_secs(workspace.config.close_open_tabs_after_secs);
+            let path = Path::new(&workspace.path);
+            let (tabs, _, _) = Workspace::read_tabs(path, &workspace.config.layout);
+
+            let closes: Vec<WorkspaceAction> = tabs
+                .into_iter()
+                .filter(|tab| {
+                    tab_open_since(path, &tab.name, &workspace.config.layout).is_some_and(
+                        |opened_at| now.duration_since(opened_at).unwrap_or_default() >= limit,
+                    )
+                })
+                .map(|tab| WorkspaceAction::CloseTabBecause(tab.name, CloseReason::Expired))
+                .collect();
+            if closes.is_empty() {
+                continue;
+            }
+            println!(
+                "Closing {} tabs of workspace {} that were open too long",
+                closes.len(),
+                workspace.id
+            );
+            if let Err(e) = self.apply_actions(&workspace.id, &closes).await {
+                eprintln!("Error closing expired tabs of {}: {}", workspace.id, e);
+                continue;
+            }
+            expired.extend(
+                closes
+                    .iter()
+                    .map(|action| (workspace.id.clone(), action.tab_name().to_string())),
+            );
+            self.send_actions_to_connected(&workspace.id, closes).await;
+        }
+
+        expired
+    }
+
+    /// Moves closed tabs past their workspace's grace period to `.archive` and tells the
+    /// browsers they're gone. Returns the archived tabs as (workspace id, tab name)
+    pub async fn archive_closed_tabs(&self) -> Vec<(String, String)> {
+        let now = self.clock.now();
+        let mut archived = Vec::new();
+
+        for workspace in self.get_all_workspaces().await {
+            if workspace.config.archive_closed_after_secs == 0 {
+                continue;
+            }
+            let grace = Duration::from_secs(workspace.config.archive_closed_after_secs);
+            let path = Path::new(&workspace.path);
+            let (tabs, _, _) = Workspace::read_tabs(path, &workspace.config.layout);
+
+            let reconciler = self.reconciler(&workspace).await;
+            let write_lock = self.write_lock(&workspace.id).await;
+            let writing = write_lock.lock().await;
+            let mut removed = Vec::new();
+            for tab in tabs {
+                let Some(closed_at) = tab_closed_since(path, &tab.name, &workspace.config.layout)
+                else {
+                    continue;
+                };
+                if now.duration_since(closed_at).unwrap_or_default() < grace {
+                    continue;
+                }
+                let Ok(_deleting) = self.begin_delete(&workspace.id, &tab.name) else {
+                    continue;
+                };
+                println!("Archiving tab {} of workspace {}", tab.name, workspace.id);
+                match archive_tab(path, &tab.name) {
+                    Ok(()) => {
+                        let entry =
+                            HistoryEntry::new(&tab.name, "RemoveTab", CloseReason::Archived, now);
+                        if let Err(e) = history::record(path, &entry) {
+                            eprintln!("Error recording history of {}: {}", workspace.id, e);
+                        }
+                        let action = WorkspaceAction::RemoveTab(tab.name);
+                        reconciler.wrote(&action).await;
+                        removed.push(action)
+                    }
+                    Err(e) => eprintln!("Error archiving tab {}: {}", tab.name, e),
+                }
+            }
+            if removed.is_empty() {
+                continue;
+            }
+
+            if !workspace.evicted {
+                self.refresh_workspace(&workspace).await;
+            }
+            drop(writing);
+            archived.extend(
+                removed
+                    .iter()
+                    .map(|action| (workspace.id.clone(), action.tab_name().to_string())),
+            );
+            self.send_actions_to_connected(&workspace.id, removed).await;
+            self.notify_workspaces_changed();
+        }
+
+        archived
+    }
+
+    /// Periodically writes the current timestamp to `.mounttab-alive` in every workspace
+    pub fn spawn_keep_alive(&self) {
+        if self.config.keep_alive_interval_secs == 0 {
+            return;
+        }
+        let manager = self.clone();
+        let interval = Duration::from_secs(self.config.keep_alive_interval_secs);
+
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                manager.touch_alive_files().await;
+            }
+        });
+    }
+
+    async fn touch_alive_files(&self) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        for workspace in self.get_all_workspaces().await {
+            let alive_file = Path::new(&workspace.path).join(ALIVE_FILE);
+            if let Err(e) = tokio::fs::write(&alive_file, now.to_string()).await {
+                eprintln!("Error touching {}: {}", alive_file.display(), e);
+            }
+        }
+    }
+
+    pub async fn get_all_workspaces(&self) -> Vec<Arc<Workspace>> {
+        self.workspaces.read().await.to_vec()
+    }
+
+    /// The workspaces the browser's client owns and the ones nobody owns
+    pub async fn workspaces_for(&self, browser_id: usize) -> Vec<Arc<Workspace>> {
+        let client = self.clients.read().await.get(&browser_id).cloned();
+        self.workspaces
+            .read()
+            .await
+            .iter()
+            .filter(|workspace| visible_to(workspace, client.as_deref()))
+            .cloned()
+            .collect()
+    }
+
+    /** Fails for a message naming a workspace owned by another client. Those look like
+     * missing workspaces so a browser can't tell which ids other clients have */
+    async fn check_access(
+        &self,
+        browser_id: usize,
+        message: &FromBrowserMessage,
+    ) -> io::Result<()> {
+        let ids = message.workspace_ids();
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let client = self.clients.read().await.get(&browser_id).cloned();
+        let workspaces = self.workspaces.read().await;
+        for id in ids {
+            let hidden = workspaces
+                .iter()
+                .any(|workspace| workspace.id == id && !visible_to(workspace, client.as_deref()));
+            if hidden {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No workspace with id: {}", id),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn get_workspace(&self, id: &str) -> Option<Arc<Workspace>> {
+        self.workspaces
+            .read()
+            .await
+            .iter()
+            .find(|workspace| workspace.id == id)
+            .cloned()
+    }
+
+    // Add the workspace to a list on a file. A directory that's already loaded under
+    // another spelling of its path gives back the loaded workspace
+    pub async fn make_worksapce(&self, path: &Path) -> io::Result<Arc<Workspace>> {
+        let path = self.config.check_allowed_root(path)?;
+        if !path.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a directory", path.display()),
+            ));
+        }
+
+        // `./ws`, `ws/` and a symlink to it are all the same workspace
+        if let Some(loaded) = WorkspaceManager::loaded_at(&self.workspaces.read().await, &path) {
+            println!("{} is already loaded as {}", path.display(), loaded.id);
+            return Ok(loaded);
+        }
+
+        let workspace = Arc::new(Workspace::new_from_fs(&path));
+        // Copied so the order lock isn't held while waiting for the workspaces, reordering
+        // takes them the other way around
+        let order = self.workspace_order.read().await.clone();
+        let mut workspaces = self.workspaces.write().await;
+        // Another registration of the same directory may have won the race
+        if let Some(loaded) = WorkspaceManager::loaded_at(&workspaces, &path) {
+            return Ok(loaded);
+        }
+        if let Some(loaded) = workspaces.iter().find(|w| w.id == workspace.id) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                    "Workspace {} is already loaded from {}",
+                    workspace.id, loaded.path
+                ),
+            ));
+        }
+        workspaces.push(Arc::clone(&workspace));
+        WorkspaceManager::sort_workspaces(&mut workspaces, &order);
+        drop(workspaces);
+        self.notify_workspaces_changed();
+        Ok(workspace)
+    }
+
+    /// Serializes the registry, the watched workspaces and the subscriptions so a
+    /// restarted daemon can pick up where this one left off with `rehydrate`
+    pub async fn dehydrate(&self) -> Vec<u8> {
+        let mut active: Vec<String> = self.watchers.read().await.keys().cloned().collect();
+        active.sort();
+        let snapshot = ManagerSnapshot {
+            config: (*self.config).clone(),
+            config_path: self.config.path.clone(),
+            workspaces: self
+                .get_all_workspaces()
+                .await
+                .iter()
+                .map(|w| w.path.clone())
+                .collect(),
+            active,
+            workspace_order: self.workspace_order.read().await.clone(),
+            sessions: self.sessions.read().await.clone(),
+            pinned_tabs: self.pinned_tabs.read().await.clone(),
+            subscriptions: self.subscriptions.read().await.by_token().clone(),
+        };
+        serde_json::to_vec(&snapshot).unwrap_or_default()
+    }
+
+    /** Builds a manager from what `dehydrate` returned. Workspaces are read from disk
+     * again and the ones that were active get their watchers back, browsers that
+     * reconnect with their token are resumed onto them. */
+    pub async fn rehydrate(bytes: &[u8]) -> io::Result<WorkspaceManager> {
+        let snapshot: ManagerSnapshot = serde_json::from_slice(bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let mut config = snapshot.config;
+        config.path = snapshot.config_path;
+
+        let manager = WorkspaceManager::new(config);
+        *manager.workspace_order.write().await = snapshot.workspace_order;
+        *manager.sessions.write().await = snapshot.sessions;
+        *manager.pinned_tabs.write().await = snapshot.pinned_tabs;
+        manager
+            .subscriptions
+            .write()
+            .await
+            .restore(snapshot.subscriptions);
+
+        for path in &snapshot.workspaces {
+            if let Err(e) = manager.make_worksapce(Path::new(path)).await {
+                eprintln!("Error rehydrating workspace {}: {}", path, e);
+            }
+        }
+        for id in &snapshot.active {
+            let Some(workspace) = manager.get_workspace(id).await else {
+                println!("Not watching workspace {} again, it didn't load", id);
+                continue;
+            };
+            manager.rewatch(&workspace).await;
+        }
+        Ok(manager)
+    }
+
+    // A watcher nobody started yet, the first browser to start the workspace joins it
+    async fn rewatch(&self, workspace: &Workspace) {
+        if !workspace.config.watch {
+            return;
+        }
+        let options = self.watch_options(workspace).await;
+        let paths = Arc::clone(&options.watched);
+        let (rx, ready, watcher) = self.spawn_watch_task(workspace, options);
+        self.watchers.write().await.insert(
+            workspace.id.clone(),
+            WatcherTask {
+                browsers: HashSet::new(),
+                watcher,
+                forwarder: None,
+                paths,
+            },
+        );
+        WorkspaceManager::wait_ready(ready).await;
+        self.spawn_forwarder(workspace, rx).await;
+    }
+
+    // The entries of the config file's workspace list that are the directory at `path`
+    fn configured_entries(&self, path: &Path) -> Vec<PathBuf> {
+        let Some(config_path) = &self.config.path else {
+            return Vec::new();
+        };
+        let Ok(config) = ManagerConfig::load(config_path) else {
+            return Vec::new();
+        };
+        let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+        config
+            .workspaces
+            .iter()
+            .filter(|entry| {
+                std::fs::canonicalize(config.resolve_path(entry)).is_ok_and(|real| real == path)
+            })
+            .cloned()
+            .collect()
+    }
+
+    // The loaded workspace whose directory is really `path`, `path` has to be canonical
+    fn loaded_at(workspaces: &[Arc<Workspace>], path: &Path) -> Option<Arc<Workspace>> {
+        workspaces
+            .iter()
+            .find(|w| std::fs::canonicalize(&w.path).is_ok_and(|real| real == path))
+            .cloned()
+    }
+
+    /// Moves the workspace's directory to `new_path`, the id and tabs stay the same
+    pub async fn relocate_workspace(&self, id: &str, new_path: &Path) -> io::Result<()> {
+        let workspace = self.get_workspace(id).await.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No workspace with id: {}", id),
+            )
+        })?;
+        if new_path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", new_path.display()),
+            ));
+        }
+        let parent = new_path.parent().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} has no parent directory", new_path.display()),
+            )
+        })?;
+        let parent = self.config.check_allowed_root(parent)?;
+        let new_path = match new_path.file_name() {
+            Some(name) => parent.join(name),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} has no directory name", new_path.display()),
+                ))
+            }
+        };
+
+        // Workspace paths are kept as strings, checked before anything moves
+        let new_path_str = new_path.to_str().map(str::to_owned).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} isn't valid UTF-8", new_path.display()),
+            )
+        })?;
+        // Looked up while the old directory is still there to resolve them against
+        let configured = self.configured_entries(Path::new(&workspace.path));
+
+        println!("Moving workspace {} to {}", id, new_path.display());
+        // The watcher holds on to the old path, it's stopped during the move and started
+        // again wherever the workspace ends up
+        let paused = self.watchers.write().await.remove(id);
+        if let Some(task) = &paused {
+            task.abort();
+        }
+        if let Err(e) = tokio::fs::rename(&workspace.path, &new_path).await {
+            if let Some(task) = paused {
+                self.resume_watcher(&workspace, task.browsers).await;
+            }
+            return Err(e);
+        }
+        // The directory already moved, a config that can't be saved only gets logged
+        let saved = self.config.update(|config| {
+            let roots: Vec<PathBuf> = config
+                .workspace_roots
+                .iter()
+                .map(|root| config.resolve_path(root))
+                .map(|root| std::fs::canonicalize(&root).unwrap_or(root))
+                .collect();
+            let config_dir = config
+                .path
+                .as_ref()
+                .and_then(|p| p.parent())
+                .map(PathBuf::from);
+            let mut found = false;
+            for entry in config.workspaces.iter_mut() {
+                if !configured.contains(entry) {
+                    continue;
+                }
+                found = true;
+                // Relative entries stay relative when the new place allows it
+                *entry = match (&config_dir, entry.is_relative()) {
+                    (Some(dir), true) => match new_path.strip_prefix(dir) {
+                        Ok(relative) => relative.to_owned(),
+                        Err(_) => new_path.clone(),
+                    },
+                    _ => new_path.clone(),
+                };
+            }
+            // A workspace found in a root that it's no longer in would be lost otherwise
+            let in_root = |path: &Path| roots.iter().any(|root| path.parent() == Some(root));
+            if !found && in_root(Path::new(&workspace.path)) && !in_root(&new_path) {
+                config.workspaces.push(new_path.clone());
+            }
+        });
+        if let Err(e) = saved {
+            eprintln!("Error saving the new path of workspace {}: {}", id, e);
+        }
+        let new_path = new_path_str;
+
+        let relocated = Arc::new(Workspace {
+            path: new_path.clone(),
+            ..(*workspace).clone()
+        });
+        let mut workspaces = self.workspaces.write().await;
+        if let Some(entry) = workspaces.iter_mut().find(|w| w.id == id) {
+            *entry = Arc::clone(&relocated);
+        }
+        drop(workspaces);
+        if let Some(task) = paused {
+            self.resume_watcher(&relocated, task.browsers).await;
+        }
+
+        let mut order = self.workspace_order.write().await;
+        if let Some(entry) = order.iter_mut().find(|p| **p == workspace.path) {
+            *entry = new_path;
+            let order = order.clone();
+            self.config
+                .update(|config| config.workspace_order = order)?;
+        }
+        drop(order);
+
+        self.notify_workspaces_changed();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, ManualClock};
+    use crate::file_watcher::RawFsEventKind;
+    use crate::layout::TabLayout;
+    use crate::test_util::{write_tab, TempDir, TestBrowser};
+
+    fn manager() -> WorkspaceManager {
+        WorkspaceManager::new(ManagerConfig {
+            load_ack_timeout_ms: 0,
+            ..ManagerConfig::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn alive_file_mtime_advances() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "a", "https://example.com/", true);
+        let manager = manager();
+        manager.make_worksapce(&path).await.unwrap();
+
+        let mtime = || {
+            std::fs::metadata(path.join(ALIVE_FILE))
+                .unwrap()
+                .modified()
+                .unwrap()
+        };
+        manager.touch_alive_files().await;
+        let first = mtime();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        manager.touch_alive_files().await;
+        assert!(mtime() > first);
+    }
+
+    #[tokio::test]
+    async fn idle_workspaces_are_evicted_and_reloaded() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "a", "https://example.com/", true);
+        let clock = ManualClock::default();
+        let manager =
+            WorkspaceManager::with_clock(ManagerConfig::default(), SharedClock::new(clock.clone()));
+        let id = manager.make_worksapce(&path).await.unwrap().id.clone();
+        let idle_timeout = Duration::from_secs(60);
+
+        // The first sweep only starts the idle clock
+        manager.evict_idle(idle_timeout).await;
+        assert!(!manager.get_workspace(&id).await.unwrap().evicted);
+
+        clock.advance(Duration::from_secs(61));
+        manager.evict_idle(idle_timeout).await;
+        let evicted = manager.get_workspace(&id).await.unwrap();
+        assert!(evicted.evicted);
+        assert!(evicted.tabs.is_empty());
+
+        let reloaded = manager.loaded_workspace(&id).await.unwrap();
+        assert!(!reloaded.evicted);
+        assert_eq!(reloaded.tabs.len(), 1);
+        assert_eq!(reloaded.tabs[0].url, "https://example.com/");
+    }
+
+    #[tokio::test]
+    async fn workspaces_with_browsers_are_never_evicted() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "a", "https://example.com/", true);
+        let clock = ManualClock::default();
+        let manager =
+            WorkspaceManager::with_clock(ManagerConfig::default(), SharedClock::new(clock.clone()));
+        let id = manager.make_worksapce(&path).await.unwrap().id.clone();
+        manager.touch(&id, 1).await;
+
+        clock.advance(Duration::from_secs(3600));
+        manager.evict_idle(Duration::from_secs(60)).await;
+        assert!(!manager.get_workspace(&id).await.unwrap().evicted);
+    }
+
+    #[tokio::test]
+    async fn the_least_recently_used_go_over_the_cache_size() {
+        let dir = TempDir::new();
+        let clock = ManualClock::default();
+        let manager = WorkspaceManager::with_clock(
+            ManagerConfig {
+                max_loaded_workspaces: 1,
+                ..ManagerConfig::default()
+            },
+            SharedClock::new(clock.clone()),
+        );
+        let mut ids = Vec::new();
+        for name in ["old", "new"] {
+            let path = dir.dir(name);
+            write_tab(&path, "a", "https://example.com/", true);
+            let id = manager.make_worksapce(&path).await.unwrap().id.clone();
+            manager.touch(&id, 0).await;
+            clock.advance(Duration::from_secs(1));
+            ids.push(id);
+        }
+
+        manager.evict_idle(Duration::ZERO).await;
+        assert!(manager.get_workspace(&ids[0]).await.unwrap().evicted);
+        assert!(!manager.get_workspace(&ids[1]).await.unwrap().evicted);
+    }
+
+    #[tokio::test]
+    async fn workspaces_outside_the_allowed_roots_are_not_loaded() {
+        let dir = TempDir::new();
+        let outside = dir.dir("etc");
+        let manager = WorkspaceManager::new(ManagerConfig {
+            allowed_roots: vec![dir.dir("allowed")],
+            ..ManagerConfig::default()
+        });
+        let err = manager.make_worksapce(&outside).await.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert!(manager.get_all_workspaces().await.is_empty());
+    }
+
+    // Skips what a browser gets when it connects
+    async fn connected(manager: &WorkspaceManager, id: usize) -> TestBrowser {
+        let mut browser = TestBrowser::connect(manager, id);
+        assert!(matches!(
+            browser.recv().await,
+            Some(ToBrowserMessage::AllWorkspaces(_))
+        ));
+        assert!(matches!(
+            browser.recv().await,
+            Some(ToBrowserMessage::Sessions(_))
+        ));
+        browser
+    }
+
+    #[tokio::test]
+    async fn rapid_adds_make_one_broadcast() {
+        let dir = TempDir::new();
+        let manager = manager();
+        let mut browser = connected(&manager, 1).await;
+        for name in ["a", "b", "c"] {
+            manager.make_worksapce(&dir.dir(name)).await.unwrap();
+        }
+
+        let Some(ToBrowserMessage::AllWorkspaces(workspaces)) = browser.recv().await else {
+            panic!("expected the workspace list");
+        };
+        assert_eq!(workspaces.len(), 3);
+        let window = Duration::from_millis(manager.config.registry_notify_window_ms);
+        assert!(browser.recv_within(window * 3).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn starting_an_unknown_workspace_gets_a_notice() {
+        let manager = manager();
+        let mut browser = connected(&manager, 1).await;
+        browser.send(FromBrowserMessage::StartWorkspace("nope".into()));
+        let Some(ToBrowserMessage::Notice(notice)) = browser.recv().await else {
+            panic!("expected a notice");
+        };
+        assert!(notice.contains("nope"));
+    }
+
+    async fn paths(manager: &WorkspaceManager) -> Vec<String> {
+        let workspaces = manager.get_all_workspaces().await;
+        workspaces.iter().map(|w| w.path.clone()).collect()
+    }
+
+    #[tokio::test]
+    async fn the_order_is_stable_across_reloads() {
+        let dir = TempDir::new();
+        let dirs: Vec<PathBuf> = ["a", "b", "c"].iter().map(|name| dir.dir(name)).collect();
+        let config_path = dir.path().join("config.json");
+
+        let first = WorkspaceManager::new(ManagerConfig::load(&config_path).unwrap());
+        for path in &dirs {
+            first.make_worksapce(path).await.unwrap();
+        }
+        let loaded = paths(&first).await;
+
+        // Loaded the other way around, the ids kept in the workspaces decide the order
+        let second = WorkspaceManager::new(ManagerConfig::load(&config_path).unwrap());
+        for path in dirs.iter().rev() {
+            second.make_worksapce(path).await.unwrap();
+        }
+        assert_eq!(paths(&second).await, loaded);
+
+        // A user defined order is saved and survives a restart
+        let last = second.get_all_workspaces().await[2].id.clone();
+        second.reorder_workspace(&last, 0).await.unwrap();
+        let reordered = paths(&second).await;
+        assert_eq!(reordered[0], loaded[2]);
+
+        let third = WorkspaceManager::new(ManagerConfig::load(&config_path).unwrap());
+        for path in &dirs {
+            third.make_worksapce(path).await.unwrap();
+        }
+        assert_eq!(paths(&third).await, reordered);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn reordering_while_adding_doesnt_deadlock() {
+        let dir = TempDir::new();
+        let manager = manager();
+        let id = manager
+            .make_worksapce(&dir.dir("first"))
+            .await
+            .unwrap()
+            .id
+            .clone();
+
+        let mut tasks = Vec::new();
+        for i in 0..20 {
+            let adding = manager.clone();
+            let path = dir.dir(&format!("ws{}", i));
+            tasks.push(tokio::spawn(async move {
+                adding.make_worksapce(&path).await.map(|_| ())
+            }));
+            let reordering = manager.clone();
+            let id = id.clone();
+            tasks.push(tokio::spawn(async move {
+                reordering.reorder_workspace(&id, i).await
+            }));
+        }
+        let all = futures_util::future::join_all(tasks);
+        let results = tokio::time::timeout(Duration::from_secs(10), all)
+            .await
+            .expect("reordering and adding deadlocked");
+        for result in results {
+            result.unwrap().unwrap();
+        }
+        assert_eq!(manager.get_all_workspaces().await.len(), 21);
+    }
+
+    fn write_start_hook(path: &Path) {
+        use std::os::unix::fs::PermissionsExt;
+        let hook = path.join(START_HOOK_FILE);
+        let script = "#!/bin/sh\nmkdir fresh\necho 1 > fresh/is_open\n\
+                      printf https://fresh.example/ > \"$MOUNTTAB_WORKSPACE/fresh/url\"\n";
+        std::fs::write(&hook, script).unwrap();
+        std::fs::set_permissions(&hook, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    async fn started_tabs(manager: &WorkspaceManager, id: &str) -> Vec<Tab> {
+        let mut browser = connected(manager, 1).await;
+        browser.send(FromBrowserMessage::StartWorkspace(id.to_string()));
+        browser
+            .recv_until(|message| match message {
+                ToBrowserMessage::LoadWorkspace(workspace) => Some(workspace.tabs),
+                _ => None,
+            })
+            .await
+            .expect("the workspace never loaded")
+    }
+
+    #[tokio::test]
+    async fn the_start_hook_runs_before_the_load() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_start_hook(&path);
+        let manager = WorkspaceManager::new(ManagerConfig {
+            allow_scripts: true,
+            load_ack_timeout_ms: 0,
+            ..ManagerConfig::default()
+        });
+        let id = manager.make_worksapce(&path).await.unwrap().id.clone();
+
+        let tabs = started_tabs(&manager, &id).await;
+        assert_eq!(tabs.len(), 1);
+        assert_eq!(tabs[0].name, "fresh");
+        assert_eq!(tabs[0].url, "https://fresh.example/");
+    }
+
+    #[tokio::test]
+    async fn the_start_hook_needs_scripts_allowed() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_start_hook(&path);
+        let manager = manager();
+        let id = manager.make_worksapce(&path).await.unwrap().id.clone();
+
+        assert!(started_tabs(&manager, &id).await.is_empty());
+        assert!(!path.join("fresh").exists());
+    }
+
+    // A workspace with one open tab whose url edits go out right away
+    async fn watched_workspace(manager: &WorkspaceManager, dir: &TempDir, name: &str) -> String {
+        let path = dir.dir(name);
+        write_tab(&path, "a", "https://example.com/", true);
+        WorkspaceConfig::update(&path, |config| config.url_settle_ms = 0).unwrap();
+        manager.make_worksapce(&path).await.unwrap().id.clone()
+    }
+
+    async fn start(browser: &mut TestBrowser, id: &str) -> ApiWorkspace {
+        browser.send(FromBrowserMessage::StartWorkspace(id.to_string()));
+        browser
+            .recv_until(|message| match message {
+                ToBrowserMessage::LoadWorkspace(workspace) => Some(workspace),
+                _ => None,
+            })
+            .await
+            .expect("the workspace never loaded")
+    }
+
+    // Whether any workspace action comes in the next moment, other messages are skipped
+    async fn got_actions(browser: &mut TestBrowser) -> bool {
+        while let Some(message) = browser.recv_within(Duration::from_millis(300)).await {
+            if matches!(
+                message,
+                ToBrowserMessage::WorkspaceAction(_)
+                    | ToBrowserMessage::TabFieldChanged { .. }
+                    | ToBrowserMessage::Transaction { .. }
+            ) {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn edit_url(path: &str, tab: &str, url: &str) {
+        std::fs::write(Path::new(path).join(tab).join("url"), url).unwrap();
+    }
+
+    #[tokio::test]
+    async fn actions_only_reach_connected_browsers() {
+        let dir = TempDir::new();
+        let manager = manager();
+        let id = watched_workspace(&manager, &dir, "ws").await;
+        let mut started = connected(&manager, 1).await;
+        let mut other = connected(&manager, 2).await;
+        start(&mut started, &id).await;
+
+        started.send(FromBrowserMessage::GetStatus);
+        let Some(ToBrowserMessage::Status(status)) = started.recv().await else {
+            panic!("expected the status");
+        };
+        assert_eq!(status.connected_workspaces, vec![id.clone()]);
+
+        let path = manager.get_workspace(&id).await.unwrap().path.clone();
+        edit_url(&path, "a", "https://changed.example/");
+        // A write can be seen twice, as the truncate and as the write
+        let changed = started
+            .recv_until(|message| match message {
+                ToBrowserMessage::TabFieldChanged { value, .. }
+                    if value == "https://changed.example/" =>
+                {
+                    Some(value)
+                }
+                _ => None,
+            })
+            .await;
+        assert!(changed.is_some());
+        assert!(!got_actions(&mut other).await);
+    }
+
+    fn hello(token: Option<String>, client: Option<&str>) -> FromBrowserMessage {
+        FromBrowserMessage::Hello(Hello {
+            protocol_version: PROTOCOL_VERSION,
+            token,
+            client: client.map(str::to_string),
+        })
+    }
+
+    async fn welcome(browser: &mut TestBrowser) -> String {
+        browser
+            .recv_until(|message| match message {
+                ToBrowserMessage::Welcome { token, .. } => Some(token),
+                _ => None,
+            })
+            .await
+            .expect("no Welcome")
+    }
+
+    #[tokio::test]
+    async fn a_restart_restores_subscriptions() {
+        let dir = TempDir::new();
+        let config_path = dir.path().join("config.json");
+        let load = || ManagerConfig {
+            load_ack_timeout_ms: 0,
+            ..ManagerConfig::load(&config_path).unwrap()
+        };
+        let path = dir.dir("ws");
+        write_tab(&path, "a", "https://example.com/", true);
+
+        let before = WorkspaceManager::new(load());
+        let id = before.make_worksapce(&path).await.unwrap().id.clone();
+        let mut browser = connected(&before, 1).await;
+        browser.send(hello(None, None));
+        let token = welcome(&mut browser).await;
+        start(&mut browser, &id).await;
+        drop(browser);
+
+        let after = WorkspaceManager::new(load());
+        after.make_worksapce(&path).await.unwrap();
+        let mut browser = connected(&after, 1).await;
+        browser.send(hello(Some(token.clone()), None));
+        assert_eq!(welcome(&mut browser).await, token);
+        let resumed = browser
+            .recv_until(|message| match message {
+                ToBrowserMessage::LoadWorkspace(workspace) => Some(workspace.id),
+                _ => None,
+            })
+            .await;
+        assert_eq!(resumed, Some(id));
+    }
+
+    #[tokio::test]
+    async fn a_reorder_goes_out_as_one_transaction() {
+        let dir = TempDir::new();
+        let manager = manager();
+        let path = dir.dir("ws");
+        for name in ["a", "b", "c"] {
+            write_tab(&path, name, "https://example.com/", true);
+        }
+        WorkspaceConfig::update(&path, |config| config.url_settle_ms = 0).unwrap();
+        let id = manager.make_worksapce(&path).await.unwrap().id.clone();
+        let mut browser = connected(&manager, 1).await;
+        start(&mut browser, &id).await;
+
+        let reorder = vec![
+            WorkspaceAction::ReorderTab("a".into(), 2),
+            WorkspaceAction::ReorderTab("b".into(), 0),
+            WorkspaceAction::ReorderTab("c".into(), 1),
+        ];
+        manager.apply_actions(&id, &reorder).await.unwrap();
+        assert_eq!(
+            file_watcher::ordered_tab_names(&path, &TabLayout::default()).unwrap(),
+            ["b", "c", "a"]
+        );
+
+        let mut transactions = Vec::new();
+        while let Some(message) = browser.recv_within(Duration::from_millis(500)).await {
+            match message {
+                ToBrowserMessage::Transaction { actions, .. } => transactions.push(actions),
+                ToBrowserMessage::WorkspaceAction(action) => {
+                    panic!("{:?} came outside the transaction", action)
+                }
+                ToBrowserMessage::TabFieldChanged { tab, field, .. } => {
+                    panic!("{:?} of {} came outside the transaction", field, tab)
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(transactions, [reorder]);
+    }
+
+    #[test]
+    fn a_single_action_isnt_a_transaction() {
+        let open = vec![WorkspaceAction::OpenTab("a".into())];
+        assert!(matches!(
+            ToBrowserMessage::from_actions(open),
+            ToBrowserMessage::WorkspaceAction(WorkspaceAction::OpenTab(_))
+        ));
+    }
+
+    // The next item, None if the stream has nothing within a few seconds
+    async fn next_within<T>(stream: &mut (impl Stream<Item = T> + Unpin)) -> Option<T> {
+        tokio::time::timeout(Duration::from_secs(5), stream.next())
+            .await
+            .ok()
+            .flatten()
+    }
+
+    #[tokio::test]
+    async fn watch_only_yields_edits_without_a_browser() {
+        let dir = TempDir::new();
+        let manager = manager();
+        let id = watched_workspace(&manager, &dir, "ws").await;
+        let path = manager.get_workspace(&id).await.unwrap().path.clone();
+        let mut actions = Box::pin(manager.watch_only(&id).await.unwrap());
+
+        edit_url(&path, "a", "https://changed.example/");
+        let changed = WorkspaceAction::ChangeTabUrl("a".into(), "https://changed.example/".into());
+        // A write can be seen twice, as the truncate and as the write
+        let mut seen = None;
+        while let Some(action) = next_within(&mut actions).await {
+            if action == changed {
+                seen = Some(action);
+                break;
+            }
+        }
+        assert_eq!(seen, Some(changed));
+        assert!(manager.browsers.read().await.is_empty());
+    }
+
+    // inotify watches of this process on the directory, each watcher has its own
+    #[cfg(target_os = "linux")]
+    fn inotify_watches_on(dir: &Path) -> usize {
+        use std::os::unix::fs::MetadataExt;
+        let ino = format!("ino:{:x} ", std::fs::metadata(dir).unwrap().ino());
+        std::fs::read_dir("/proc/self/fdinfo")
+            .unwrap()
+            .filter_map(|entry| std::fs::read_to_string(entry.ok()?.path()).ok())
+            .flat_map(|info| info.lines().map(str::to_string).collect::<Vec<_>>())
+            .filter(|line| line.starts_with("inotify") && line.contains(&ino))
+            .count()
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn dropping_a_watch_only_stream_stops_its_watcher() {
+        for single_threaded in [false, true] {
+            let dir = TempDir::new();
+            let manager = WorkspaceManager::new(ManagerConfig {
+                load_ack_timeout_ms: 0,
+                single_threaded,
+                ..ManagerConfig::default()
+            });
+            let id = watched_workspace(&manager, &dir, "ws").await;
+            let path = dir.path().join("ws");
+            assert_eq!(inotify_watches_on(&path), 0);
+
+            let actions = manager.watch_only(&id).await.unwrap();
+            assert_eq!(inotify_watches_on(&path), 1);
+            drop(actions);
+            let mut gone = false;
+            for _ in 0..100 {
+                if inotify_watches_on(&path) == 0 {
+                    gone = true;
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            assert!(
+                gone,
+                "single_threaded {}: the watch outlived its stream",
+                single_threaded
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn relocating_keeps_the_id_and_tabs() {
+        let dir = TempDir::new();
+        let config_path = dir.path().join("config.json");
+        let path = dir.dir("ws");
+        write_tab(&path, "a", "https://example.com/", true);
+        WorkspaceConfig::update(&path, |config| config.url_settle_ms = 0).unwrap();
+        ManagerConfig::load(&config_path)
+            .unwrap()
+            .update(|config| config.workspaces = vec![PathBuf::from("ws")])
+            .unwrap();
+        let manager = WorkspaceManager::new(ManagerConfig {
+            load_ack_timeout_ms: 0,
+            ..ManagerConfig::load(&config_path).unwrap()
+        });
+        manager.load_workspaces().await;
+        let id = manager.get_all_workspaces().await[0].id.clone();
+        let mut browser = connected(&manager, 1).await;
+        start(&mut browser, &id).await;
+
+        let moved = dir.path().join("moved");
+        manager.relocate_workspace(&id, &moved).await.unwrap();
+        let relocated = manager.get_workspace(&id).await.unwrap();
+        assert_eq!(Path::new(&relocated.path), moved);
+        assert_eq!(relocated.tabs.len(), 1);
+        assert_eq!(relocated.tabs[0].url, "https://example.com/");
+        assert!(!path.exists());
+
+        // The config points at the new place, still relative to it
+        let config = ManagerConfig::load(&config_path).unwrap();
+        assert_eq!(config.workspaces, vec![PathBuf::from("moved")]);
+        let restarted = WorkspaceManager::new(config);
+        restarted.load_workspaces().await;
+        assert!(restarted.get_workspace(&id).await.is_some());
+
+        // The watcher followed the directory
+        edit_url(&relocated.path, "a", "https://moved.example/");
+        let changed = browser
+            .recv_until(|message| match message {
+                ToBrowserMessage::TabFieldChanged { value, .. }
+                    if value == "https://moved.example/" =>
+                {
+                    Some(value)
+                }
+                _ => None,
+            })
+            .await;
+        assert!(changed.is_some());
+    }
+
+    // Waits for the browsers' disconnects to be handled
+    async fn watchers_for(manager: &WorkspaceManager, browsers: usize) -> Vec<WatcherInfo> {
+        for _ in 0..100 {
+            if manager.browsers.read().await.len() == browsers {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        manager.watcher_tasks().await
+    }
+
+    #[tokio::test]
+    async fn watchers_are_reaped_when_every_browser_is_gone() {
+        let dir = TempDir::new();
+        let manager = manager();
+        let id = watched_workspace(&manager, &dir, "ws").await;
+        let mut first = connected(&manager, 1).await;
+        let mut second = connected(&manager, 2).await;
+        start(&mut first, &id).await;
+        start(&mut second, &id).await;
+
+        let tasks = manager.watcher_tasks().await;
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].workspace_id, id);
+        assert_eq!(tasks[0].browser_ids, vec![1, 2]);
+
+        drop(first);
+        let tasks = watchers_for(&manager, 1).await;
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].browser_ids, vec![2]);
+
+        drop(second);
+        assert!(watchers_for(&manager, 0).await.is_empty());
+    }
+
+    fn manager_at(clock: &ManualClock) -> WorkspaceManager {
+        WorkspaceManager::with_clock(
+            ManagerConfig {
+                load_ack_timeout_ms: 0,
+                ..ManagerConfig::default()
+            },
+            SharedClock::new(clock.clone()),
+        )
+    }
+
+    #[tokio::test]
+    async fn closed_tabs_are_archived_after_the_grace_period() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "closed", "https://closed.example/", false);
+        write_tab(&path, "open", "https://open.example/", true);
+        WorkspaceConfig::update(&path, |config| config.archive_closed_after_secs = 60).unwrap();
+        let clock = ManualClock::default();
+        let manager = manager_at(&clock);
+        let id = manager.make_worksapce(&path).await.unwrap().id.clone();
+
+        assert!(manager.archive_closed_tabs().await.is_empty());
+        clock.advance(Duration::from_secs(61));
+        let archived = manager.archive_closed_tabs().await;
+        assert_eq!(archived, vec![(id.clone(), "closed".to_string())]);
+        assert!(!path.join("closed").exists());
+        assert!(path
+            .join(crate::config::ARCHIVE_DIR)
+            .join("closed")
+            .is_dir());
+        assert!(path.join("open").is_dir());
+
+        let history = manager.export_history(&id).await.unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].close_reason, CloseReason::Archived);
+        let tabs = &manager.get_workspace(&id).await.unwrap().tabs;
+        assert_eq!(tabs.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn out_of_band_edits_show_up_as_drift() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "a", "https://example.com/", true);
+        write_tab(&path, "gone", "https://gone.example/", true);
+        let manager = manager();
+        let id = manager.make_worksapce(&path).await.unwrap().id.clone();
+        assert!(manager.drift_report(&id).await.unwrap().is_empty());
+
+        // Nothing watches the workspace, the cache doesn't see these
+        std::fs::write(path.join("a").join("url"), "https://edited.example/").unwrap();
+        std::fs::remove_dir_all(path.join("gone")).unwrap();
+        write_tab(&path, "new", "https://new.example/", false);
+
+        let drift = manager.drift_report(&id).await.unwrap();
+        assert_eq!(drift.changed.len(), 1);
+        assert_eq!(drift.changed[0].tab, "a");
+        assert_eq!(drift.changed[0].fields, vec!["url"]);
+        assert_eq!(drift.only_in_memory, vec!["gone".to_string()]);
+        assert_eq!(drift.only_on_disk, vec!["new".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn rapid_reload_requests_collapse_into_one_scan() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "a", "https://example.com/", true);
+        let manager = WorkspaceManager::new(ManagerConfig {
+            load_ack_timeout_ms: 0,
+            min_reload_interval_ms: 200,
+            ..ManagerConfig::default()
+        });
+        let id = manager.make_worksapce(&path).await.unwrap().id.clone();
+        // Every scan reads the workspace's only tab once
+        let scans = || manager.tab_io_counts(&id).get("a").map_or(0, |c| c.reads);
+        let before = scans();
+        // From here on time only moves when the test sleeps
+        tokio::time::pause();
+
+        for _ in 0..10 {
+            manager.request_reload(&id).await;
+        }
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        assert_eq!(scans(), before + 1);
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(scans(), before + 1);
+
+        // A burst right after a scan waits out the interval and is one more scan
+        manager.request_reload(&id).await;
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        assert_eq!(scans(), before + 2);
+        for _ in 0..10 {
+            manager.request_reload(&id).await;
+        }
+        tokio::time::sleep(Duration::from_millis(198)).await;
+        assert_eq!(scans(), before + 2);
+        tokio::time::sleep(Duration::from_millis(2)).await;
+        assert_eq!(scans(), before + 3);
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        assert_eq!(scans(), before + 3);
+    }
+
+    #[cfg(feature = "fault-injection")]
+    #[tokio::test]
+    async fn injected_write_failures_come_back_as_action_errors() {
+        use crate::faults::FaultConfig;
+
+        let dir = TempDir::new();
+        let manager = manager();
+        let id = watched_workspace(&manager, &dir, "ws").await;
+        let mut browser = connected(&manager, 1).await;
+        start(&mut browser, &id).await;
+
+        manager.faults().set(FaultConfig {
+            fail_fs_writes: true,
+            ..FaultConfig::default()
+        });
+        let close = WorkspaceAction::CloseTab("a".into());
+        browser.send(FromBrowserMessage::WorkspaceAction(
+            id.clone(),
+            close.clone(),
+        ));
+        let (action, error) = browser
+            .recv_until(|message| match message {
+                ToBrowserMessage::ActionError { action, error, .. } => Some((action, error)),
+                _ => None,
+            })
+            .await
+            .expect("no ActionError for the failed write");
+        assert_eq!(action, close);
+        assert!(error.contains("injected fault"), "{}", error);
+        let is_open = dir.path().join("ws").join("a").join("is_open");
+        assert_eq!(std::fs::read_to_string(&is_open).unwrap(), "1");
+
+        manager.faults().set(FaultConfig::default());
+        browser.send(FromBrowserMessage::WorkspaceAction(id.clone(), close));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(std::fs::read_to_string(&is_open).unwrap(), "0");
+    }
+
+    async fn status(browser: &mut TestBrowser) -> Vec<String> {
+        browser.send(FromBrowserMessage::GetStatus);
+        browser
+            .recv_until(|message| match message {
+                ToBrowserMessage::Status(status) => Some(status.connected_workspaces),
+                _ => None,
+            })
+            .await
+            .expect("no Status")
+    }
+
+    #[tokio::test]
+    async fn a_session_starts_exactly_its_members() {
+        let dir = TempDir::new();
+        let manager = manager();
+        let first = watched_workspace(&manager, &dir, "first").await;
+        let second = watched_workspace(&manager, &dir, "second").await;
+        let outside = watched_workspace(&manager, &dir, "outside").await;
+        let mut browser = connected(&manager, 1).await;
+
+        let members = vec![first.clone(), second.clone()];
+        let save = AppAction::SaveSession("work".into(), members.clone());
+        browser.send(FromBrowserMessage::AppAction(save));
+        let sessions = browser
+            .recv_until(|message| match message {
+                ToBrowserMessage::Sessions(sessions) => Some(sessions),
+                _ => None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(sessions.get("work"), Some(&members));
+
+        browser.send(FromBrowserMessage::AppAction(AppAction::OpenSession(
+            "work".into(),
+        )));
+        let mut started = status(&mut browser).await;
+        started.sort();
+        let mut expected = members.clone();
+        expected.sort();
+        assert_eq!(started, expected);
+        assert!(!started.contains(&outside));
+
+        browser.send(FromBrowserMessage::AppAction(AppAction::CloseSession(
+            "work".into(),
+        )));
+        assert_eq!(status(&mut browser).await, Vec::<String>::new());
+    }
+
+    #[tokio::test]
+    async fn paths_from_another_machine_are_skipped() {
+        let dir = TempDir::new();
+        let shared = dir.dir("shared");
+        write_tab(&shared, "a", "https://example.com/", true);
+        std::fs::write(dir.path().join("notes.txt"), "not a workspace").unwrap();
+        let config_path = dir.path().join("config.json");
+        let config = serde_json::json!({
+            "workspaces": ["/Users/someone-else/Dropbox/tabs", "notes.txt", "shared"],
+        });
+        std::fs::write(&config_path, config.to_string()).unwrap();
+
+        let manager = WorkspaceManager::new(ManagerConfig::load(&config_path).unwrap());
+        manager.load_workspaces().await;
+        assert_eq!(paths(&manager).await, vec![shared.to_str().unwrap()]);
+    }
+
+    #[tokio::test]
+    async fn a_filter_for_opens_skips_url_changes() {
+        let dir = TempDir::new();
+        let manager = manager();
+        let id = watched_workspace(&manager, &dir, "ws").await;
+        let mut filtered = connected(&manager, 1).await;
+        let mut everything = connected(&manager, 2).await;
+        let kinds = vec!["OpenTab".to_string(), "CloseTab".to_string()];
+        filtered.send(FromBrowserMessage::SetActionFilter(Some(kinds)));
+        start(&mut filtered, &id).await;
+        start(&mut everything, &id).await;
+
+        let path = manager.get_workspace(&id).await.unwrap().path.clone();
+        edit_url(&path, "a", "https://changed.example/");
+        let changed = everything
+            .recv_until(|message| match message {
+                ToBrowserMessage::TabFieldChanged { value, .. }
+                    if value == "https://changed.example/" =>
+                {
+                    Some(value)
+                }
+                _ => None,
+            })
+            .await;
+        assert!(changed.is_some());
+        assert!(!got_actions(&mut filtered).await);
+
+        std::fs::write(Path::new(&path).join("a").join("is_open"), "0").unwrap();
+        let closed = filtered
+            .recv_until(|message| match message {
+                ToBrowserMessage::WorkspaceAction(action) => Some(action),
+                _ => None,
+            })
+            .await;
+        assert_eq!(closed, Some(WorkspaceAction::CloseTab("a".into())));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_reorders_stay_consistent() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        let names: Vec<String> = (0..6).map(|i| format!("tab{}", i)).collect();
+        for name in &names {
+            write_tab(&path, name, "https://example.com/", true);
+        }
+        let manager = manager();
+        let id = manager.make_worksapce(&path).await.unwrap().id.clone();
+
+        let mut reorders = Vec::new();
+        for (i, name) in names.iter().enumerate() {
+            let (manager, id, name) = (manager.clone(), id.clone(), name.clone());
+            reorders.push(tokio::spawn(async move {
+                for round in 0..10 {
+                    let reorder = WorkspaceAction::ReorderTab(name.clone(), (i + round) % 6);
+                    manager.apply_browser_action(&id, reorder).await.unwrap();
+                }
+            }));
+        }
+        for reorder in reorders {
+            reorder.await.unwrap();
+        }
+
+        let layout = TabLayout::default();
+        assert_eq!(
+            file_watcher::order_collisions(&path, &layout).unwrap(),
+            Vec::<Vec<String>>::new()
+        );
+        let mut ordered = file_watcher::ordered_tab_names(&path, &layout).unwrap();
+        for name in &ordered {
+            assert!(
+                path.join(name).join(&layout.order_file).exists(),
+                "{}",
+                name
+            );
+        }
+        ordered.sort();
+        assert_eq!(ordered, names);
+    }
+
+    #[tokio::test]
+    async fn export_follows_the_tab_order() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        let order_file = TabLayout::default().order_file;
+        for (order, name, is_open) in [(2, "a", true), (0, "b", false), (1, "c", true)] {
+            write_tab(&path, name, &format!("https://{}.example/", name), is_open);
+            std::fs::write(path.join(name).join(&order_file), order.to_string()).unwrap();
+        }
+        let manager = manager();
+        let id = manager.make_worksapce(&path).await.unwrap().id.clone();
+
+        assert_eq!(
+            manager.export_urls(&id, false).await.unwrap(),
+            "https://b.example/\nhttps://c.example/\nhttps://a.example/"
+        );
+        assert_eq!(
+            manager.export_urls(&id, true).await.unwrap(),
+            "https://c.example/\nhttps://a.example/"
+        );
+    }
+
+    #[tokio::test]
+    async fn import_skips_lines_that_arent_urls() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "example.com", "https://example.com/", false);
+        let manager = manager();
+        let id = manager.make_worksapce(&path).await.unwrap().id.clone();
+
+        let text =
+            "https://example.com/page\n\nnot a url\n  https://rust-lang.org/  \nexample.com\n";
+        let report = manager.import_urls(&id, text, true).await.unwrap();
+        assert_eq!(report.created, vec!["example.com-2", "rust-lang.org"]);
+        assert_eq!(report.skipped, 2);
+
+        let workspace = manager.get_workspace(&id).await.unwrap();
+        let imported = workspace
+            .tabs
+            .iter()
+            .find(|tab| tab.name == "rust-lang.org")
+            .unwrap();
+        assert_eq!(imported.url, "https://rust-lang.org/");
+        assert!(imported.is_open);
+        let url = std::fs::read_to_string(path.join("example.com-2").join("url")).unwrap();
+        assert_eq!(url, "https://example.com/page");
+    }
+
+    #[tokio::test]
+    async fn the_open_count_follows_opens_and_closes() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "a", "https://a.example/", true);
+        write_tab(&path, "b", "https://b.example/", false);
+        let manager = manager();
+        let id = manager.make_worksapce(&path).await.unwrap().id.clone();
+        let mut counts = Box::pin(manager.open_count_stream(&id).await.unwrap());
+        assert_eq!(next_within(&mut counts).await, Some(1));
+
+        let open = WorkspaceAction::OpenTab("b".into());
+        manager.apply_browser_action(&id, open).await.unwrap();
+        assert_eq!(next_within(&mut counts).await, Some(2));
+        for tab in ["a", "b"] {
+            let close = WorkspaceAction::CloseTab(tab.into());
+            manager.apply_browser_action(&id, close).await.unwrap();
+        }
+        // The watch keeps only the latest count, a reader that's behind skips to it
+        let mut count = next_within(&mut counts).await;
+        if count == Some(1) {
+            count = next_within(&mut counts).await;
+        }
+        assert_eq!(count, Some(0));
+    }
+
+    fn dir_names(path: &Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(path)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn a_move_failing_on_the_destination_leaves_the_source_intact() {
+        let dir = TempDir::new();
+        let from_path = dir.dir("from");
+        let to_path = dir.dir("to");
+        write_tab(&from_path, "a", "https://a.example/", true);
+        write_tab(&from_path, "b", "https://b.example/", false);
+        write_tab(&to_path, "taken", "https://taken.example/", true);
+        let manager = manager();
+        let from = manager.make_worksapce(&from_path).await.unwrap().id.clone();
+        let to = manager.make_worksapce(&to_path).await.unwrap().id.clone();
+        let (from_before, to_before) = (dir_names(&from_path), dir_names(&to_path));
+
+        // The first tab moves fine, the second runs into a tab that's there already
+        let tab_move = |tab: &str, new_name: &str| TabMove {
+            from: from.clone(),
+            tab: tab.into(),
+            new_name: new_name.into(),
+            to: to.clone(),
+        };
+        let moves = [tab_move("a", "a"), tab_move("b", "taken")];
+        let err = manager.move_batch(&moves).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+
+        assert_eq!(dir_names(&from_path), from_before);
+        assert_eq!(dir_names(&to_path), to_before);
+        let url = std::fs::read_to_string(from_path.join("a").join("url")).unwrap();
+        assert_eq!(url, "https://a.example/");
+        let tabs = &manager.get_workspace(&from).await.unwrap().tabs;
+        assert_eq!(tabs.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn a_root_scan_reports_progress_up_to_the_total() {
+        let dir = TempDir::new();
+        for name in ["a", "b", "c"] {
+            write_tab(&dir.dir(name), "t", "https://example.com/", true);
+        }
+        dir.dir(".hidden");
+        std::fs::write(dir.path().join("file"), "").unwrap();
+        let manager = manager();
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let made = manager
+            .load_workspaces_from_root(dir.path(), Some(tx))
+            .await
+            .unwrap();
+        assert_eq!(made, 3);
+        let mut progress = Vec::new();
+        while let Ok(event) = rx.try_recv() {
+            progress.push((event.loaded, event.total));
+        }
+        assert_eq!(progress, vec![(0, 3), (1, 3), (2, 3), (3, 3)]);
+    }
+
+    #[tokio::test]
+    async fn one_directory_is_one_workspace_however_its_spelled() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "a", "https://example.com/", true);
+        dir.dir("other");
+        std::os::unix::fs::symlink(&path, dir.path().join("link")).unwrap();
+        let manager = manager();
+
+        let first = manager.make_worksapce(&path).await.unwrap();
+        for spelling in [
+            dir.path().join(".").join("ws"),
+            PathBuf::from(format!("{}/", path.display())),
+            dir.path().join("other").join("..").join("ws"),
+            dir.path().join("link"),
+        ] {
+            let again = manager.make_worksapce(&spelling).await.unwrap();
+            assert_eq!(again.id, first.id, "{}", spelling.display());
+        }
+        assert_eq!(paths(&manager).await, vec![path.to_str().unwrap()]);
+    }
+
+    #[tokio::test]
+    async fn many_workspace_switches_keep_state_bounded() {
+        let dir = TempDir::new();
+        let manager = manager();
+        let first = watched_workspace(&manager, &dir, "first").await;
+        let second = watched_workspace(&manager, &dir, "second").await;
+        manager
+            .save_session("both", Some(vec![first, second]))
+            .await
+            .unwrap();
+        let mut browser = connected(&manager, 1).await;
+
+        for _ in 0..50 {
+            let open = AppAction::OpenSession("both".into());
+            browser.send(FromBrowserMessage::AppAction(open));
+            let close = AppAction::CloseSession("both".into());
+            browser.send(FromBrowserMessage::AppAction(close));
+            // Closing the session answers with the status
+            let started = browser
+                .recv_until(|message| match message {
+                    ToBrowserMessage::Status(status) => Some(status.connected_workspaces),
+                    _ => None,
+                })
+                .await;
+            assert_eq!(started, Some(vec![]));
+        }
+
+        // A browser with nothing started has no connection entry left
+        assert!(!manager.connections.read().await.contains_key(&1));
+        assert!(manager.watcher_tasks().await.len() <= 2);
+        assert!(manager.reconcilers.read().await.len() <= 2);
+        assert!(manager.change_logs.read().await.len() <= 2);
+        assert!(manager.open_counts.read().await.len() <= 2);
+    }
+
+    #[tokio::test]
+    async fn an_action_for_a_tab_renamed_on_disk_follows_it() {
+        let dir = TempDir::new();
+        let manager = manager();
+        let id = watched_workspace(&manager, &dir, "ws").await;
+        let mut browser = connected(&manager, 1).await;
+        start(&mut browser, &id).await;
+
+        let path = PathBuf::from(&manager.get_workspace(&id).await.unwrap().path);
+        std::fs::rename(path.join("a"), path.join("b")).unwrap();
+        let renamed = browser
+            .recv_until(|message| match message {
+                ToBrowserMessage::WorkspaceAction(action @ WorkspaceAction::RenameTab(..)) => {
+                    Some(action)
+                }
+                _ => None,
+            })
+            .await;
+        assert_eq!(
+            renamed,
+            Some(WorkspaceAction::RenameTab("a".into(), "b".into()))
+        );
+
+        // Sent by a browser that hadn't seen the rename yet
+        let close = WorkspaceAction::CloseTab("a".into());
+        manager.apply_browser_action(&id, close).await.unwrap();
+        assert!(!path.join("a").exists());
+        assert_eq!(
+            std::fs::read_to_string(path.join("b").join("is_open")).unwrap(),
+            "0"
+        );
+        let workspace = manager.get_workspace(&id).await.unwrap();
+        let names: Vec<&str> = workspace.tabs.iter().map(|tab| tab.name.as_str()).collect();
+        assert_eq!(names, vec!["b"]);
+        assert!(!workspace.tabs[0].is_open);
+    }
+
+    #[tokio::test]
+    async fn rehydrating_keeps_the_workspaces_and_the_active_ones() {
+        let dir = TempDir::new();
+        let manager = manager();
+        let watched = watched_workspace(&manager, &dir, "watched").await;
+        let idle = watched_workspace(&manager, &dir, "idle").await;
+        manager
+            .save_session("work", Some(vec![watched.clone(), idle]))
+            .await
+            .unwrap();
+        let mut browser = connected(&manager, 1).await;
+        start(&mut browser, &watched).await;
+
+        let bytes = manager.dehydrate().await;
+        let rehydrated = WorkspaceManager::rehydrate(&bytes).await.unwrap();
+        assert_eq!(paths(&rehydrated).await, paths(&manager).await);
+        let active: Vec<String> = rehydrated
+            .watcher_tasks()
+            .await
+            .into_iter()
+            .map(|watcher| watcher.workspace_id)
+            .collect();
+        assert_eq!(active, vec![watched]);
+        assert_eq!(
+            *rehydrated.sessions.read().await,
+            *manager.sessions.read().await
+        );
+    }
+
+    #[tokio::test]
+    async fn a_browser_catches_up_on_what_it_missed() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "a", "https://example.com/", true);
+        let manager = manager();
+        let id = manager.make_worksapce(&path).await.unwrap().id.clone();
+        let (seen, missed) = manager.changes_since(&id, 0).await;
+        assert_eq!(missed, None);
+
+        let actions = vec![
+            WorkspaceAction::CloseTab("a".into()),
+            WorkspaceAction::ChangeTabUrl("a".into(), "https://missed.example/".into()),
+            WorkspaceAction::OpenTab("a".into()),
+        ];
+        for action in &actions {
+            manager
+                .apply_browser_action(&id, action.clone())
+                .await
+                .unwrap();
+        }
+        let (seq, missed) = manager.changes_since(&id, seen).await;
+        assert_eq!(seq, seen + 3);
+        assert_eq!(missed, Some(actions));
+        assert_eq!(manager.changes_since(&id, seq).await, (seq, Some(vec![])));
+
+        // Once the log rolled over the browser has to load the workspace again
+        let scrolls: Vec<WorkspaceAction> = (0..CHANGE_LOG_LEN)
+            .map(|i| WorkspaceAction::SetTabScroll("a".into(), i as f64))
+            .collect();
+        manager.apply_to_cache(&id, &scrolls).await;
+        assert_eq!(
+            manager.changes_since(&id, seq).await.1.map(|a| a.len()),
+            Some(CHANGE_LOG_LEN)
+        );
+        assert_eq!(manager.changes_since(&id, seen).await.1, None);
+
+        assert_eq!(manager.changes_since("unknown", 0).await, (0, None));
+    }
+
+    #[tokio::test]
+    async fn an_unwatched_workspace_sends_no_disk_edits() {
+        let dir = TempDir::new();
+        let manager = manager();
+        let path = dir.dir("ws");
+        write_tab(&path, "a", "https://example.com/", true);
+        WorkspaceConfig::update(&path, |config| config.watch = false).unwrap();
+        let id = manager.make_worksapce(&path).await.unwrap().id.clone();
+        let mut browser = connected(&manager, 1).await;
+        start(&mut browser, &id).await;
+        assert!(manager.watcher_tasks().await.is_empty());
+
+        edit_url(path.to_str().unwrap(), "a", "https://changed.example/");
+        assert!(!got_actions(&mut browser).await);
+
+        // The browser still writes to disk
+        let close = WorkspaceAction::CloseTab("a".into());
+        browser.send(FromBrowserMessage::WorkspaceAction(id.clone(), close));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let is_open = std::fs::read_to_string(path.join("a").join("is_open")).unwrap();
+        assert_eq!(is_open, "0");
+    }
+
+    #[tokio::test]
+    async fn watched_paths_follow_tabs_coming_and_going() {
+        let dir = TempDir::new();
+        let manager = manager();
+        let id = watched_workspace(&manager, &dir, "ws").await;
+        let path = PathBuf::from(&manager.get_workspace(&id).await.unwrap().path);
+        assert!(manager.watched_paths(&id).await.is_empty());
+        let mut browser = connected(&manager, 1).await;
+        start(&mut browser, &id).await;
+        assert_eq!(
+            manager.watched_paths(&id).await,
+            vec![path.clone(), path.join("a")]
+        );
+
+        // Polled, the watcher picks the change up on its own time
+        let watched_becomes = |expected: Vec<PathBuf>| {
+            let manager = manager.clone();
+            let id = id.clone();
+            async move {
+                for _ in 0..100 {
+                    if manager.watched_paths(&id).await == expected {
+                        return true;
+                    }
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                }
+                false
+            }
+        };
+        write_tab(&path, "b", "https://b.example/", true);
+        let with_b = vec![path.clone(), path.join("a"), path.join("b")];
+        assert!(watched_becomes(with_b).await);
+        std::fs::remove_dir_all(path.join("a")).unwrap();
+        assert!(watched_becomes(vec![path.clone(), path.join("b")]).await);
+    }
+
+    #[tokio::test]
+    async fn a_created_tab_gets_a_clean_directory_name() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        let manager = manager();
+        let id = manager.make_worksapce(&path).await.unwrap().id.clone();
+
+        let create = WorkspaceAction::CreateTab("  My Tab  ".into());
+        manager.apply_browser_action(&id, create).await.unwrap();
+        assert_eq!(dir_names(&path), vec![".mounttab.json", "My Tab"]);
+        let workspace = manager.get_workspace(&id).await.unwrap();
+        assert_eq!(workspace.tabs[0].name, "My Tab");
+    }
+
+    #[tokio::test]
+    async fn a_batch_creates_the_same_clean_names() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "a", "https://example.com/", true);
+        let manager = manager();
+        let id = manager.make_worksapce(&path).await.unwrap().id.clone();
+
+        let actions = [
+            WorkspaceAction::CreateTab("  My Tab  ".into()),
+            WorkspaceAction::ChangeTabUrl("  My Tab  ".into(), "https://rust-lang.org/".into()),
+            WorkspaceAction::RenameTab("a".into(), "Renamed...".into()),
+        ];
+        manager.apply_actions(&id, &actions).await.unwrap();
+        assert_eq!(dir_names(&path), [".mounttab.json", "My Tab", "Renamed"]);
+        let url = std::fs::read_to_string(path.join("My Tab").join("url")).unwrap();
+        assert_eq!(url, "https://rust-lang.org/");
+        let workspace = manager.get_workspace(&id).await.unwrap();
+        let mut names: Vec<_> = workspace.tabs.iter().map(|tab| tab.name.as_str()).collect();
+        names.sort();
+        assert_eq!(names, ["My Tab", "Renamed"]);
+    }
+
+    #[tokio::test]
+    async fn activating_tabs_keeps_them_most_recent_first() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        for name in ["a", "b", "c", "d"] {
+            write_tab(&path, name, "https://example.com/", true);
+        }
+        let manager = manager();
+        let id = manager.make_worksapce(&path).await.unwrap().id.clone();
+        assert_eq!(manager.mru_tabs(&id).await.unwrap(), Vec::<String>::new());
+
+        for tab in ["a", "b", "c", "a"] {
+            let activate = WorkspaceAction::ActivateTab(tab.into());
+            manager.apply_browser_action(&id, activate).await.unwrap();
+        }
+        assert_eq!(manager.mru_tabs(&id).await.unwrap(), vec!["a", "c", "b"]);
+
+        // Tabs that are gone drop out
+        std::fs::remove_dir_all(path.join("c")).unwrap();
+        assert_eq!(manager.mru_tabs(&id).await.unwrap(), vec!["a", "b"]);
+    }
+
+    fn notice(message: Option<ToBrowserMessage>) -> Option<String> {
+        match message? {
+            ToBrowserMessage::Notice(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn a_broadcast_reaches_every_browser() {
+        let manager = manager();
+        let mut first = connected(&manager, 1).await;
+        let mut second = connected(&manager, 2).await;
+
+        manager.broadcast("please reload".into()).await;
+        for browser in [&mut first, &mut second] {
+            assert_eq!(notice(browser.recv().await), Some("please reload".into()));
+        }
+    }
+
+    #[tokio::test]
+    async fn a_stalled_browser_doesnt_hold_up_the_others() {
+        let manager = WorkspaceManager::new(ManagerConfig {
+            load_ack_timeout_ms: 0,
+            outbound_queue_len: 4,
+            outbound_overflow: OverflowPolicy::DropNewest,
+            ..ManagerConfig::default()
+        });
+        // Never reads anything
+        let mut stalled = TestBrowser::connect(&manager, 1);
+        let mut healthy = connected(&manager, 2).await;
+
+        for i in 0..20 {
+            let started = std::time::Instant::now();
+            manager.broadcast(format!("notice {}", i)).await;
+            let received = notice(healthy.recv_within(Duration::from_secs(1)).await);
+            assert_eq!(received, Some(format!("notice {}", i)));
+            assert!(started.elapsed() < Duration::from_millis(500));
+        }
+
+        let mut queued = 0;
+        while stalled
+            .recv_within(Duration::from_millis(50))
+            .await
+            .is_some()
+        {
+            queued += 1;
+        }
+        assert!(queued <= 4, "{} messages were queued", queued);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn opposite_moves_at_the_same_time_dont_deadlock() {
+        let dir = TempDir::new();
+        let (left, right) = (dir.dir("left"), dir.dir("right"));
+        for i in 0..5 {
+            write_tab(&left, &format!("l{}", i), "https://left.example/", true);
+            write_tab(&right, &format!("r{}", i), "https://right.example/", true);
+        }
+        let manager = manager();
+        let left_id = manager.make_worksapce(&left).await.unwrap().id.clone();
+        let right_id = manager.make_worksapce(&right).await.unwrap().id.clone();
+
+        let mut moves = Vec::new();
+        for i in 0..5 {
+            for (from, tab, to) in [
+                (&left_id, format!("l{}", i), &right_id),
+                (&right_id, format!("r{}", i), &left_id),
+            ] {
+                let (manager, from, to) = (manager.clone(), from.clone(), to.clone());
+                moves.push(tokio::spawn(async move {
+                    manager.move_tab(&from, &tab, &to).await
+                }));
+            }
+        }
+        let all = futures_util::future::join_all(moves);
+        let results = tokio::time::timeout(Duration::from_secs(10), all)
+            .await
+            .expect("the moves deadlocked");
+        for result in results {
+            result.unwrap().unwrap();
+        }
+        let names = |path: &Path| {
+            dir_names(path)
+                .into_iter()
+                .filter(|name| !name.starts_with('.'))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&left), vec!["r0", "r1", "r2", "r3", "r4"]);
+        assert_eq!(names(&right), vec!["l0", "l1", "l2", "l3", "l4"]);
+    }
+
+    #[tokio::test]
+    async fn moves_going_in_a_circle_are_refused() {
+        let dir = TempDir::new();
+        let manager = manager();
+        let a = watched_workspace(&manager, &dir, "a").await;
+        let b = watched_workspace(&manager, &dir, "b").await;
+        let tab_move = |from: &str, to: &str| TabMove {
+            from: from.into(),
+            tab: "a".into(),
+            new_name: "moved".into(),
+            to: to.into(),
+        };
+
+        let circle = [tab_move(&a, &b), tab_move(&b, &a)];
+        // The circle is reported from the lowest id
+        let (low, high) = (a.clone().min(b.clone()), a.clone().max(b.clone()));
+        assert_eq!(
+            TabMove::find_cycle(&circle),
+            Some(vec![low.clone(), high, low])
+        );
+        let err = manager.move_batch(&circle).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(dir.path().join("a").join("a").is_dir());
+        assert!(dir.path().join("b").join("a").is_dir());
+
+        assert_eq!(TabMove::find_cycle(&[tab_move(&a, &b)]), None);
+    }
+
+    #[tokio::test]
+    async fn the_multiplexer_serves_several_workspaces() {
+        let dir = TempDir::new();
+        let manager = WorkspaceManager::new(ManagerConfig {
+            load_ack_timeout_ms: 0,
+            single_threaded: true,
+            ..ManagerConfig::default()
+        });
+        let first = watched_workspace(&manager, &dir, "first").await;
+        let second = watched_workspace(&manager, &dir, "second").await;
+        let mut browser = connected(&manager, 1).await;
+        start(&mut browser, &first).await;
+        start(&mut browser, &second).await;
+        assert!(manager.multiplexer.lock().unwrap().is_some());
+
+        for id in [&first, &second] {
+            let path = manager.get_workspace(id).await.unwrap().path.clone();
+            let url = format!("https://{}.example/", id);
+            edit_url(&path, "a", &url);
+            let changed = browser
+                .recv_until(|message| match message {
+                    ToBrowserMessage::TabFieldChanged { value, .. } if value == url.as_str() => {
+                        Some(value)
+                    }
+                    _ => None,
+                })
+                .await;
+            assert!(changed.is_some(), "no change from {}", id);
+        }
+    }
+
+    #[tokio::test]
+    async fn only_old_closed_tabs_are_stale() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "old", "https://old.example/", false);
+        write_tab(&path, "recent", "https://recent.example/", false);
+        write_tab(&path, "open", "https://open.example/", true);
+        let hour = Duration::from_secs(60 * 60);
+        std::fs::File::options()
+            .write(true)
+            .open(path.join("old").join("is_open"))
+            .unwrap()
+            .set_modified(SystemTime::now() - hour * 3)
+            .unwrap();
+        let clock = ManualClock::default();
+        let manager = manager_at(&clock);
+        let id = manager.make_worksapce(&path).await.unwrap().id.clone();
+        let names = |tabs: Vec<Tab>| tabs.into_iter().map(|tab| tab.name).collect::<Vec<_>>();
+
+        let stale = manager.stale_tabs(&id, hour).await.unwrap();
+        assert_eq!(names(stale), vec!["old"]);
+        clock.advance(hour * 2);
+        let stale = manager.stale_tabs(&id, hour).await.unwrap();
+        assert_eq!(names(stale), vec!["old", "recent"]);
+    }
+
+    // Whether an event of this kind for the file comes, other events are skipped
+    async fn raw_event_for(
+        events: &mut (impl Stream<Item = RawFsEvent> + Unpin),
+        file: &Path,
+        kind: RawFsEventKind,
+    ) -> bool {
+        while let Some(event) = next_within(events).await {
+            if event.kind == kind && event.paths.iter().any(|path| path == file) {
+                return true;
+            }
+        }
+        false
+    }
+
+    #[tokio::test]
+    async fn raw_events_come_for_a_create_modify_and_remove() {
+        let dir = TempDir::new();
+        let manager = manager();
+        let id = watched_workspace(&manager, &dir, "ws").await;
+        let mut events = manager.raw_fs_events(&id).await.unwrap();
+        let file = dir.path().join("ws").join("a").join("custom.txt");
+
+        std::fs::write(&file, "one").unwrap();
+        assert!(raw_event_for(&mut events, &file, RawFsEventKind::Created).await);
+        std::fs::write(&file, "two").unwrap();
+        assert!(raw_event_for(&mut events, &file, RawFsEventKind::Modified).await);
+        std::fs::remove_file(&file).unwrap();
+        assert!(raw_event_for(&mut events, &file, RawFsEventKind::Removed).await);
+    }
+
+    #[tokio::test]
+    async fn two_windows_round_trip_through_the_load() {
+        let dir = TempDir::new();
+        let manager = manager();
+        let id = watched_workspace(&manager, &dir, "ws").await;
+        let mut browser = connected(&manager, 1).await;
+        let geometry = |left| WindowGeometry {
+            left,
+            top: -20,
+            width: 1280,
+            height: 800,
+            state: Some("maximized".into()),
+        };
+        for (window, left) in [("1", 0), ("2", 1280), ("3", 2560)] {
+            let set = AppAction::SetWindowGeometry(id.clone(), window.into(), Some(geometry(left)));
+            browser.send(FromBrowserMessage::AppAction(set));
+        }
+        let closed = AppAction::SetWindowGeometry(id.clone(), "3".into(), None);
+        browser.send(FromBrowserMessage::AppAction(closed));
+
+        let loaded = start(&mut browser, &id).await;
+        let windows = BTreeMap::from([
+            ("1".to_string(), geometry(0)),
+            ("2".to_string(), geometry(1280)),
+        ]);
+        assert_eq!(
+            loaded.windows,
+            Some(WindowLayout {
+                version: WindowLayout::VERSION,
+                windows,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn a_tampered_file_fails_verification() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "a", "https://example.com/", true);
+        write_tab(&path, "b", "https://rust-lang.org/", false);
+        let manager = manager();
+        let id = manager.make_worksapce(&path).await.unwrap().id.clone();
+
+        assert_eq!(manager.write_manifest(&id).await.unwrap(), 4);
+        assert!(manager.verify_manifest(&id).await.unwrap().is_clean());
+
+        std::fs::write(path.join("b").join("url"), "https://rust-lang.org/?").unwrap();
+        std::fs::remove_file(path.join("a").join("is_open")).unwrap();
+        let report = manager.verify_manifest(&id).await.unwrap();
+        assert_eq!(report.mismatched, ["b/url"]);
+        assert_eq!(report.missing, ["a/is_open"]);
+        assert!(report.added.is_empty());
+    }
+
+    #[tokio::test]
+    async fn watcher_actions_wait_for_the_load_ack() {
+        let dir = TempDir::new();
+        let manager = WorkspaceManager::new(ManagerConfig {
+            load_ack_timeout_ms: 30_000,
+            ..ManagerConfig::default()
+        });
+        let id = watched_workspace(&manager, &dir, "ws").await;
+        let mut browser = connected(&manager, 1).await;
+        start(&mut browser, &id).await;
+
+        edit_url(
+            &paths(&manager).await[0],
+            "a",
+            "https://example.com/changed",
+        );
+        assert!(!got_actions(&mut browser).await);
+
+        // A write can show up as more than one event, then they're replayed together
+        browser.send(FromBrowserMessage::LoadAcknowledged(id.clone()));
+        let replayed = browser
+            .recv_until(|message| match message {
+                ToBrowserMessage::TabFieldChanged { tab, value, .. } => Some(vec![(tab, value)]),
+                ToBrowserMessage::Transaction { actions, .. } => Some(
+                    actions
+                        .into_iter()
+                        .map(|action| match action {
+                            WorkspaceAction::ChangeTabUrl(tab, url) => {
+                                (tab, serde_json::json!(url))
+                            }
+                            other => panic!("unexpected {:?}", other),
+                        })
+                        .collect(),
+                ),
+                _ => None,
+            })
+            .await
+            .expect("nothing was replayed");
+        let changed = (
+            "a".to_string(),
+            serde_json::json!("https://example.com/changed"),
+        );
+        assert!(
+            replayed.iter().all(|change| *change == changed),
+            "{:?}",
+            replayed
+        );
+    }
+
+    #[tokio::test]
+    async fn a_renamed_root_stops_the_watcher_with_an_error() {
+        let dir = TempDir::new();
+        let manager = manager();
+        let id = watched_workspace(&manager, &dir, "ws").await;
+        let mut browser = connected(&manager, 1).await;
+        start(&mut browser, &id).await;
+
+        std::fs::rename(dir.path().join("ws"), dir.path().join("renamed")).unwrap();
+        let error = tokio::time::timeout(Duration::from_secs(15), async {
+            loop {
+                match browser.rx.recv().await {
+                    Some(ToBrowserMessage::WatcherError {
+                        workspace_id,
+                        error,
+                    }) => break (workspace_id, error),
+                    Some(_) => {}
+                    None => panic!("the browser was dropped"),
+                }
+            }
+        })
+        .await
+        .expect("no WatcherError");
+        assert_eq!(error.0, id);
+        assert!(error.1.contains("relocate_workspace"), "{}", error.1);
+        assert!(!manager.watchers.read().await.contains_key(&id));
+    }
+
+    #[tokio::test]
+    async fn a_relocated_root_keeps_being_watched() {
+        let dir = TempDir::new();
+        let manager = manager();
+        let id = watched_workspace(&manager, &dir, "ws").await;
+        let mut browser = connected(&manager, 1).await;
+        start(&mut browser, &id).await;
+
+        let new_path = dir.path().join("moved");
+        manager.relocate_workspace(&id, &new_path).await.unwrap();
+        assert_eq!(paths(&manager).await, [new_path.to_str().unwrap()]);
+
+        edit_url(new_path.to_str().unwrap(), "a", "https://example.com/moved");
+        let url = browser
+            .recv_until(|message| match message {
+                ToBrowserMessage::TabFieldChanged { value, .. } => Some(value),
+                ToBrowserMessage::WatcherError { error, .. } => panic!("{}", error),
+                _ => None,
+            })
+            .await;
+        assert_eq!(url, Some(serde_json::json!("https://example.com/moved")));
+    }
+
+    #[tokio::test]
+    async fn writes_and_reads_count_against_their_tab() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "a", "https://example.com/", true);
+        write_tab(&path, "b", "https://rust-lang.org/", true);
+        let manager = manager();
+        let id = manager.make_worksapce(&path).await.unwrap().id.clone();
+        let counts = |tab: &str| {
+            manager
+                .tab_io_counts(&id)
+                .get(tab)
+                .copied()
+                .unwrap_or_default()
+        };
+        let (a, b) = (counts("a"), counts("b"));
+
+        for n in 0..3 {
+            let url = format!("https://example.com/{}", n);
+            let action = WorkspaceAction::ChangeTabUrl("a".into(), url);
+            manager.apply_browser_action(&id, action).await.unwrap();
+        }
+        let action = WorkspaceAction::ChangeTabUrl("b".into(), "https://crates.io/".into());
+        manager.apply_browser_action(&id, action).await.unwrap();
+        assert_eq!(
+            counts("a"),
+            TabIoCounts {
+                writes: a.writes + 3,
+                ..a
+            }
+        );
+        assert_eq!(
+            counts("b"),
+            TabIoCounts {
+                writes: b.writes + 1,
+                ..b
+            }
+        );
+
+        // Starting reads every tab again
+        let mut browser = connected(&manager, 1).await;
+        start(&mut browser, &id).await;
+        assert_eq!(
+            counts("a"),
+            TabIoCounts {
+                reads: a.reads + 1,
+                writes: a.writes + 3
+            }
+        );
+        assert_eq!(
+            counts("b"),
+            TabIoCounts {
+                reads: b.reads + 1,
+                writes: b.writes + 1
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn a_stale_precondition_is_rejected() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "a", "https://example.com/", true);
+        let manager = manager();
+        let id = manager.make_worksapce(&path).await.unwrap().id.clone();
+        let change = |url: &str| WorkspaceAction::ChangeTabUrl("a".into(), url.into());
+        let expecting = |url: &str| TabPrecondition {
+            url: Some(url.into()),
+            is_open: Some(true),
+            ..TabPrecondition::default()
+        };
+
+        // Someone edited the url on disk after the browser last saw it
+        edit_url(path.to_str().unwrap(), "a", "https://example.com/on-disk");
+        let stale = manager
+            .apply_browser_action_if(
+                &id,
+                change("https://example.com/browser"),
+                Some(&expecting("https://example.com/")),
+            )
+            .await
+            .unwrap_err();
+        assert!(stale.to_string().contains("url is"), "{}", stale);
+        let url = std::fs::read_to_string(path.join("a").join("url")).unwrap();
+        assert_eq!(url, "https://example.com/on-disk");
+
+        manager
+            .apply_browser_action_if(
+                &id,
+                change("https://example.com/browser"),
+                Some(&expecting("https://example.com/on-disk")),
+            )
+            .await
+            .unwrap();
+        let url = std::fs::read_to_string(path.join("a").join("url")).unwrap();
+        assert_eq!(url.trim(), "https://example.com/browser");
+    }
+
+    #[tokio::test]
+    async fn a_durable_batch_lands_like_separate_writes() {
+        let dir = TempDir::new();
+        let manager = manager();
+        let mut ids = Vec::new();
+        for (name, durable) in [("durable", true), ("plain", false)] {
+            let path = dir.dir(name);
+            write_tab(&path, "a", "https://example.com/", true);
+            write_tab(&path, "b", "https://rust-lang.org/", false);
+            WorkspaceConfig::update(&path, |config| config.durable_writes = durable).unwrap();
+            ids.push(manager.make_worksapce(&path).await.unwrap().id.clone());
+        }
+        let actions = [
+            WorkspaceAction::CreateTab("c".into()),
+            WorkspaceAction::ChangeTabUrl("c".into(), "https://crates.io/".into()),
+            WorkspaceAction::OpenTab("c".into()),
+            WorkspaceAction::CloseTab("a".into()),
+            WorkspaceAction::RenameTab("b".into(), "d".into()),
+            WorkspaceAction::SetTabNotes("d".into(), "read later".into()),
+            WorkspaceAction::ReorderTab("c".into(), 0),
+        ];
+
+        manager.apply_actions(&ids[0], &actions).await.unwrap();
+        for action in &actions {
+            manager
+                .apply_actions(&ids[1], std::slice::from_ref(action))
+                .await
+                .unwrap();
+        }
+        let mut on_disk = Vec::new();
+        for id in &ids {
+            let workspace = manager.get_workspace(id).await.unwrap().reload_from_fs();
+            on_disk.push(serde_json::to_value(&workspace.tabs).unwrap());
+        }
+        assert_eq!(on_disk[0], on_disk[1]);
+        let names: Vec<_> = on_disk[0]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|tab| tab["name"].clone())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                serde_json::json!("c"),
+                serde_json::json!("a"),
+                serde_json::json!("d")
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn actions_wait_out_a_pending_remove() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "a", "https://example.com/", true);
+        let manager = manager();
+        let id = manager.make_worksapce(&path).await.unwrap().id.clone();
+        let change =
+            |tab: &str| WorkspaceAction::ChangeTabUrl(tab.into(), "https://rust-lang.org/".into());
+
+        let removing = manager.begin_delete(&id, "a").unwrap();
+        let refused = manager
+            .apply_browser_action(&id, change("a"))
+            .await
+            .unwrap_err();
+        assert!(refused.to_string().contains("being deleted"), "{}", refused);
+        assert!(manager.apply_actions(&id, &[change("a")]).await.is_err());
+        let again = WorkspaceAction::RemoveTab("a".into());
+        assert!(manager.apply_browser_action(&id, again).await.is_err());
+        assert_eq!(
+            std::fs::read_to_string(path.join("a").join("url")).unwrap(),
+            "https://example.com/"
+        );
+        drop(removing);
+        manager
+            .apply_browser_action(&id, change("a"))
+            .await
+            .unwrap();
+
+        // A tab that's already gone from disk is matched on the name it'd be made with
+        let gone = manager.begin_delete(&id, "My Tab").unwrap();
+        assert!(manager
+            .apply_browser_action(&id, change("  My Tab  "))
+            .await
+            .is_err());
+        assert!(!path.join("My Tab").exists());
+        drop(gone);
+    }
+
+    #[tokio::test]
+    async fn two_clients_see_disjoint_workspaces() {
+        let dir = TempDir::new();
+        let manager = manager();
+        let mut ids = HashMap::new();
+        for (name, owner) in [
+            ("alice", Some("alice")),
+            ("bob", Some("bob")),
+            ("shared", None),
+        ] {
+            let path = dir.dir(name);
+            write_tab(&path, "a", "https://example.com/", true);
+            let owner = owner.map(str::to_string);
+            WorkspaceConfig::update(&path, |config| config.owner = owner).unwrap();
+            ids.insert(
+                name,
+                manager.make_worksapce(&path).await.unwrap().id.clone(),
+            );
+        }
+
+        let mut seen = Vec::new();
+        for (browser_id, client) in [(1, "alice"), (2, "bob")] {
+            let mut browser = connected(&manager, browser_id).await;
+            browser.send(hello(None, Some(client)));
+            let visible = browser
+                .recv_until(|message| match message {
+                    ToBrowserMessage::AllWorkspaces(workspaces) => Some(workspaces),
+                    _ => None,
+                })
+                .await
+                .unwrap();
+            let mut visible: Vec<String> = visible.iter().map(|w| w.id.clone()).collect();
+            visible.sort();
+            let mut expected = vec![ids[client].clone(), ids["shared"].clone()];
+            expected.sort();
+            assert_eq!(visible, expected);
+            welcome(&mut browser).await;
+            seen.push(browser);
+        }
+
+        // Another client's workspace looks like it doesn't exist
+        let bob = &mut seen[1];
+        bob.send(FromBrowserMessage::StartWorkspace(ids["alice"].clone()));
+        let refused = notice(bob.recv().await).unwrap();
+        assert!(refused.contains("No workspace"), "{}", refused);
+        let close = WorkspaceAction::CloseTab("a".into());
+        bob.send(FromBrowserMessage::WorkspaceAction(
+            ids["alice"].clone(),
+            close,
+        ));
+        assert!(matches!(
+            bob.recv().await,
+            Some(ToBrowserMessage::ActionError { .. })
+        ));
+        let alice_tab = dir.path().join("alice").join("a").join("is_open");
+        assert_eq!(std::fs::read_to_string(alice_tab).unwrap(), "1");
+    }
+
+    #[tokio::test]
+    async fn an_expired_tab_is_closed_with_the_expired_reason() {
+        let dir = TempDir::new();
+        let path = dir.dir("ws");
+        write_tab(&path, "long", "https://long.example/", true);
+        write_tab(&path, "short", "https://short.example/", true);
+        std::fs::File::options()
+            .write(true)
+            .open(path.join("long").join("is_open"))
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(3 * 60 * 60))
+            .unwrap();
+        WorkspaceConfig::update(&path, |config| {
+            config.url_settle_ms = 0;
+            config.close_open_tabs_after_secs = 60 * 60;
+        })
+        .unwrap();
+        let clock = ManualClock::default();
+        let manager = manager_at(&clock);
+        let id = manager.make_worksapce(&path).await.unwrap().id.clone();
+        let mut browser = connected(&manager, 1).await;
+        start(&mut browser, &id).await;
+
+        let expired = manager.expire_open_tabs().await;
+        assert_eq!(expired, [(id.clone(), "long".to_string())]);
+        let close = browser
+            .recv_until(|message| match message {
+                ToBrowserMessage::WorkspaceAction(action) => Some(action),
+                _ => None,
+            })
+            .await;
+        assert_eq!(
+            close,
+            Some(WorkspaceAction::CloseTabBecause(
+                "long".into(),
+                CloseReason::Expired
+            ))
+        );
+        assert_eq!(
+            std::fs::read_to_string(path.join("long").join("is_open")).unwrap(),
+            "0"
+        );
+
+        // The watcher seeing the close is an echo, it's only recorded once
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let history = manager.export_history(&id).await.unwrap();
+        assert_eq!(
+            history,
+            [HistoryEntry::new(
+                "long",
+                "CloseTab",
+                CloseReason::Expired,
+                clock.now()
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn a_connection_reports_its_lifecycle_in_order() {
+        let dir = TempDir::new();
+        let manager = manager();
+        let id = watched_workspace(&manager, &dir, "ws").await;
+        let mut events = manager.connection_events();
+
+        let mut browser = connected(&manager, 1).await;
+        browser.send(hello(None, None));
+        welcome(&mut browser).await;
+        start(&mut browser, &id).await;
+        browser.send(FromBrowserMessage::StartWorkspace("missing".into()));
+        notice(browser.recv().await).unwrap();
+        drop(browser);
+
+        let mut seen = Vec::new();
+        while let Some(lifecycle) = next_within(&mut events).await {
+            assert_eq!(lifecycle.browser_id, 1);
+            let done = lifecycle.event == ConnectionEvent::Disconnected;
+            seen.push(lifecycle.event);
+            if done {
+                break;
+            }
+        }
+        assert_eq!(
+            seen,
+            [
+                ConnectionEvent::Connected,
+                ConnectionEvent::HelloReceived {
+                    asked: PROTOCOL_VERSION,
+                    using: PROTOCOL_VERSION,
+                },
+                ConnectionEvent::WorkspaceStarted(id),
+                ConnectionEvent::Error("No workspace with id: missing".into()),
+                ConnectionEvent::Disconnected,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn a_client_cant_reach_a_sibling_workspace_through_dot_dot() {
+        let dir = TempDir::new();
+        let manager = manager();
+        let mut ids = HashMap::new();
+        for name in ["alice", "bob"] {
+            let path = dir.dir(name);
+            write_tab(&path, "a", "https://example.com/", true);
+            WorkspaceConfig::update(&path, |config| config.owner = Some(name.to_string())).unwrap();
+            ids.insert(
+                name,
+                manager.make_worksapce(&path).await.unwrap().id.clone(),
+            );
+        }
+        let mut alice = connected(&manager, 1).await;
+        alice.send(hello(None, Some("alice")));
+        welcome(&mut alice).await;
+
+        let sneaky = [
+            WorkspaceAction::ChangeTabUrl("../bob/a".into(), "https://evil.example/".into()),
+            WorkspaceAction::CreateTab("../bob/x".into()),
+            WorkspaceAction::RenameTab("a".into(), "../bob/b".into()),
+            WorkspaceAction::CloseTab("/tmp".into()),
+            WorkspaceAction::CloseTab("..".into()),
+        ];
+        for action in sneaky.clone() {
+            alice.send(FromBrowserMessage::WorkspaceAction(
+                ids["alice"].clone(),
+                action,
+            ));
+            let refused = alice
+                .recv_until(|message| match message {
+                    ToBrowserMessage::ActionError { error, .. } => Some(error),
+                    _ => None,
+                })
+                .await
+                .unwrap();
+            assert!(refused.contains("isn't a tab name"), "{}", refused);
+        }
+        for action in sneaky {
+            let refused = manager
+                .apply_actions(&ids["alice"], &[action])
+                .await
+                .unwrap_err();
+            assert_eq!(refused.kind(), io::ErrorKind::InvalidInput);
+        }
+
+        let bob = dir.path().join("bob");
+        assert_eq!(
+            std::fs::read_to_string(bob.join("a").join("url")).unwrap(),
+            "https://example.com/"
+        );
+        assert_eq!(dir_names(&bob), [".mounttab.json", "a"]);
+        assert_eq!(
+            dir_names(&dir.path().join("alice")),
+            [".mounttab.json", "a"]
+        );
+    }
+
+    // How many reads a second `readers` tasks manage together
+    async fn read_throughput<F>(manager: &WorkspaceManager, readers: usize, read: F) -> f64
+    where
+        F: Fn(&WorkspaceManager) -> futures_util::future::BoxFuture<'_, usize>
+            + Copy
+            + Send
+            + 'static,
+    {
+        const READS: usize = 100;
+        let started = std::time::Instant::now();
+        let tasks: Vec<_> = (0..readers)
+            .map(|_| {
+                let manager = manager.clone();
+                tokio::spawn(async move {
+                    let mut tabs = 0;
+                    for _ in 0..READS {
+                        tabs += read(&manager).await;
+                    }
+                    tabs
+                })
+            })
+            .collect();
+        for task in tasks {
+            assert!(task.await.unwrap() > 0);
+        }
+        (readers * READS) as f64 / started.elapsed().as_secs_f64()
+    }
+
+    // cargo test --release -- --ignored --nocapture workspace_reads_clone_pointers
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    #[ignore]
+    async fn workspace_reads_clone_pointers_not_tabs() {
+        let manager = manager();
+        let tabs: Vec<Tab> = (0..200)
+            .map(|n| Tab {
+                name: format!("tab-{}", n),
+                url: format!("https://example.com/{}", n),
+                is_open: n % 3 == 0,
+                notes: Some("some notes about the page".repeat(4)),
+                ..Tab::default()
+            })
+            .collect();
+        {
+            let mut workspaces = manager.workspaces.write().await;
+            for n in 0..100 {
+                workspaces.push(Arc::new(Workspace {
+                    id: format!("ws-{}", n),
+                    name: format!("ws-{}", n),
+                    path: format!("/nowhere/ws-{}", n),
+                    tabs: tabs.clone(),
+                    malformed_tabs: Vec::new(),
+                    tab_errors: BTreeMap::new(),
+                    config: WorkspaceConfig::default(),
+                    evicted: false,
+                }));
+            }
+        }
+
+        // What every read cost before workspaces were kept behind Arcs
+        let deep_copy = read_throughput(&manager, 4, |manager| {
+            Box::pin(async move {
+                let workspaces = manager.workspaces.read().await;
+                let copied: Vec<Workspace> = workspaces.iter().map(|w| (**w).clone()).collect();
+                copied.iter().map(|w| w.tabs.len()).sum()
+            })
+        })
+        .await;
+        let shared = read_throughput(&manager, 4, |manager| {
+            Box::pin(async move {
+                let workspaces = manager.get_all_workspaces().await;
+                workspaces.iter().map(|w| w.tabs.len()).sum()
+            })
+        })
+        .await;
+        println!(
+            "100 workspaces of 200 tabs, 4 readers: {:.0} reads/s deep copied, {:.0} reads/s as Arcs",
+            deep_copy, shared
+        );
+        assert!(shared > deep_copy);
+    }
+
+    // cargo test --release -- --ignored --nocapture a_durable_burst
+    #[tokio::test]
+    #[ignore]
+    async fn a_durable_burst_syncs_faster_as_one_batch() {
+        const TABS: usize = 20;
+        const WRITES: usize = 200;
+        let dir = TempDir::new();
+        let manager = manager();
+        let mut ids = Vec::new();
+        for name in ["separate", "batched"] {
+            let path = dir.dir(name);
+            for n in 0..TABS {
+                write_tab(&path, &format!("tab-{}", n), "https://example.com/", true);
+            }
+            WorkspaceConfig::update(&path, |config| config.durable_writes = true).unwrap();
+            ids.push(manager.make_worksapce(&path).await.unwrap().id.clone());
+        }
+        let burst: Vec<WorkspaceAction> = (0..WRITES)
+            .map(|n| {
+                let url = format!("https://example.com/{}", n);
+                WorkspaceAction::ChangeTabUrl(format!("tab-{}", n % TABS), url)
+            })
+            .collect();
+
+        let started = std::time::Instant::now();
+        for action in &burst {
+            manager
+                .apply_actions(&ids[0], std::slice::from_ref(action))
+                .await
+                .unwrap();
+        }
+        let separate = WRITES as f64 / started.elapsed().as_secs_f64();
+        let started = std::time::Instant::now();
+        manager.apply_actions(&ids[1], &burst).await.unwrap();
+        let batched = WRITES as f64 / started.elapsed().as_secs_f64();
+        println!(
+            "{} durable url writes over {} tabs: {:.0} writes/s synced one by one, {:.0} writes/s synced once",
+            WRITES, TABS, separate, batched
+        );
+
+        // Both end up with the same urls
+        for name in ["separate", "batched"] {
+            let url =
+                std::fs::read_to_string(dir.path().join(name).join("tab-0").join("url")).unwrap();
+            assert_eq!(url, format!("https://example.com/{}", WRITES - TABS));
+        }
+        assert!(batched > separate);
+    }
 }
@@ -0,0 +1,790 @@
+use crate::history::CloseReason;
+use crate::model::{
+    ApiWorkspace, AppAction, BrowserStatus, FromBrowserMessage, Hello, Tab, TabField,
+    TabPrecondition, ToBrowserMessage, WindowGeometry, WindowLayout, Workspace, WorkspaceAction,
+};
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// The newest protocol the daemon speaks
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/** How messages look on the wire.
+ * V1 is serde's default, enums are `{"WorkspaceAction": {"OpenTab": "gandalf"}}`.
+ * V2 tags every enum with a camelCase `type` and puts the payload in `data`, an object
+ * with camelCase fields:
+ * `{"type": "workspaceAction", "data": {"type": "openTab", "data": {"tab": "gandalf"}}}`.
+ * Browsers that never send a `Hello` get V1 so old extensions keep working. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolVersion {
+    V1,
+    V2,
+}
+
+impl ProtocolVersion {
+    /// The version both sides understand
+    pub fn negotiate(requested: u32) -> ProtocolVersion {
+        match requested.min(PROTOCOL_VERSION) {
+            0 | 1 => ProtocolVersion::V1,
+            _ => ProtocolVersion::V2,
+        }
+    }
+
+    pub fn number(&self) -> u32 {
+        match self {
+            ProtocolVersion::V1 => 1,
+            ProtocolVersion::V2 => 2,
+        }
+    }
+}
+
+pub fn encode(message: &ToBrowserMessage, version: ProtocolVersion) -> serde_json::Result<String> {
+    match version {
+        ProtocolVersion::V1 => match v1_action(message) {
+            Some(action) => serde_json::to_string(&ToBrowserMessage::WorkspaceAction(action)),
+            None => serde_json::to_string(message),
+        },
+        ProtocolVersion::V2 => serde_json::to_string(&V2ToBrowser::from(message.clone())),
+    }
+}
+
+// V1 extensions don't know field changes, they get the action the change came from
+fn v1_action(message: &ToBrowserMessage) -> Option<WorkspaceAction> {
+    let ToBrowserMessage::TabFieldChanged { tab, field, value } = message else {
+        return None;
+    };
+    match (field, value) {
+        (TabField::Url, Value::String(url)) => {
+            Some(WorkspaceAction::ChangeTabUrl(tab.clone(), url.clone()))
+        }
+        (TabField::Incognito, Value::Bool(incognito)) => {
+            Some(WorkspaceAction::SetTabIncognito(tab.clone(), *incognito))
+        }
+        _ => None,
+    }
+}
+
+/// Accepts both versions, a V2 message is always an object with a `type`
+pub fn decode(message: &str) -> serde_json::Result<FromBrowserMessage> {
+    let value: Value = serde_json::from_str(message)?;
+    if matches!(value.get("type"), Some(Value::String(_))) {
+        return serde_json::from_value::<V2FromBrowser>(value).map(FromBrowserMessage::from);
+    }
+    serde_json::from_value(value)
+}
+
+/* The V2 shapes of what goes on the wire. They mirror the V1 types variant for variant,
+ * payloads are objects with camelCase fields instead of positional arrays, so JS reads
+ * `data.url` rather than `data[1]`. Values that belong to the user (tab meta, session
+ * and tab names, ...) are never rewritten. */
+
+#[derive(Serialize, Deserialize)]
+#[serde(
+    tag = "type",
+    content = "data",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+enum V2Action {
+    OpenTab { tab: String },
+    CloseTab { tab: String },
+    CloseTabBecause { tab: String, reason: CloseReason },
+    ChangeTabUrl { tab: String, url: String },
+    CreateTab { tab: String },
+    RemoveTab { tab: String },
+    SetTabIncognito { tab: String, incognito: bool },
+    SetTabMeta { tab: String, meta: Value },
+    SetTabNotes { tab: String, notes: String },
+    SetTabFavicon { tab: String, favicon: String },
+    SetTabScroll { tab: String, scroll: f64 },
+    ReorderTab { tab: String, index: usize },
+    RenameTab { from: String, to: String },
+    ActivateTab { tab: String },
+}
+
+impl From<WorkspaceAction> for V2Action {
+    fn from(action: WorkspaceAction) -> V2Action {
+        match action {
+            WorkspaceAction::OpenTab(tab) => V2Action::OpenTab { tab },
+            WorkspaceAction::CloseTab(tab) => V2Action::CloseTab { tab },
+            WorkspaceAction::CloseTabBecause(tab, reason) => {
+                V2Action::CloseTabBecause { tab, reason }
+            }
+            WorkspaceAction::ChangeTabUrl(tab, url) => V2Action::ChangeTabUrl { tab, url },
+            WorkspaceAction::CreateTab(tab) => V2Action::CreateTab { tab },
+            WorkspaceAction::RemoveTab(tab) => V2Action::RemoveTab { tab },
+            WorkspaceAction::SetTabIncognito(tab, incognito) => {
+                V2Action::SetTabIncognito { tab, incognito }
+            }
+            WorkspaceAction::SetTabMeta(tab, meta) => V2Action::SetTabMeta { tab, meta },
+            WorkspaceAction::SetTabNotes(tab, notes) => V2Action::SetTabNotes { tab, notes },
+            WorkspaceAction::SetTabFavicon(tab, favicon) => {
+                V2Action::SetTabFavicon { tab, favicon }
+            }
+            WorkspaceAction::SetTabScroll(tab, scroll) => V2Action::SetTabScroll { tab, scroll },
+            WorkspaceAction::ReorderTab(tab, index) => V2Action::ReorderTab { tab, index },
+            WorkspaceAction::RenameTab(from, to) => V2Action::RenameTab { from, to },
+            WorkspaceAction::ActivateTab(tab) => V2Action::ActivateTab { tab },
+        }
+    }
+}
+
+impl From<V2Action> for WorkspaceAction {
+    fn from(action: V2Action) -> WorkspaceAction {
+        match action {
+            V2Action::OpenTab { tab } => WorkspaceAction::OpenTab(tab),
+            V2Action::CloseTab { tab } => WorkspaceAction::CloseTab(tab),
+            V2Action::CloseTabBecause { tab, reason } => {
+                WorkspaceAction::CloseTabBecause(tab, reason)
+            }
+            V2Action::ChangeTabUrl { tab, url } => WorkspaceAction::ChangeTabUrl(tab, url),
+            V2Action::CreateTab { tab } => WorkspaceAction::CreateTab(tab),
+            V2Action::RemoveTab { tab } => WorkspaceAction::RemoveTab(tab),
+            V2Action::SetTabIncognito { tab, incognito } => {
+                WorkspaceAction::SetTabIncognito(tab, incognito)
+            }
+            V2Action::SetTabMeta { tab, meta } => WorkspaceAction::SetTabMeta(tab, meta),
+            V2Action::SetTabNotes { tab, notes } => WorkspaceAction::SetTabNotes(tab, notes),
+            V2Action::SetTabFavicon { tab, favicon } => {
+                WorkspaceAction::SetTabFavicon(tab, favicon)
+            }
+            V2Action::SetTabScroll { tab, scroll } => WorkspaceAction::SetTabScroll(tab, scroll),
+            V2Action::ReorderTab { tab, index } => WorkspaceAction::ReorderTab(tab, index),
+            V2Action::RenameTab { from, to } => WorkspaceAction::RenameTab(from, to),
+            V2Action::ActivateTab { tab } => WorkspaceAction::ActivateTab(tab),
+        }
+    }
+}
+
+// Borrowed camelCase views of the structs inside the messages, no tab gets copied
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct V2Tab<'a> {
+    name: &'a str,
+    url: &'a str,
+    is_open: bool,
+    incognito: bool,
+    meta: &'a Option<Value>,
+    notes: &'a Option<String>,
+    favicon: &'a Option<String>,
+    scroll: Option<f64>,
+    is_blank: bool,
+}
+
+impl<'a> From<&'a Tab> for V2Tab<'a> {
+    fn from(tab: &'a Tab) -> V2Tab<'a> {
+        V2Tab {
+            name: &tab.name,
+            url: &tab.url,
+            is_open: tab.is_open,
+            incognito: tab.incognito,
+            meta: &tab.meta,
+            notes: &tab.notes,
+            favicon: &tab.favicon,
+            scroll: tab.scroll,
+            is_blank: tab.is_blank,
+        }
+    }
+}
+
+fn v2_tabs(tabs: &[Tab]) -> Vec<V2Tab<'_>> {
+    tabs.iter().map(V2Tab::from).collect()
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct V2Workspace<'a> {
+    id: &'a str,
+    name: &'a str,
+    path: &'a str,
+    tabs: Vec<V2Tab<'a>>,
+    malformed_tabs: &'a [String],
+    tab_errors: &'a BTreeMap<String, String>,
+    evicted: bool,
+}
+
+fn v2_workspaces<S: Serializer>(workspaces: &[Arc<Workspace>], s: S) -> Result<S::Ok, S::Error> {
+    s.collect_seq(workspaces.iter().map(|workspace| V2Workspace {
+        id: &workspace.id,
+        name: &workspace.name,
+        path: &workspace.path,
+        tabs: v2_tabs(&workspace.tabs),
+        malformed_tabs: &workspace.malformed_tabs,
+        tab_errors: &workspace.tab_errors,
+        evicted: workspace.evicted,
+    }))
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct V2ApiWorkspace<'a> {
+    id: &'a str,
+    name: &'a str,
+    tabs: Vec<V2Tab<'a>>,
+    windows: &'a Option<WindowLayout>,
+}
+
+fn v2_api_workspace<S: Serializer>(workspace: &ApiWorkspace, s: S) -> Result<S::Ok, S::Error> {
+    V2ApiWorkspace {
+        id: &workspace.id,
+        name: &workspace.name,
+        tabs: v2_tabs(&workspace.tabs),
+        windows: &workspace.windows,
+    }
+    .serialize(s)
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct V2BrowserStatus<'a> {
+    browser_id: usize,
+    connected_workspaces: &'a [String],
+}
+
+fn v2_status<S: Serializer>(status: &BrowserStatus, s: S) -> Result<S::Ok, S::Error> {
+    V2BrowserStatus {
+        browser_id: status.browser_id,
+        connected_workspaces: &status.connected_workspaces,
+    }
+    .serialize(s)
+}
+
+#[derive(Serialize)]
+#[serde(
+    tag = "type",
+    content = "data",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+enum V2ToBrowser {
+    AllWorkspaces(#[serde(serialize_with = "v2_workspaces")] Vec<Arc<Workspace>>),
+    WorkspaceAction(V2Action),
+    LoadWorkspace(#[serde(serialize_with = "v2_api_workspace")] ApiWorkspace),
+    TabFieldChanged {
+        tab: String,
+        field: TabField,
+        value: Value,
+    },
+    Status(#[serde(serialize_with = "v2_status")] BrowserStatus),
+    Transaction {
+        id: usize,
+        actions: Vec<V2Action>,
+    },
+    Welcome {
+        protocol_version: u32,
+        token: String,
+    },
+    Sessions(BTreeMap<String, Vec<String>>),
+    ActionError {
+        workspace_id: String,
+        action: V2Action,
+        error: String,
+    },
+    Notice(String),
+    WatcherError {
+        workspace_id: String,
+        error: String,
+    },
+    Changes {
+        workspace_id: String,
+        seq: u64,
+        actions: Option<Vec<V2Action>>,
+    },
+}
+
+fn v2_actions(actions: Vec<WorkspaceAction>) -> Vec<V2Action> {
+    actions.into_iter().map(V2Action::from).collect()
+}
+
+impl From<ToBrowserMessage> for V2ToBrowser {
+    fn from(message: ToBrowserMessage) -> V2ToBrowser {
+        match message {
+            ToBrowserMessage::AllWorkspaces(workspaces) => V2ToBrowser::AllWorkspaces(workspaces),
+            ToBrowserMessage::WorkspaceAction(action) => {
+                V2ToBrowser::WorkspaceAction(action.into())
+            }
+            ToBrowserMessage::LoadWorkspace(workspace) => V2ToBrowser::LoadWorkspace(workspace),
+            ToBrowserMessage::TabFieldChanged { tab, field, value } => {
+                V2ToBrowser::TabFieldChanged { tab, field, value }
+            }
+            ToBrowserMessage::Status(status) => V2ToBrowser::Status(status),
+            ToBrowserMessage::Transaction { id, actions } => V2ToBrowser::Transaction {
+                id,
+                actions: v2_actions(actions),
+            },
+            ToBrowserMessage::Welcome {
+                protocol_version,
+                token,
+            } => V2ToBrowser::Welcome {
+                protocol_version,
+                token,
+            },
+            ToBrowserMessage::Sessions(sessions) => V2ToBrowser::Sessions(sessions),
+            ToBrowserMessage::ActionError {
+                workspace_id,
+                action,
+                error,
+            } => V2ToBrowser::ActionError {
+                workspace_id,
+                action: action.into(),
+                error,
+            },
+            ToBrowserMessage::Notice(notice) => V2ToBrowser::Notice(notice),
+            ToBrowserMessage::WatcherError {
+                workspace_id,
+                error,
+            } => V2ToBrowser::WatcherError {
+                workspace_id,
+                error,
+            },
+            ToBrowserMessage::Changes {
+                workspace_id,
+                seq,
+                actions,
+            } => V2ToBrowser::Changes {
+                workspace_id,
+                seq,
+                actions: actions.map(v2_actions),
+            },
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(
+    tag = "type",
+    content = "data",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+enum V2AppAction {
+    OpenWorkspace {
+        path: String,
+    },
+    WorkspaceAction {
+        workspace_id: String,
+        action: V2Action,
+    },
+    CloseWorkspace {
+        workspace_id: String,
+    },
+    ReorderWorkspace {
+        workspace_id: String,
+        index: usize,
+    },
+    ReloadWorkspace {
+        workspace_id: String,
+    },
+    PinWatchedTabs {
+        workspace_id: String,
+        tabs: Option<Vec<String>>,
+    },
+    SaveSession {
+        name: String,
+        workspace_ids: Vec<String>,
+    },
+    DeleteSession {
+        name: String,
+    },
+    OpenSession {
+        name: String,
+    },
+    CloseSession {
+        name: String,
+    },
+    MoveTab {
+        from: String,
+        tab: String,
+        to: String,
+    },
+    MergeWorkspaces {
+        from: String,
+        into: String,
+    },
+    SetWindowGeometry {
+        workspace_id: String,
+        window: String,
+        geometry: Option<WindowGeometry>,
+    },
+}
+
+impl From<V2AppAction> for AppAction {
+    fn from(action: V2AppAction) -> AppAction {
+        match action {
+            V2AppAction::OpenWorkspace { path } => AppAction::OpenWorkspace(path),
+            V2AppAction::WorkspaceAction {
+                workspace_id,
+                action,
+            } => AppAction::WorkspaceAction(workspace_id, action.into()),
+            V2AppAction::CloseWorkspace { workspace_id } => AppAction::CloseWorkspace(workspace_id),
+            V2AppAction::ReorderWorkspace {
+                workspace_id,
+                index,
+            } => AppAction::ReorderWorkspace(workspace_id, index),
+            V2AppAction::ReloadWorkspace { workspace_id } => {
+                AppAction::ReloadWorkspace(workspace_id)
+            }
+            V2AppAction::PinWatchedTabs { workspace_id, tabs } => {
+                AppAction::PinWatchedTabs(workspace_id, tabs)
+            }
+            V2AppAction::SaveSession {
+                name,
+                workspace_ids,
+            } => AppAction::SaveSession(name, workspace_ids),
+            V2AppAction::DeleteSession { name } => AppAction::DeleteSession(name),
+            V2AppAction::OpenSession { name } => AppAction::OpenSession(name),
+            V2AppAction::CloseSession { name } => AppAction::CloseSession(name),
+            V2AppAction::MoveTab { from, tab, to } => AppAction::MoveTab(from, tab, to),
+            V2AppAction::MergeWorkspaces { from, into } => AppAction::MergeWorkspaces(from, into),
+            V2AppAction::SetWindowGeometry {
+                workspace_id,
+                window,
+                geometry,
+            } => AppAction::SetWindowGeometry(workspace_id, window, geometry),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct V2Hello {
+    protocol_version: u32,
+    #[serde(default)]
+    token: Option<String>,
+    #[serde(default)]
+    client: Option<String>,
+}
+
+impl From<V2Hello> for Hello {
+    fn from(hello: V2Hello) -> Hello {
+        Hello {
+            protocol_version: hello.protocol_version,
+            token: hello.token,
+            client: hello.client,
+        }
+    }
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "camelCase", default)]
+struct V2TabPrecondition {
+    url: Option<String>,
+    is_open: Option<bool>,
+    incognito: Option<bool>,
+}
+
+impl From<V2TabPrecondition> for TabPrecondition {
+    fn from(precondition: V2TabPrecondition) -> TabPrecondition {
+        TabPrecondition {
+            url: precondition.url,
+            is_open: precondition.is_open,
+            incognito: precondition.incognito,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(
+    tag = "type",
+    content = "data",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+enum V2FromBrowser {
+    StartWorkspace {
+        workspace_id: String,
+    },
+    WorkspaceAction {
+        workspace_id: String,
+        action: V2Action,
+    },
+    AppAction(V2AppAction),
+    GetStatus,
+    Hello(V2Hello),
+    SetActionFilter {
+        kinds: Option<Vec<String>>,
+    },
+    ChangesSince {
+        workspace_id: String,
+        seq: u64,
+    },
+    WorkspaceActionIf {
+        workspace_id: String,
+        action: V2Action,
+        precondition: V2TabPrecondition,
+    },
+    LoadAcknowledged {
+        workspace_id: String,
+    },
+}
+
+impl From<V2FromBrowser> for FromBrowserMessage {
+    fn from(message: V2FromBrowser) -> FromBrowserMessage {
+        match message {
+            V2FromBrowser::StartWorkspace { workspace_id } => {
+                FromBrowserMessage::StartWorkspace(workspace_id)
+            }
+            V2FromBrowser::WorkspaceAction {
+                workspace_id,
+                action,
+            } => FromBrowserMessage::WorkspaceAction(workspace_id, action.into()),
+            V2FromBrowser::AppAction(action) => FromBrowserMessage::AppAction(action.into()),
+            V2FromBrowser::GetStatus => FromBrowserMessage::GetStatus,
+            V2FromBrowser::Hello(hello) => FromBrowserMessage::Hello(hello.into()),
+            V2FromBrowser::SetActionFilter { kinds } => FromBrowserMessage::SetActionFilter(kinds),
+            V2FromBrowser::ChangesSince { workspace_id, seq } => {
+                FromBrowserMessage::ChangesSince(workspace_id, seq)
+            }
+            V2FromBrowser::WorkspaceActionIf {
+                workspace_id,
+                action,
+                precondition,
+            } => FromBrowserMessage::WorkspaceActionIf(
+                workspace_id,
+                action.into(),
+                precondition.into(),
+            ),
+            V2FromBrowser::LoadAcknowledged { workspace_id } => {
+                FromBrowserMessage::LoadAcknowledged(workspace_id)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn url_change() -> ToBrowserMessage {
+        ToBrowserMessage::from_actions(vec![WorkspaceAction::ChangeTabUrl(
+            "a".into(),
+            "https://example.com/".into(),
+        )])
+    }
+
+    fn encoded(message: &ToBrowserMessage, version: ProtocolVersion) -> Value {
+        serde_json::from_str(&encode(message, version).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn a_url_change_is_a_single_field_change() {
+        let ToBrowserMessage::TabFieldChanged { tab, field, value } = url_change() else {
+            panic!("expected a TabFieldChanged");
+        };
+        assert_eq!(tab, "a");
+        assert_eq!(field, TabField::Url);
+        assert_eq!(value, json!("https://example.com/"));
+    }
+
+    #[test]
+    fn v1_gets_the_action_instead_of_the_field_change() {
+        assert_eq!(
+            encoded(&url_change(), ProtocolVersion::V1),
+            json!({"WorkspaceAction": {"ChangeTabUrl": ["a", "https://example.com/"]}})
+        );
+        let incognito =
+            ToBrowserMessage::from_action(WorkspaceAction::SetTabIncognito("a".into(), true));
+        assert_eq!(
+            encoded(&incognito, ProtocolVersion::V1),
+            json!({"WorkspaceAction": {"SetTabIncognito": ["a", true]}})
+        );
+    }
+
+    #[test]
+    fn v2_gets_the_field_change() {
+        assert_eq!(
+            encoded(&url_change(), ProtocolVersion::V2),
+            json!({
+                "type": "tabFieldChanged",
+                "data": {"tab": "a", "field": "url", "value": "https://example.com/"}
+            })
+        );
+    }
+
+    #[test]
+    fn versions_are_negotiated_down() {
+        assert_eq!(ProtocolVersion::negotiate(0), ProtocolVersion::V1);
+        assert_eq!(ProtocolVersion::negotiate(1), ProtocolVersion::V1);
+        assert_eq!(ProtocolVersion::negotiate(2), ProtocolVersion::V2);
+        assert_eq!(ProtocolVersion::negotiate(99), ProtocolVersion::V2);
+    }
+
+    #[test]
+    fn v2_tags_every_enum() {
+        let open = ToBrowserMessage::WorkspaceAction(WorkspaceAction::OpenTab("gandalf".into()));
+        assert_eq!(
+            encoded(&open, ProtocolVersion::V2),
+            json!({
+                "type": "workspaceAction",
+                "data": {"type": "openTab", "data": {"tab": "gandalf"}}
+            })
+        );
+        assert_eq!(
+            encoded(&open, ProtocolVersion::V1),
+            json!({"WorkspaceAction": {"OpenTab": "gandalf"}})
+        );
+    }
+
+    #[test]
+    fn capitalized_meta_keys_round_trip() {
+        let meta = json!({"Title": "Gandalf", "Nested": {"OpenTab": 1}});
+        let message = json!({
+            "type": "workspaceAction",
+            "data": {
+                "workspaceId": "ws",
+                "action": {"type": "setTabMeta", "data": {"tab": "a", "meta": meta}}
+            }
+        });
+        let FromBrowserMessage::WorkspaceAction(id, action) = decode(&message.to_string()).unwrap()
+        else {
+            panic!("expected a workspace action");
+        };
+        assert_eq!(id, "ws");
+        assert_eq!(
+            action,
+            WorkspaceAction::SetTabMeta("a".into(), meta.clone())
+        );
+
+        let back = encoded(
+            &ToBrowserMessage::WorkspaceAction(action),
+            ProtocolVersion::V2,
+        );
+        assert_eq!(back["data"]["data"]["meta"], meta);
+    }
+
+    #[test]
+    fn v1_messages_still_decode() {
+        let message = json!({"WorkspaceAction": ["ws", {"OpenTab": "a"}]});
+        let FromBrowserMessage::WorkspaceAction(id, action) = decode(&message.to_string()).unwrap()
+        else {
+            panic!("expected a workspace action");
+        };
+        assert_eq!(id, "ws");
+        assert_eq!(action, WorkspaceAction::OpenTab("a".into()));
+        assert!(matches!(
+            decode(&json!({"type": "getStatus"}).to_string()).unwrap(),
+            FromBrowserMessage::GetStatus
+        ));
+    }
+
+    #[test]
+    fn v2_payloads_have_named_camel_case_fields() {
+        let url = ToBrowserMessage::WorkspaceAction(WorkspaceAction::ChangeTabUrl(
+            "a".into(),
+            "https://example.com/".into(),
+        ));
+        assert_eq!(
+            encoded(&url, ProtocolVersion::V2),
+            json!({
+                "type": "workspaceAction",
+                "data": {
+                    "type": "changeTabUrl",
+                    "data": {"tab": "a", "url": "https://example.com/"}
+                }
+            })
+        );
+        let rename =
+            ToBrowserMessage::WorkspaceAction(WorkspaceAction::RenameTab("a".into(), "b".into()));
+        assert_eq!(
+            encoded(&rename, ProtocolVersion::V2)["data"]["data"],
+            json!({"from": "a", "to": "b"})
+        );
+
+        let welcome = ToBrowserMessage::Welcome {
+            protocol_version: 2,
+            token: "t".into(),
+        };
+        assert_eq!(
+            encoded(&welcome, ProtocolVersion::V2),
+            json!({"type": "welcome", "data": {"protocolVersion": 2, "token": "t"}})
+        );
+
+        let workspace = Workspace {
+            id: "ws".into(),
+            name: "ws".into(),
+            path: "/ws".into(),
+            tabs: vec![Tab {
+                name: "a".into(),
+                url: "https://example.com/".into(),
+                is_open: true,
+                ..Tab::default()
+            }],
+            malformed_tabs: vec![],
+            tab_errors: BTreeMap::new(),
+            config: Default::default(),
+            evicted: false,
+        };
+        let all = encoded(
+            &ToBrowserMessage::AllWorkspaces(vec![Arc::new(workspace)]),
+            ProtocolVersion::V2,
+        );
+        let sent = &all["data"][0];
+        assert_eq!(sent["malformedTabs"], json!([]));
+        assert_eq!(sent["tabErrors"], json!({}));
+        assert_eq!(sent["tabs"][0]["isOpen"], json!(true));
+        assert_eq!(sent["tabs"][0]["isBlank"], json!(false));
+        assert!(sent["tabs"][0].get("is_open").is_none());
+
+        let status = encoded(
+            &ToBrowserMessage::Status(BrowserStatus {
+                browser_id: 1,
+                connected_workspaces: vec!["ws".into()],
+            }),
+            ProtocolVersion::V2,
+        );
+        assert_eq!(
+            status["data"],
+            json!({"browserId": 1, "connectedWorkspaces": ["ws"]})
+        );
+    }
+
+    #[test]
+    fn v2_messages_from_the_browser_use_named_fields() {
+        let message = json!({
+            "type": "workspaceActionIf",
+            "data": {
+                "workspaceId": "ws",
+                "action": {"type": "closeTab", "data": {"tab": "a"}},
+                "precondition": {"isOpen": true}
+            }
+        });
+        let FromBrowserMessage::WorkspaceActionIf(id, action, precondition) =
+            decode(&message.to_string()).unwrap()
+        else {
+            panic!("expected a conditional workspace action");
+        };
+        assert_eq!(id, "ws");
+        assert_eq!(action, WorkspaceAction::CloseTab("a".into()));
+        assert_eq!(
+            precondition,
+            TabPrecondition {
+                is_open: Some(true),
+                ..TabPrecondition::default()
+            }
+        );
+
+        let message = json!({
+            "type": "appAction",
+            "data": {"type": "moveTab", "data": {"from": "ws", "tab": "a", "to": "other"}}
+        });
+        let FromBrowserMessage::AppAction(AppAction::MoveTab(from, tab, to)) =
+            decode(&message.to_string()).unwrap()
+        else {
+            panic!("expected a tab move");
+        };
+        assert_eq!(
+            (from.as_str(), tab.as_str(), to.as_str()),
+            ("ws", "a", "other")
+        );
+
+        let hello = json!({"type": "hello", "data": {"protocolVersion": 2, "client": "me"}});
+        let FromBrowserMessage::Hello(hello) = decode(&hello.to_string()).unwrap() else {
+            panic!("expected a hello");
+        };
+        assert_eq!(hello.protocol_version, 2);
+        assert_eq!(hello.client.as_deref(), Some("me"));
+    }
+}
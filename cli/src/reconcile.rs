@@ -0,0 +1,331 @@
+use crate::config::{canonical_url, ConflictPolicy, WorkspaceConfig};
+use crate::model::WorkspaceAction;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+/// Where an action came from, the daemon's own writes count as the browser's
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActionSource {
+    Browser,
+    Filesystem,
+}
+
+/// What `admit` decided
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    Admitted,
+    // The other side seeing a write that was just made, applying it again is harmless
+    Echo,
+    // Lost a conflict, the action should be dropped
+    Lost,
+}
+
+impl Admission {
+    pub fn is_admitted(&self) -> bool {
+        *self != Admission::Lost
+    }
+}
+
+/** Whether both actions end up as the same files. A close with a reason is written like
+ * a plain close, and with canonical urls the watcher reads back the canonical form of
+ * the url the browser sent. */
+fn same_write(a: &WorkspaceAction, b: &WorkspaceAction, canonical_urls: bool) -> bool {
+    let plain = |action: &WorkspaceAction| match action {
+        WorkspaceAction::CloseTabBecause(tab, _) => WorkspaceAction::CloseTab(tab.clone()),
+        WorkspaceAction::ChangeTabUrl(tab, url) if canonical_urls => {
+            WorkspaceAction::ChangeTabUrl(tab.clone(), canonical_url(url))
+        }
+        action => action.clone(),
+    };
+    plain(a) == plain(b)
+}
+
+#[derive(Debug, Clone)]
+struct LastWrite {
+    source: ActionSource,
+    action: WorkspaceAction,
+    at: Instant,
+}
+
+/** Keeps track of the last write to every field of every tab of a workspace so that a
+ * browser action and a filesystem edit racing on the same field are resolved with the
+ * workspace's conflict policy instead of whoever happens to land last. Writes to
+ * different fields of the same tab don't conflict. */
+#[derive(Clone)]
+pub struct Reconciler {
+    policy: ConflictPolicy,
+    window: Duration,
+    canonical_urls: bool,
+    // Keyed by tab name and the field the write changed
+    last_writes: Arc<RwLock<HashMap<(String, &'static str), LastWrite>>>,
+}
+
+/// What part of the tab the action writes, making, removing and renaming is the whole tab
+fn written_field(action: &WorkspaceAction) -> &'static str {
+    match action {
+        WorkspaceAction::OpenTab(_)
+        | WorkspaceAction::CloseTab(_)
+        | WorkspaceAction::CloseTabBecause(..) => "is_open",
+        WorkspaceAction::ChangeTabUrl(..) => "url",
+        WorkspaceAction::SetTabIncognito(..) => "incognito",
+        WorkspaceAction::SetTabMeta(..) => "meta",
+        WorkspaceAction::SetTabNotes(..) => "notes",
+        WorkspaceAction::SetTabFavicon(..) => "favicon",
+        WorkspaceAction::SetTabScroll(..) => "scroll",
+        WorkspaceAction::ReorderTab(..) => "order",
+        WorkspaceAction::ActivateTab(_) => "active",
+        WorkspaceAction::CreateTab(_)
+        | WorkspaceAction::RemoveTab(_)
+        | WorkspaceAction::RenameTab(..) => "tab",
+    }
+}
+
+impl Reconciler {
+    pub fn new(config: &WorkspaceConfig) -> Reconciler {
+        Reconciler {
+            policy: config.conflict_policy,
+            window: Duration::from_millis(config.conflict_window_ms),
+            canonical_urls: config.canonical_urls,
+            last_writes: Arc::default(),
+        }
+    }
+
+    /// Decides whether the action is applied, a lost one should be dropped
+    pub async fn admit(&self, source: ActionSource, action: &WorkspaceAction) -> Admission {
+        let now = Instant::now();
+        let key = (action.tab_name().to_string(), written_field(action));
+        let mut last_writes = self.last_writes.write().await;
+        // Writes older than the window can't conflict anymore, dropping them keeps this
+        // from growing with every tab a long running connection ever touched
+        last_writes.retain(|_, last| now.saturating_duration_since(last.at) < self.window);
+
+        if let Some(last) = last_writes.get(&key) {
+            if last.source != source {
+                // The watcher seeing the write we just made for the browser (or the
+                // other way around) is an echo, not a conflict
+                if same_write(&last.action, action, self.canonical_urls) {
+                    return Admission::Echo;
+                }
+
+                let racing = now.saturating_duration_since(last.at) < self.window;
+                if racing && !self.wins(source) {
+                    println!(
+                        "Dropping {:?} action, conflicts with a recent {:?} write: {:?}",
+                        source, last.source, action
+                    );
+                    return Admission::Lost;
+                }
+            }
+        }
+
+        last_writes.insert(
+            key,
+            LastWrite {
+                source,
+                action: action.clone(),
+                at: now,
+            },
+        );
+        Admission::Admitted
+    }
+
+    /// Remembers a write the daemon made without going through `admit`, so the
+    /// watcher seeing it counts as an echo
+    pub async fn wrote(&self, action: &WorkspaceAction) {
+        let key = (action.tab_name().to_string(), written_field(action));
+        self.last_writes.write().await.insert(
+            key,
+            LastWrite {
+                source: ActionSource::Browser,
+                action: action.clone(),
+                at: Instant::now(),
+            },
+        );
+    }
+
+    /// Keeps the tab's last writes when its directory is renamed
+    pub async fn rename(&self, from: &str, to: &str) {
+        let mut last_writes = self.last_writes.write().await;
+        let renamed: Vec<(String, &'static str)> = last_writes
+            .keys()
+            .filter(|(tab, _)| tab == from)
+            .cloned()
+            .collect();
+        for key in renamed {
+            if let Some(mut last) = last_writes.remove(&key) {
+                last.action.rename_tab(from, to);
+                last_writes.insert((to.to_string(), key.1), last);
+            }
+        }
+    }
+
+    fn wins(&self, source: ActionSource) -> bool {
+        match self.policy {
+            ConflictPolicy::FilesystemWins => source == ActionSource::Filesystem,
+            ConflictPolicy::BrowserWins => source == ActionSource::Browser,
+            ConflictPolicy::LastWriteWins => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reconciler(policy: ConflictPolicy) -> Reconciler {
+        Reconciler::new(&WorkspaceConfig {
+            conflict_policy: policy,
+            ..WorkspaceConfig::default()
+        })
+    }
+
+    fn url(tab: &str, url: &str) -> WorkspaceAction {
+        WorkspaceAction::ChangeTabUrl(tab.to_string(), url.to_string())
+    }
+
+    // The browser writes first, the filesystem edits the same url right after
+    async fn race(policy: ConflictPolicy) -> (Admission, Admission) {
+        let reconciler = reconciler(policy);
+        let browser = reconciler
+            .admit(ActionSource::Browser, &url("a", "https://browser.example/"))
+            .await;
+        let fs = reconciler
+            .admit(ActionSource::Filesystem, &url("a", "https://fs.example/"))
+            .await;
+        (browser, fs)
+    }
+
+    #[tokio::test]
+    async fn filesystem_wins_drops_the_browser_write() {
+        assert_eq!(
+            race(ConflictPolicy::FilesystemWins).await,
+            (Admission::Admitted, Admission::Admitted)
+        );
+        let reconciler = reconciler(ConflictPolicy::FilesystemWins);
+        reconciler
+            .admit(ActionSource::Filesystem, &url("a", "https://fs.example/"))
+            .await;
+        let browser = reconciler
+            .admit(ActionSource::Browser, &url("a", "https://browser.example/"))
+            .await;
+        assert_eq!(browser, Admission::Lost);
+    }
+
+    #[tokio::test]
+    async fn browser_wins_drops_the_filesystem_write() {
+        assert_eq!(
+            race(ConflictPolicy::BrowserWins).await,
+            (Admission::Admitted, Admission::Lost)
+        );
+    }
+
+    #[tokio::test]
+    async fn last_write_wins_admits_both() {
+        assert_eq!(
+            race(ConflictPolicy::LastWriteWins).await,
+            (Admission::Admitted, Admission::Admitted)
+        );
+    }
+
+    #[tokio::test]
+    async fn writes_outside_the_window_never_conflict() {
+        let reconciler = Reconciler::new(&WorkspaceConfig {
+            conflict_policy: ConflictPolicy::BrowserWins,
+            conflict_window_ms: 0,
+            ..WorkspaceConfig::default()
+        });
+        reconciler
+            .admit(ActionSource::Browser, &url("a", "https://browser.example/"))
+            .await;
+        let fs = reconciler
+            .admit(ActionSource::Filesystem, &url("a", "https://fs.example/"))
+            .await;
+        assert_eq!(fs, Admission::Admitted);
+    }
+
+    #[tokio::test]
+    async fn different_fields_of_a_tab_dont_conflict() {
+        let reconciler = reconciler(ConflictPolicy::BrowserWins);
+        reconciler
+            .admit(ActionSource::Browser, &url("a", "https://browser.example/"))
+            .await;
+        let open = reconciler
+            .admit(
+                ActionSource::Filesystem,
+                &WorkspaceAction::OpenTab("a".into()),
+            )
+            .await;
+        let other_tab = reconciler
+            .admit(ActionSource::Filesystem, &url("b", "https://fs.example/"))
+            .await;
+        assert_eq!(open, Admission::Admitted);
+        assert_eq!(other_tab, Admission::Admitted);
+    }
+
+    #[tokio::test]
+    async fn the_watcher_seeing_a_browser_write_is_an_echo() {
+        let reconciler = reconciler(ConflictPolicy::FilesystemWins);
+        reconciler
+            .admit(ActionSource::Browser, &url("a", "https://browser.example/"))
+            .await;
+        let echo = reconciler
+            .admit(
+                ActionSource::Filesystem,
+                &url("a", "https://browser.example/"),
+            )
+            .await;
+        assert_eq!(echo, Admission::Echo);
+        assert!(echo.is_admitted());
+    }
+
+    #[tokio::test]
+    async fn renamed_tabs_keep_their_last_writes() {
+        let reconciler = reconciler(ConflictPolicy::BrowserWins);
+        reconciler
+            .admit(ActionSource::Browser, &url("a", "https://browser.example/"))
+            .await;
+        reconciler.rename("a", "b").await;
+        let fs = reconciler
+            .admit(ActionSource::Filesystem, &url("b", "https://fs.example/"))
+            .await;
+        assert_eq!(fs, Admission::Lost);
+    }
+
+    #[tokio::test]
+    async fn writes_older_than_the_window_are_forgotten() {
+        let reconciler = Reconciler::new(&WorkspaceConfig {
+            conflict_window_ms: 20,
+            ..WorkspaceConfig::default()
+        });
+        for i in 0..200 {
+            let tab = format!("tab{}", i);
+            reconciler
+                .admit(ActionSource::Browser, &url(&tab, "https://example.com/"))
+                .await;
+        }
+        assert_eq!(reconciler.last_writes.read().await.len(), 200);
+
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        reconciler
+            .admit(ActionSource::Browser, &url("new", "https://example.com/"))
+            .await;
+        assert_eq!(reconciler.last_writes.read().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn the_canonical_form_of_a_browser_url_is_an_echo() {
+        let reconciler = Reconciler::new(&WorkspaceConfig {
+            canonical_urls: true,
+            ..WorkspaceConfig::default()
+        });
+        reconciler
+            .admit(ActionSource::Browser, &url("a", "https://Example.com:443"))
+            .await;
+        let read_back = reconciler
+            .admit(ActionSource::Filesystem, &url("a", "https://example.com/"))
+            .await;
+        assert_eq!(read_back, Admission::Echo);
+    }
+}
@@ -0,0 +1,463 @@
+use crate::model::WorkspaceAction;
+use serde_json::{json, Value};
+
+/** JSON Schema (draft 2020-12) for the messages in protocol version 1, the format
+ * serde writes by default. Enums are externally tagged: a unit variant is a string,
+ * every other variant an object with the variant name as its only key. Tuple
+ * variants are arrays. Keep this in step with the enums in `model`. */
+pub fn protocol_schema() -> Value {
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "mounttab protocol",
+        "oneOf": [
+            { "$ref": "#/$defs/ToBrowserMessage" },
+            { "$ref": "#/$defs/FromBrowserMessage" },
+        ],
+        "$defs": {
+            "ToBrowserMessage": one_of(vec![
+                variant("AllWorkspaces", json!({ "type": "array", "items": reference("Workspace") })),
+                variant("WorkspaceAction", reference("WorkspaceAction")),
+                variant("LoadWorkspace", reference("ApiWorkspace")),
+                variant("TabFieldChanged", object(
+                    &[
+                        ("tab", string()),
+                        ("field", reference("TabField")),
+                        ("value", json!({})),
+                    ],
+                    &["tab", "field", "value"],
+                )),
+                variant("Status", reference("BrowserStatus")),
+                variant("Transaction", object(
+                    &[
+                        ("id", integer()),
+                        ("actions", json!({ "type": "array", "items": reference("WorkspaceAction") })),
+                    ],
+                    &["id", "actions"],
+                )),
+                variant("Welcome", object(
+                    &[("protocol_version", integer()), ("token", string())],
+                    &["protocol_version", "token"],
+                )),
+                variant("Sessions", json!({
+                    "type": "object",
+                    "additionalProperties": { "type": "array", "items": string() },
+                })),
+                variant("ActionError", object(
+                    &[
+                        ("workspace_id", string()),
+                        ("action", reference("WorkspaceAction")),
+                        ("error", string()),
+                    ],
+                    &["workspace_id", "action", "error"],
+                )),
+                variant("Notice", string()),
+                variant("WatcherError", object(
+                    &[("workspace_id", string()), ("error", string())],
+                    &["workspace_id", "error"],
+                )),
+                variant("Changes", object(
+                    &[
+                        ("workspace_id", string()),
+                        ("seq", integer()),
+                        ("actions", json!({
+                            "type": ["array", "null"],
+                            "items": reference("WorkspaceAction"),
+                        })),
+                    ],
+                    &["workspace_id", "seq", "actions"],
+                )),
+            ]),
+            "FromBrowserMessage": one_of(vec![
+                variant("StartWorkspace", string()),
+                variant("WorkspaceAction", tuple(vec![string(), reference("WorkspaceAction")])),
+                variant("AppAction", reference("AppAction")),
+                unit("GetStatus"),
+                variant("Hello", reference("Hello")),
+                variant("SetActionFilter", json!({
+                    "type": ["array", "null"],
+                    "items": { "enum": WorkspaceAction::KINDS },
+                })),
+                variant("ChangesSince", tuple(vec![string(), integer()])),
+                variant("WorkspaceActionIf", tuple(vec![
+                    string(),
+                    reference("WorkspaceAction"),
+                    reference("TabPrecondition"),
+                ])),
+                variant("LoadAcknowledged", string()),
+            ]),
+            "AppAction": one_of(vec![
+                variant("OpenWorkspace", string()),
+                variant("WorkspaceAction", tuple(vec![string(), reference("WorkspaceAction")])),
+                variant("CloseWorkspace", string()),
+                variant("ReorderWorkspace", tuple(vec![string(), integer()])),
+                variant("ReloadWorkspace", string()),
+                variant("PinWatchedTabs", tuple(vec![
+                    string(),
+                    json!({ "type": ["array", "null"], "items": string() }),
+                ])),
+                variant("SaveSession", tuple(vec![
+                    string(),
+                    json!({ "type": "array", "items": string() }),
+                ])),
+                variant("DeleteSession", string()),
+                variant("OpenSession", string()),
+                variant("CloseSession", string()),
+                variant("MoveTab", tuple(vec![string(), string(), string()])),
+                variant("MergeWorkspaces", tuple(vec![string(), string()])),
+                variant("SetWindowGeometry", tuple(vec![
+                    string(),
+                    string(),
+                    json!({ "oneOf": [reference("WindowGeometry"), { "type": "null" }] }),
+                ])),
+            ]),
+            "WorkspaceAction": one_of(vec![
+                variant("OpenTab", string()),
+                variant("CloseTab", string()),
+                variant("CloseTabBecause", tuple(vec![string(), reference("CloseReason")])),
+                variant("ChangeTabUrl", tuple(vec![string(), string()])),
+                variant("CreateTab", string()),
+                variant("RemoveTab", string()),
+                variant("SetTabIncognito", tuple(vec![string(), json!({ "type": "boolean" })])),
+                variant("SetTabMeta", tuple(vec![string(), json!({})])),
+                variant("SetTabNotes", tuple(vec![string(), string()])),
+                variant("SetTabFavicon", tuple(vec![string(), string()])),
+                variant("SetTabScroll", tuple(vec![string(), json!({ "type": "number", "minimum": 0 })])),
+                variant("ReorderTab", tuple(vec![string(), integer()])),
+                variant("RenameTab", tuple(vec![string(), string()])),
+                variant("ActivateTab", string()),
+            ]),
+            "TabPrecondition": object(
+                &[
+                    ("url", json!({ "type": ["string", "null"] })),
+                    ("is_open", json!({ "type": ["boolean", "null"] })),
+                    ("incognito", json!({ "type": ["boolean", "null"] })),
+                ],
+                &[],
+            ),
+            "Hello": object(
+                &[
+                    ("protocol_version", integer()),
+                    ("token", json!({ "type": ["string", "null"] })),
+                    ("client", json!({ "type": ["string", "null"] })),
+                ],
+                &["protocol_version"],
+            ),
+            "BrowserStatus": object(
+                &[
+                    ("browser_id", integer()),
+                    ("connected_workspaces", json!({ "type": "array", "items": string() })),
+                ],
+                &["browser_id", "connected_workspaces"],
+            ),
+            "TabField": { "enum": ["url", "incognito"] },
+            "CloseReason": { "enum": ["User", "Expired", "Archived"] },
+            "Workspace": object(
+                &[
+                    ("id", string()),
+                    ("name", string()),
+                    ("path", string()),
+                    ("tabs", json!({ "type": "array", "items": reference("Tab") })),
+                    ("malformed_tabs", json!({ "type": "array", "items": string() })),
+                    ("tab_errors", json!({ "type": "object", "additionalProperties": string() })),
+                    ("evicted", json!({ "type": "boolean" })),
+                ],
+                &["id", "name", "path", "tabs"],
+            ),
+            "ApiWorkspace": object(
+                &[
+                    ("id", string()),
+                    ("name", string()),
+                    ("tabs", json!({ "type": "array", "items": reference("Tab") })),
+                    ("windows", json!({ "oneOf": [reference("WindowLayout"), { "type": "null" }] })),
+                ],
+                &["id", "name", "tabs"],
+            ),
+            "WindowLayout": object(
+                &[
+                    ("version", integer()),
+                    ("windows", json!({
+                        "type": "object",
+                        "additionalProperties": reference("WindowGeometry"),
+                    })),
+                ],
+                &["version", "windows"],
+            ),
+            "WindowGeometry": object(
+                &[
+                    ("left", json!({ "type": "integer" })),
+                    ("top", json!({ "type": "integer" })),
+                    ("width", integer()),
+                    ("height", integer()),
+                    ("state", json!({ "type": ["string", "null"] })),
+                ],
+                &["left", "top", "width", "height"],
+            ),
+            "Tab": object(
+                &[
+                    ("name", string()),
+                    ("url", string()),
+                    ("is_open", json!({ "type": "boolean" })),
+                    ("incognito", json!({ "type": "boolean" })),
+                    ("meta", json!({})),
+                    ("notes", json!({ "type": ["string", "null"] })),
+                    ("favicon", json!({ "type": ["string", "null"] })),
+                    ("scroll", json!({ "type": ["number", "null"] })),
+                    ("is_blank", json!({ "type": "boolean" })),
+                ],
+                &["name", "url", "is_open"],
+            ),
+        },
+    })
+}
+
+fn string() -> Value {
+    json!({ "type": "string" })
+}
+
+fn integer() -> Value {
+    json!({ "type": "integer", "minimum": 0 })
+}
+
+fn reference(name: &str) -> Value {
+    json!({ "$ref": format!("#/$defs/{}", name) })
+}
+
+fn one_of(variants: Vec<Value>) -> Value {
+    json!({ "oneOf": variants })
+}
+
+fn unit(name: &str) -> Value {
+    json!({ "const": name })
+}
+
+fn variant(name: &str, data: Value) -> Value {
+    json!({
+        "type": "object",
+        "properties": { name: data },
+        "required": [name],
+        "additionalProperties": false,
+    })
+}
+
+fn tuple(items: Vec<Value>) -> Value {
+    let len = items.len();
+    json!({
+        "type": "array",
+        "prefixItems": items,
+        "items": false,
+        "minItems": len,
+        "maxItems": len,
+    })
+}
+
+fn object(properties: &[(&str, Value)], required: &[&str]) -> Value {
+    let properties: serde_json::Map<String, Value> = properties
+        .iter()
+        .map(|(name, schema)| (name.to_string(), schema.clone()))
+        .collect();
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{
+        ApiWorkspace, AppAction, FromBrowserMessage, Hello, Tab, TabField, TabPrecondition,
+        ToBrowserMessage,
+    };
+
+    // Checks `value` against the parts of JSON Schema `protocol_schema` uses
+    fn valid(root: &Value, schema: &Value, value: &Value) -> bool {
+        let Some(schema) = schema.as_object() else {
+            return schema.as_bool().unwrap_or(false);
+        };
+        if let Some(path) = schema.get("$ref").and_then(Value::as_str) {
+            let name = path.trim_start_matches("#/$defs/");
+            return valid(root, &root["$defs"][name], value);
+        }
+        if let Some(variants) = schema.get("oneOf").and_then(Value::as_array) {
+            let matching = variants.iter().filter(|s| valid(root, s, value)).count();
+            if matching != 1 {
+                return false;
+            }
+        }
+        if schema.get("const").is_some_and(|c| c != value) {
+            return false;
+        }
+        if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+            if !values.contains(value) {
+                return false;
+            }
+        }
+        if let Some(types) = schema.get("type") {
+            let types: Vec<&str> = match types {
+                Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+                types => types.as_str().into_iter().collect(),
+            };
+            let fits = types.iter().any(|ty| match *ty {
+                "string" => value.is_string(),
+                "integer" => value.is_i64() || value.is_u64(),
+                "number" => value.is_number(),
+                "boolean" => value.is_boolean(),
+                "array" => value.is_array(),
+                "object" => value.is_object(),
+                "null" => value.is_null(),
+                _ => false,
+            });
+            if !fits {
+                return false;
+            }
+        }
+        if let (Some(minimum), Some(number)) = (
+            schema.get("minimum").and_then(Value::as_f64),
+            value.as_f64(),
+        ) {
+            if number < minimum {
+                return false;
+            }
+        }
+        if let Some(object) = value.as_object() {
+            let properties = schema.get("properties").and_then(Value::as_object);
+            let required = schema.get("required").and_then(Value::as_array);
+            if required.is_some_and(|r| r.iter().any(|k| !object.contains_key(k.as_str().unwrap())))
+            {
+                return false;
+            }
+            for (key, field) in object {
+                let fits = match properties.and_then(|p| p.get(key)) {
+                    Some(property) => valid(root, property, field),
+                    None => schema
+                        .get("additionalProperties")
+                        .is_none_or(|extra| valid(root, extra, field)),
+                };
+                if !fits {
+                    return false;
+                }
+            }
+        }
+        if let Some(items) = value.as_array() {
+            let len = items.len() as u64;
+            if schema
+                .get("minItems")
+                .and_then(Value::as_u64)
+                .is_some_and(|min| len < min)
+                || schema
+                    .get("maxItems")
+                    .and_then(Value::as_u64)
+                    .is_some_and(|max| len > max)
+            {
+                return false;
+            }
+            let prefix = schema
+                .get("prefixItems")
+                .and_then(Value::as_array)
+                .map_or(&[][..], Vec::as_slice);
+            for (i, item) in items.iter().enumerate() {
+                let fits = match prefix.get(i) {
+                    Some(item_schema) => valid(root, item_schema, item),
+                    None => schema.get("items").is_none_or(|s| valid(root, s, item)),
+                };
+                if !fits {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    fn tab() -> Tab {
+        Tab {
+            name: "a".into(),
+            url: "https://example.com/".into(),
+            is_open: true,
+            scroll: Some(0.5),
+            ..Tab::default()
+        }
+    }
+
+    #[test]
+    fn the_schema_accepts_what_serde_writes() {
+        let schema = protocol_schema();
+        let to_browser = vec![
+            ToBrowserMessage::WorkspaceAction(WorkspaceAction::RenameTab("a".into(), "b".into())),
+            ToBrowserMessage::LoadWorkspace(ApiWorkspace {
+                id: "1".into(),
+                name: "ws".into(),
+                tabs: vec![tab()],
+                windows: None,
+            }),
+            ToBrowserMessage::TabFieldChanged {
+                tab: "a".into(),
+                field: TabField::Incognito,
+                value: true.into(),
+            },
+            ToBrowserMessage::Transaction {
+                id: 3,
+                actions: vec![
+                    WorkspaceAction::ReorderTab("a".into(), 1),
+                    WorkspaceAction::SetTabScroll("a".into(), 0.25),
+                ],
+            },
+            ToBrowserMessage::Welcome {
+                protocol_version: 2,
+                token: "t".into(),
+            },
+            ToBrowserMessage::Notice("hi".into()),
+            ToBrowserMessage::Changes {
+                workspace_id: "1".into(),
+                seq: 4,
+                actions: None,
+            },
+        ];
+        for message in to_browser {
+            let json = serde_json::to_value(&message).unwrap();
+            assert!(valid(&schema, &schema, &json), "{}", json);
+            let back: ToBrowserMessage = serde_json::from_value(json.clone()).unwrap();
+            assert_eq!(serde_json::to_value(&back).unwrap(), json);
+        }
+
+        let from_browser = vec![
+            FromBrowserMessage::StartWorkspace("1".into()),
+            FromBrowserMessage::GetStatus,
+            FromBrowserMessage::Hello(Hello {
+                protocol_version: 2,
+                token: None,
+                client: Some("laptop".into()),
+            }),
+            FromBrowserMessage::SetActionFilter(Some(vec!["OpenTab".into()])),
+            FromBrowserMessage::WorkspaceActionIf(
+                "1".into(),
+                WorkspaceAction::CloseTab("a".into()),
+                TabPrecondition {
+                    is_open: Some(true),
+                    ..TabPrecondition::default()
+                },
+            ),
+            FromBrowserMessage::AppAction(AppAction::MoveTab("1".into(), "a".into(), "2".into())),
+            FromBrowserMessage::AppAction(AppAction::PinWatchedTabs("1".into(), None)),
+        ];
+        for message in from_browser {
+            let json = serde_json::to_value(&message).unwrap();
+            assert!(valid(&schema, &schema, &json), "{}", json);
+            let back: FromBrowserMessage = serde_json::from_value(json.clone()).unwrap();
+            assert_eq!(serde_json::to_value(&back).unwrap(), json);
+        }
+    }
+
+    #[test]
+    fn the_schema_rejects_malformed_messages() {
+        let schema = protocol_schema();
+        for json in [
+            json!({ "StartWorkspace": 1 }),
+            json!({ "WorkspaceAction": ["1", { "OpenTab": "a" }, "extra"] }),
+            json!({ "SetActionFilter": ["NotAnAction"] }),
+            json!("Unknown"),
+        ] {
+            assert!(!valid(&schema, &schema, &json), "{}", json);
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fs, io, path::PathBuf};
+
+/// File next to the config where subscriptions are kept
+pub const SUBSCRIPTIONS_FILE: &str = "subscriptions.json";
+
+/** Which workspaces every browser started, keyed by the reconnection token the browser
+ * got in its Welcome. A browser that reconnects with its token after the daemon
+ * restarted gets those workspaces started again. */
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct Subscriptions {
+    by_token: BTreeMap<String, Vec<String>>,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl Subscriptions {
+    pub fn load(path: Option<PathBuf>) -> Subscriptions {
+        let mut subscriptions = match &path {
+            Some(path) if path.exists() => fs::read_to_string(path)
+                .and_then(|contents| {
+                    serde_json::from_str(&contents)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+                })
+                .unwrap_or_else(|e| {
+                    eprintln!("Error reading subscriptions {}: {}", path.display(), e);
+                    Subscriptions::default()
+                }),
+            _ => Subscriptions::default(),
+        };
+        subscriptions.path = path;
+        subscriptions
+    }
+
+    pub fn workspaces(&self, token: &str) -> Vec<String> {
+        self.by_token.get(token).cloned().unwrap_or_default()
+    }
+
+    /// Records the workspace for the token and saves, returns false if it was already there
+    pub fn subscribe(&mut self, token: &str, workspace_id: &str) -> bool {
+        let workspaces = self.by_token.entry(token.to_string()).or_default();
+        if workspaces.iter().any(|id| id == workspace_id) {
+            return false;
+        }
+        workspaces.push(workspace_id.to_string());
+        if let Err(e) = self.save() {
+            eprintln!("Error saving subscriptions: {}", e);
+        }
+        true
+    }
+
+    pub fn unsubscribe(&mut self, token: &str, workspace_id: &str) {
+        let Some(workspaces) = self.by_token.get_mut(token) else {
+            return;
+        };
+        workspaces.retain(|id| id != workspace_id);
+        if let Err(e) = self.save() {
+            eprintln!("Error saving subscriptions: {}", e);
+        }
+    }
+
+    pub fn by_token(&self) -> &BTreeMap<String, Vec<String>> {
+        &self.by_token
+    }
+
+    /// Replaces every subscription, used when a manager is rehydrated
+    pub fn restore(&mut self, by_token: BTreeMap<String, Vec<String>>) {
+        self.by_token = by_token;
+        if let Err(e) = self.save() {
+            eprintln!("Error saving subscriptions: {}", e);
+        }
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(path, contents)
+    }
+
+    pub fn new_token(browser_id: usize) -> String {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        format!("{:x}-{:x}", nanos, browser_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn subscriptions_survive_a_reload() {
+        let dir = TempDir::new();
+        let path = dir.path().join(SUBSCRIPTIONS_FILE);
+        let mut subscriptions = Subscriptions::load(Some(path.clone()));
+        assert!(subscriptions.subscribe("token", "a"));
+        assert!(!subscriptions.subscribe("token", "a"));
+        subscriptions.subscribe("token", "b");
+        subscriptions.unsubscribe("token", "a");
+
+        let reloaded = Subscriptions::load(Some(path));
+        assert_eq!(reloaded.workspaces("token"), vec!["b".to_string()]);
+        assert!(reloaded.workspaces("other").is_empty());
+    }
+}
@@ -0,0 +1,99 @@
+use crate::model::{FromBrowserMessage, ToBrowserMessage, WorkspaceManager};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use std::{fs, process};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+/// A directory under the system temp dir that's deleted again when dropped
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> TempDir {
+        let dir = std::env::temp_dir().join(format!(
+            "mounttab-test-{}-{}",
+            process::id(),
+            NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        // Workspace paths are compared canonicalized
+        TempDir(fs::canonicalize(dir).unwrap())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// A directory inside, made if it's not there yet
+    pub fn dir(&self, name: &str) -> PathBuf {
+        let dir = self.0.join(name);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Writes a tab in the default layout
+pub fn write_tab(workspace: &Path, name: &str, url: &str, is_open: bool) {
+    let dir = workspace.join(name);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("url"), url).unwrap();
+    fs::write(dir.join("is_open"), if is_open { "1" } else { "0" }).unwrap();
+}
+
+/// A browser talking to the manager from a spawned task, like the socket task does
+pub struct TestBrowser {
+    pub tx: mpsc::UnboundedSender<FromBrowserMessage>,
+    pub rx: mpsc::Receiver<ToBrowserMessage>,
+}
+
+impl TestBrowser {
+    pub fn connect(manager: &WorkspaceManager, id: usize) -> TestBrowser {
+        let (browser, rx) = manager.make_browser(id, Arc::new(AtomicU32::new(1)));
+        let (tx, from_browser) = mpsc::unbounded_channel();
+        let manager = manager.clone();
+        tokio::spawn(async move {
+            let mut from_browser = UnboundedReceiverStream::new(from_browser);
+            manager.browser_connected(&browser, &mut from_browser).await;
+        });
+        TestBrowser { tx, rx }
+    }
+
+    pub fn send(&self, message: FromBrowserMessage) {
+        self.tx.send(message).unwrap();
+    }
+
+    /// The next message, None if nothing comes within `timeout`
+    pub async fn recv_within(&mut self, timeout: Duration) -> Option<ToBrowserMessage> {
+        tokio::time::timeout(timeout, self.rx.recv())
+            .await
+            .ok()
+            .flatten()
+    }
+
+    pub async fn recv(&mut self) -> Option<ToBrowserMessage> {
+        self.recv_within(Duration::from_secs(5)).await
+    }
+
+    /// Skips messages until `pick` returns something, None if nothing matches in time
+    pub async fn recv_until<T>(
+        &mut self,
+        mut pick: impl FnMut(ToBrowserMessage) -> Option<T>,
+    ) -> Option<T> {
+        while let Some(message) = self.recv().await {
+            if let Some(picked) = pick(message) {
+                return Some(picked);
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,151 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+enum Undo {
+    // Remove what was copied here
+    Copied(PathBuf),
+    // Move the directory back from its parking spot
+    Parked {
+        parked: PathBuf,
+        original: PathBuf,
+    },
+    // Put the copy back in place of whatever is there now, or remove what was made
+    // when there was nothing to copy
+    BackedUp {
+        backup: Option<PathBuf>,
+        original: PathBuf,
+    },
+}
+
+/** Filesystem writes across one or more workspaces that either all happen or are all
+ * undone. Removed directories are parked under a hidden name next to where they were
+ * and only deleted on commit, so a rollback can always put them back. The caller has
+ * to hold the write locks of every workspace it touches. */
+#[derive(Default)]
+pub struct FsTransaction {
+    undo: Vec<Undo>,
+}
+
+impl FsTransaction {
+    pub fn new() -> FsTransaction {
+        FsTransaction::default()
+    }
+
+    /// Copies the directory and everything in it, `to` must not exist yet
+    pub fn copy_dir(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        if to.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", to.display()),
+            ));
+        }
+        // Registered first so a copy that fails halfway is cleaned up too
+        self.undo.push(Undo::Copied(to.to_owned()));
+        copy_recursive(from, to)
+    }
+
+    pub fn remove_dir(&mut self, dir: &Path) -> io::Result<()> {
+        let parked = FsTransaction::hidden_sibling(dir, "removing")?;
+        fs::rename(dir, &parked)?;
+        self.undo.push(Undo::Parked {
+            parked,
+            original: dir.to_owned(),
+        });
+        Ok(())
+    }
+
+    /// Copies the directory aside so whatever is written into it afterwards can be
+    /// undone. Backing up the same directory twice keeps the first copy
+    pub fn back_up_dir(&mut self, dir: &Path) -> io::Result<()> {
+        let already = self.undo.iter().any(|undo| match undo {
+            Undo::BackedUp { original, .. } => original == dir,
+            _ => false,
+        });
+        if already {
+            return Ok(());
+        }
+        let backup = match dir.exists() {
+            true => {
+                let backup = FsTransaction::hidden_sibling(dir, "backup")?;
+                if backup.exists() {
+                    fs::remove_dir_all(&backup)?;
+                }
+                copy_recursive(dir, &backup)?;
+                Some(backup)
+            }
+            false => None,
+        };
+        self.undo.push(Undo::BackedUp {
+            backup,
+            original: dir.to_owned(),
+        });
+        Ok(())
+    }
+
+    fn hidden_sibling(dir: &Path, what: &str) -> io::Result<PathBuf> {
+        let name = dir.file_name().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} has no directory name", dir.display()),
+            )
+        })?;
+        Ok(dir.with_file_name(format!(".mounttab-{}-{}", what, name.to_string_lossy())))
+    }
+
+    /// Deletes the parked directories and backups, the writes can't be undone after this
+    pub fn commit(self) {
+        for undo in self.undo {
+            let leftover = match undo {
+                Undo::Parked { parked, .. } => parked,
+                Undo::BackedUp {
+                    backup: Some(backup),
+                    ..
+                } => backup,
+                _ => continue,
+            };
+            if let Err(e) = fs::remove_dir_all(&leftover) {
+                eprintln!("Error deleting {}: {}", leftover.display(), e);
+            }
+        }
+    }
+
+    /// Undoes every write, newest first
+    pub fn rollback(self) {
+        for undo in self.undo.into_iter().rev() {
+            let res = match &undo {
+                Undo::Copied(path) if path.exists() => fs::remove_dir_all(path),
+                Undo::Copied(_) => Ok(()),
+                Undo::Parked { parked, original } => fs::rename(parked, original),
+                Undo::BackedUp { backup, original } => {
+                    let cleared = match original.exists() {
+                        true => fs::remove_dir_all(original),
+                        false => Ok(()),
+                    };
+                    match backup {
+                        Some(backup) => cleared.and_then(|_| fs::rename(backup, original)),
+                        None => cleared,
+                    }
+                }
+            };
+            if let Err(e) = res {
+                eprintln!("Error rolling back a transaction: {}", e);
+            }
+        }
+    }
+}
+
+fn copy_recursive(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_recursive(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
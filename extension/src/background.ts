@@ -1,9 +1,12 @@
-import { FromDameonMessage, TabHolder, ToDameonMessage, Workspace, WorkspaceAction } from "./model";
+import { FromDameonMessage, SCROLL_PERMISSIONS, Tab, TabHolder, ToDameonMessage, Workspace, WorkspaceAction, WorkspaceId } from "./model";
 
 // Global state
 let ALL_WORKSPACES: Workspace[] = [];
 let socket: WebSocket;
 const tabHolder = new TabHolder();
+// The window every loaded workspace is in and the names of its tabs, loading a workspace
+// again updates that window instead of opening another one
+const LOADED_WORKSPACES: Record<WorkspaceId, { windowId: number, tabs: string[] }> = {};
 
 const connectToSocket = () => {
     socket = new WebSocket("ws://localhost:3030/chat");
@@ -21,8 +24,26 @@ const connectToSocket = () => {
     }
 }
 
+// Who this browser is to the daemon and the token of its last Welcome, kept across
+// restarts so the daemon starts the same workspaces again
+const CLIENT_KEY = "client";
+const TOKEN_KEY = "token";
+
+const sendHello = async () => {
+    const stored = await chrome.storage.local.get([CLIENT_KEY, TOKEN_KEY]);
+    let client: string | undefined = stored[CLIENT_KEY];
+    if (!client) {
+        client = crypto.randomUUID();
+        await chrome.storage.local.set({ [CLIENT_KEY]: client });
+    }
+    sendMessageToDaemon({
+        Hello: { protocol_version: 1, client, token: stored[TOKEN_KEY] },
+    });
+}
+
 const onSocketConnected = async () => {
     console.log("Connected to socket");
+    await sendHello();
     const tabs = await chrome.tabs.query({});
 
     console.log("Tabs", tabs);
@@ -63,6 +84,107 @@ const onSocketConnected = async () => {
 
 }
 
+// Blank tabs open as a new tab page
+const tabUrl = (tab: Tab) => tab.is_blank ? undefined : tab.url;
+
+// Private tabs can't live in a normal window, so they get their own incognito window
+const openTab = async (tab: Tab): Promise<chrome.tabs.Tab> => {
+    if (!tab.incognito) {
+        return chrome.tabs.create({ url: tabUrl(tab) });
+    }
+    const window = await chrome.windows.create({ url: tabUrl(tab), incognito: true });
+    const chromeTab = window.tabs?.[0];
+    if (!chromeTab) {
+        throw new Error("Incognito window has no tab");
+    }
+    return chromeTab;
+}
+
+// Waits for the page to load, scrolls it back to where it was and reports scrolling
+// from then on, debounced so the daemon gets one update once scrolling stops
+// Only once the user granted SCROLL_PERMISSIONS from the popup
+const trackScroll = (chromeTabId: number, scroll: number | undefined) => {
+    const onUpdated = async (tabId: number, changeInfo: chrome.tabs.TabChangeInfo) => {
+        if (tabId !== chromeTabId || changeInfo.status !== "complete") {
+            return;
+        }
+        chrome.tabs.onUpdated.removeListener(onUpdated);
+        if (!await chrome.permissions.contains(SCROLL_PERMISSIONS)) {
+            return;
+        }
+        chrome.scripting.executeScript({
+            target: { tabId },
+            args: [scroll ?? 0],
+            func: (scroll: number) => {
+                window.scrollTo(0, scroll);
+                let timer: ReturnType<typeof setTimeout> | undefined;
+                window.addEventListener("scroll", () => {
+                    clearTimeout(timer);
+                    timer = setTimeout(() => {
+                        chrome.runtime.sendMessage({ type: "TabScrolled", scroll: window.scrollY });
+                    }, 500);
+                });
+            },
+        }).catch((e) => console.log("Can't track scrolling of tab", tabId, e));
+    };
+    chrome.tabs.onUpdated.addListener(onUpdated);
+}
+
+// Opens the tab in the workspace's window, private tabs get their own
+const openWorkspaceTab = async (windowId: number, tab: Tab) => {
+    const chromeTab = tab.incognito
+        ? await openTab(tab)
+        : await chrome.tabs.create({
+            windowId,
+            url: tabUrl(tab),
+        });
+    if (!chromeTab.id) {
+        throw new Error("Chrome tab id was null");
+    }
+
+    tabHolder.setTabId(tab.name, chromeTab.id.toString());
+    trackScroll(chromeTab.id, tab.scroll);
+
+    tabHolder.applyAction({
+        CreateTab: tab,
+    });
+}
+
+const windowIsOpen = async (windowId: number) => {
+    try {
+        await chrome.windows.get(windowId);
+        return true;
+    } catch {
+        return false;
+    }
+}
+
+// Brings an already open workspace window in line with the tabs the daemon sent
+const reloadWorkspaceWindow = async (windowId: number, loadedTabs: string[], tabs: Tab[]) => {
+    const names = new Set(tabs.map(tab => tab.name));
+    for (const name of loadedTabs) {
+        const chromeTabId = tabHolder.getChromeTabId(name);
+        if (names.has(name) || !chromeTabId) {
+            continue;
+        }
+        // Closed in the holder first so onRemoved doesn't report it as the user's close
+        tabHolder.applyAction({ CloseTab: name });
+        await chrome.tabs.remove(parseInt(chromeTabId));
+    }
+    for (const tab of tabs) {
+        const chromeTabId = tabHolder.getChromeTabId(tab.name);
+        if (!chromeTabId || !loadedTabs.includes(tab.name)) {
+            await openWorkspaceTab(windowId, tab);
+            continue;
+        }
+        const known = tabHolder.getTabById(tab.name);
+        if (known?.url !== tab.url && !tab.is_blank) {
+            await chrome.tabs.update(parseInt(chromeTabId), { url: tab.url });
+        }
+        tabHolder.applyAction({ CreateTab: tab });
+    }
+}
+
 const sendMessageToDaemon = (message: ToDameonMessage) => {
     console.log("Sending message to daemon", message);
     socket.send(JSON.stringify(message));
@@ -71,35 +193,116 @@ const sendMessageToDaemon = (message: ToDameonMessage) => {
 
 const handleDameonMessage = async (message: FromDameonMessage) => {
     console.log("Handling dameon message", message);
+    if (message.Welcome) {
+        await chrome.storage.local.set({ [TOKEN_KEY]: message.Welcome.token });
+    }
     if (message.AllWorkspaces) {
         console.log("Setting all workspaces because of socket message", message.AllWorkspaces);
         ALL_WORKSPACES = message.AllWorkspaces;
     }
 
+    if (message.Transaction) {
+        console.log("Applying transaction", message.Transaction.id);
+        for (const action of message.Transaction.actions) {
+            await handleDameonMessage({ WorkspaceAction: action });
+        }
+    }
+
     if (message.WorkspaceAction) {
         tabHolder.applyAction(message.WorkspaceAction);
         if (message.WorkspaceAction.ChangeTabUrl) {
             const [tabId, url] = message.WorkspaceAction.ChangeTabUrl;
             const chromeTabId = tabHolder.getChromeTabId(tabId);
             await chrome.tabs.update(parseInt(chromeTabId), { url });
-        } else if (message.WorkspaceAction.CloseTab) {
-            const tabId = message.WorkspaceAction.CloseTab;
+        } else if (message.WorkspaceAction.CloseTab || message.WorkspaceAction.CloseTabBecause) {
+            const tabId = message.WorkspaceAction.CloseTab ?? message.WorkspaceAction.CloseTabBecause![0];
             const realTabId = tabHolder.getChromeTabId(tabId);
             await chrome.tabs.remove(parseInt(realTabId));
         } else if (message.WorkspaceAction.OpenTab) {
             const modelTabId = message.WorkspaceAction.OpenTab;
             const tab = tabHolder.getTabById(modelTabId);
-            const newTab = await chrome.tabs.create({
-                url: tab.url,
-            });
+            const newTab = await openTab(tab);
             tabHolder.setTabId(tab.name, String(newTab.id));
+            if (newTab.id) {
+                trackScroll(newTab.id, tab.scroll);
+            }
+        }
+    }
+
+    if (message.ActionError) {
+        const { workspace_id, action, error } = message.ActionError;
+        console.error("Daemon couldn't apply action", workspace_id, action, error);
+    }
+
+    if (message.WatcherError) {
+        const { workspace_id, error } = message.WatcherError;
+        console.error("Workspace isn't watched anymore", workspace_id, error);
+    }
+
+    if (message.Notice) {
+        console.warn("Notice from daemon:", message.Notice);
+    }
+
+    if (message.TabFieldChanged) {
+        const change = message.TabFieldChanged;
+        tabHolder.patchTab(change);
+        if (change.field === "url") {
+            const chromeTabId = tabHolder.getChromeTabId(change.tab);
+            await chrome.tabs.update(parseInt(chromeTabId), { url: String(change.value) });
         }
     }
 
     if (message.LoadWorkspace) {
         const workspaceId = message.LoadWorkspace.id;
         const tabs = message.LoadWorkspace.tabs;
-        const window = await chrome.windows.create({});
+        const loaded = LOADED_WORKSPACES[workspaceId];
+        if (loaded && await windowIsOpen(loaded.windowId)) {
+            await reloadWorkspaceWindow(loaded.windowId, loaded.tabs, tabs);
+            loaded.tabs = tabs.map(tab => tab.name);
+            sendMessageToDaemon({ LoadAcknowledged: workspaceId });
+            return;
+        }
+        // Workspaces open in one window, saved under this key
+        const windowKey = "main";
+        const geometry = message.LoadWorkspace.windows?.windows[windowKey];
+        const window = await chrome.windows.create(geometry ? {
+            left: geometry.left,
+            top: geometry.top,
+            width: geometry.width,
+            height: geometry.height,
+        } : {});
+        if (!window.id) {
+            throw new Error("Chrome window id was null");
+        }
+        LOADED_WORKSPACES[workspaceId] = { windowId: window.id, tabs: tabs.map(tab => tab.name) };
+
+        for (const tab of tabs) {
+            await openWorkspaceTab(window.id, tab);
+        }
+        sendMessageToDaemon({ LoadAcknowledged: workspaceId });
+
+        // The listeners of a workspace whose window was closed and opened again are
+        // still there, they look the window up when they run
+        if (loaded) {
+            return;
+        }
+
+        chrome.windows.onBoundsChanged.addListener((changed) => {
+            if (changed.id !== LOADED_WORKSPACES[workspaceId]?.windowId) {
+                return;
+            }
+            sendMessageToDaemon({
+                AppAction: {
+                    SetWindowGeometry: [workspaceId, windowKey, {
+                        left: changed.left ?? 0,
+                        top: changed.top ?? 0,
+                        width: changed.width ?? 0,
+                        height: changed.height ?? 0,
+                        state: changed.state ?? null,
+                    }]
+                }
+            });
+        });
 
         chrome.tabs.onUpdated.addListener((tabId, changeInfo, tab) => {
             const tabName = tabHolder.getTabNameFromBrowserTabId(String(tabId));
@@ -115,28 +318,52 @@ const handleDameonMessage = async (message: FromDameonMessage) => {
                 const worksapceAction: WorkspaceAction = {
                     ChangeTabUrl: [tabName, tab.url ?? ""],
                 }
+                // Only replaces the url this tab had, an edit made on disk meanwhile wins
+                const previousUrl = tabHolder.getTabById(tabName)?.url;
                 sendMessageToDaemon({
-                    WorkspaceAction: [workspaceId, worksapceAction]
+                    WorkspaceActionIf: [workspaceId, worksapceAction, { url: previousUrl }]
                 });
+                tabHolder.applyAction(worksapceAction);
             }
         });
 
-        for (const tab of tabs) {
-            const chromeTab = await chrome.tabs.create({
-                windowId: window.id,
-                url: tab.url,
-            });
-            if (!chromeTab.id) {
-                throw new Error("Chrome tab id was null");
+        // Tabs the daemon closes are already closed in the tab holder, the rest the user closed
+        chrome.tabs.onRemoved.addListener((tabId) => {
+            const tabName = tabHolder.getTabNameFromBrowserTabId(String(tabId));
+            if (!tabName || !tabHolder.getTabById(tabName)?.is_open) {
+                return;
             }
+            const worksapceAction: WorkspaceAction = {
+                CloseTabBecause: [tabName, "User"],
+            }
+            sendMessageToDaemon({
+                WorkspaceAction: [workspaceId, worksapceAction]
+            });
+            tabHolder.applyAction(worksapceAction);
+        });
 
+        chrome.tabs.onActivated.addListener((activeInfo) => {
+            const tabName = tabHolder.getTabNameFromBrowserTabId(String(activeInfo.tabId));
+            if (!tabName) {
+                return;
+            }
+            sendMessageToDaemon({
+                WorkspaceAction: [workspaceId, { ActivateTab: tabName }]
+            });
+        });
 
-            tabHolder.setTabId(tab.name, chromeTab.id.toString());
-
-            tabHolder.applyAction({
-                CreateTab: tab,
+        chrome.runtime.onMessage.addListener((request, sender) => {
+            if (request.type !== "TabScrolled" || !sender.tab?.id) {
+                return;
+            }
+            const tabName = tabHolder.getTabNameFromBrowserTabId(String(sender.tab.id));
+            if (!tabName) {
+                return;
+            }
+            sendMessageToDaemon({
+                WorkspaceAction: [workspaceId, { SetTabScroll: [tabName, request.scroll] }]
             });
-        }
+        });
     }
 }
 
@@ -4,33 +4,133 @@ export type TabId = string;
 export type ToDameonMessage = {
     StartWorkspace?: WorkspaceId
     WorkspaceAction?: [WorkspaceId, WorkspaceAction],
+    AppAction?: AppAction,
+    Hello?: { protocol_version: number, token?: string, client?: string },
+    SetActionFilter?: (keyof WorkspaceAction)[] | null,
+    ChangesSince?: [WorkspaceId, number],
+    // Only applied when the tab on disk still has these values
+    WorkspaceActionIf?: [WorkspaceId, WorkspaceAction, TabPrecondition],
+    // Sent once the tabs of a LoadWorkspace are open
+    LoadAcknowledged?: WorkspaceId,
+} | "GetStatus"
+
+export type TabPrecondition = {
+    url?: string | null,
+    is_open?: boolean | null,
+    incognito?: boolean | null,
+}
+
+export type AppAction = {
+    OpenWorkspace?: string,
+    WorkspaceAction?: [WorkspaceId, WorkspaceAction],
+    CloseWorkspace?: WorkspaceId,
+    ReorderWorkspace?: [WorkspaceId, number],
+    ReloadWorkspace?: WorkspaceId,
+    PinWatchedTabs?: [WorkspaceId, TabId[] | null],
+    SaveSession?: [string, WorkspaceId[]],
+    DeleteSession?: string,
+    OpenSession?: string,
+    CloseSession?: string,
+    MoveTab?: [WorkspaceId, TabId, WorkspaceId],
+    MergeWorkspaces?: [WorkspaceId, WorkspaceId],
+    // Workspace id, window key, null when the window was closed
+    SetWindowGeometry?: [WorkspaceId, string, WindowGeometry | null],
 }
 
 export type FromDameonMessage = {
     AllWorkspaces?: Workspace[],
     WorkspaceAction?: WorkspaceAction,
     LoadWorkspace?: Workspace,
+    TabFieldChanged?: TabFieldChange,
+    Status?: BrowserStatus,
+    Welcome?: { protocol_version: number, token: string },
+    Transaction?: { id: number, actions: WorkspaceAction[] },
+    Sessions?: Record<string, WorkspaceId[]>,
+    ActionError?: { workspace_id: WorkspaceId, action: WorkspaceAction, error: string },
+    Notice?: string,
+    WatcherError?: { workspace_id: WorkspaceId, error: string },
+    // No actions means start the workspace again
+    Changes?: { workspace_id: WorkspaceId, seq: number, actions: WorkspaceAction[] | null },
+}
+
+export type BrowserStatus = {
+    browser_id: number,
+    connected_workspaces: WorkspaceId[],
+}
+
+export type TabField = "url" | "incognito";
+
+export type TabFieldChange = {
+    tab: TabId,
+    field: TabField,
+    value: Tab[TabField],
 }
 
 export type Workspace = {
     id: WorkspaceId,
     name: string,
     tabs: Tab[],
+    // Tabs the daemon couldn't read and why
+    tab_errors?: Record<TabId, string>,
+    // The daemon dropped the tabs from memory, they're sent again when the workspace starts
+    evicted?: boolean,
+    // Only sent with LoadWorkspace
+    windows?: WindowLayout | null,
+}
+
+export type WindowGeometry = {
+    left: number,
+    top: number,
+    width: number,
+    height: number,
+    state?: string | null,
+}
+
+export type WindowLayout = {
+    version: number,
+    windows: Record<string, WindowGeometry>,
 }
 
 export type WorkspaceAction = {
     OpenTab?: TabId,
     CloseTab?: TabId,
+    CloseTabBecause?: [TabId, CloseReason],
     ChangeTabUrl?: [TabId, string]
     CreateTab?: Tab,
+    SetTabIncognito?: [TabId, boolean],
+    SetTabMeta?: [TabId, unknown],
+    SetTabNotes?: [TabId, string],
+    SetTabFavicon?: [TabId, string],
+    // Pixels from the top of the page
+    SetTabScroll?: [TabId, number],
+    ReorderTab?: [TabId, number],
+    // Old name, new name
+    RenameTab?: [TabId, TabId],
+    ActivateTab?: TabId,
 }
 
+export type CloseReason = "User" | "Expired" | "Archived";
+
 export type Tab = {
     name: string,
     is_open: boolean,
     url: string,
+    incognito: boolean,
+    meta?: unknown,
+    notes?: string,
+    favicon?: string,
+    scroll?: number,
+    // A new tab page that never had a real url
+    is_blank?: boolean,
 }
 
+// Optional, asked for from the popup. Restoring and tracking scroll positions needs a
+// script in the tab's page
+export const SCROLL_PERMISSIONS: chrome.permissions.Permissions = {
+    permissions: ["scripting"],
+    origins: ["<all_urls>"],
+};
+
 export class TabHolder {
     private idMap: Record<TabId, TabId> = {};
     private tabs: Record<TabId, Tab> = {};
@@ -58,17 +158,52 @@ export class TabHolder {
         return this.tabs[tabId];
     }
 
+    patchTab(change: TabFieldChange) {
+        console.log("Patching tab in tab holder", change);
+        const tab = this.tabs[change.tab];
+        (tab as Record<TabField, Tab[TabField]>)[change.field] = change.value;
+    }
+
     applyAction(action: WorkspaceAction) {
         console.log("Applying action to tab holder", action);
         if (action.OpenTab) {
             this.tabs[action.OpenTab].is_open = true;
         } else if (action.CloseTab) {
             this.tabs[action.CloseTab].is_open = false;
+        } else if (action.CloseTabBecause) {
+            this.tabs[action.CloseTabBecause[0]].is_open = false;
         } else if (action.CreateTab) {
             this.tabs[action.CreateTab.name] = action.CreateTab;
         } else if (action.ChangeTabUrl) {
             const [tabId, url] = action.ChangeTabUrl;
             this.tabs[tabId].url = url;
+            if (url) {
+                this.tabs[tabId].is_blank = false;
+            }
+        } else if (action.SetTabIncognito) {
+            const [tabId, incognito] = action.SetTabIncognito;
+            this.tabs[tabId].incognito = incognito;
+        } else if (action.SetTabMeta) {
+            const [tabId, meta] = action.SetTabMeta;
+            this.tabs[tabId].meta = meta;
+        } else if (action.SetTabNotes) {
+            const [tabId, notes] = action.SetTabNotes;
+            this.tabs[tabId].notes = notes;
+        } else if (action.SetTabFavicon) {
+            const [tabId, favicon] = action.SetTabFavicon;
+            this.tabs[tabId].favicon = favicon;
+        } else if (action.SetTabScroll) {
+            const [tabId, scroll] = action.SetTabScroll;
+            this.tabs[tabId].scroll = scroll;
+        } else if (action.RenameTab) {
+            const [from, to] = action.RenameTab;
+            const tab = this.tabs[from];
+            delete this.tabs[from];
+            this.tabs[to] = { ...tab, name: to };
+            if (from in this.idMap) {
+                this.idMap[to] = this.idMap[from];
+                delete this.idMap[from];
+            }
         }
         console.log("New tab holder", this);
     }
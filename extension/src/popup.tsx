@@ -1,12 +1,15 @@
 import React from "react";
 import { createRoot } from "react-dom/client";
-import { Workspace } from "./model";
+import { SCROLL_PERMISSIONS, Workspace } from "./model";
 
 
 const Popup = () => {
     const [data, setData] = React.useState<Workspace[]>();
+    const [canTrackScroll, setCanTrackScroll] = React.useState(true);
 
     React.useEffect(() => {
+        chrome.permissions.contains(SCROLL_PERMISSIONS).then(setCanTrackScroll);
+
         chrome.tabs.getCurrent((tab) => {
             console.log("Current Tab", tab);
         });
@@ -25,8 +28,16 @@ const Popup = () => {
         });
     }
 
+    // Has to come from a click, chrome only asks for permissions on a user gesture
+    const allowScrollTracking = async () => {
+        setCanTrackScroll(await chrome.permissions.request(SCROLL_PERMISSIONS));
+    }
+
     return (
         <div className="App">
+            {!canTrackScroll && (
+                <button onClick={allowScrollTracking}> Remember scroll positions </button>
+            )}
             <div>
                 {data && data.map((workspace) => {
                     return (